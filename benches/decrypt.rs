@@ -0,0 +1,49 @@
+//! Compares decrypting a large file read into memory with decrypting it through a memory map.
+//! Run with `cargo bench --bench decrypt`.
+
+use std::time::{Duration, Instant};
+
+use symmetric_key_exercise::CommonEncryptionOpts;
+
+const MESSAGE_LENGTH: usize = 256 * 1024 * 1024;
+const SAMPLES: usize = 9;
+
+fn median<F: FnMut()>(mut run: F) -> Duration {
+    let mut samples: Vec<Duration> = (0..SAMPLES)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .collect();
+    samples.sort();
+    samples[SAMPLES / 2]
+}
+
+fn main() {
+    let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+    let encrypted_file = tmpdir.path().join("large.dat");
+    let opts: CommonEncryptionOpts = clap::Parser::parse_from([
+        "bench",
+        "--key",
+        "bench key",
+        "--nonce",
+        "bench nonce",
+        "--encrypted-file",
+        encrypted_file.to_str().expect("tempdir is UTF-8"),
+    ]);
+    let _ = opts
+        .encrypt("x".repeat(MESSAGE_LENGTH))
+        .expect("Failed to encrypt data");
+
+    let read = median(|| {
+        opts.decrypt().expect("Failed to decrypt data");
+    });
+    let mapped = median(|| {
+        opts.decrypt_mapped()
+            .expect("Failed to decrypt mapped file");
+    });
+    println!("{} MiB", MESSAGE_LENGTH / (1024 * 1024));
+    println!("fs::read {read:?}");
+    println!("mmap     {mapped:?}");
+}
@@ -0,0 +1,78 @@
+//! Compares the memory allocated to encrypt a large message with and without `--prealloc`.
+//! Run with `cargo bench --bench prealloc`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use symmetric_key_exercise::CommonEncryptionOpts;
+
+const MESSAGE_LENGTH: usize = 64 * 1024 * 1024;
+
+// This counts every allocation and the bytes allocated, including growing reallocations.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        // SAFETY: the layout is passed through unchanged.
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: `ptr` was allocated by `System` with `layout`.
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(new_size.saturating_sub(layout.size()), Ordering::Relaxed);
+        // SAFETY: `ptr` was allocated by `System` with `layout`.
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn main() {
+    let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+    let encrypted_file = tmpdir.path().join("large.dat");
+    let message = "x".repeat(MESSAGE_LENGTH);
+    println!("{} MiB", MESSAGE_LENGTH / (1024 * 1024));
+    for prealloc in [false, true] {
+        let mut args = vec![
+            "bench",
+            "--key",
+            "bench key",
+            "--nonce",
+            "bench nonce",
+            "--encrypted-file",
+            encrypted_file.to_str().expect("tempdir is UTF-8"),
+        ];
+        if prealloc {
+            args.push("--prealloc");
+        }
+        let opts: CommonEncryptionOpts = clap::Parser::parse_from(args);
+        let message = message.clone();
+
+        let (allocations, allocated) = (
+            ALLOCATIONS.load(Ordering::Relaxed),
+            ALLOCATED.load(Ordering::Relaxed),
+        );
+        let start = Instant::now();
+        let _ = opts.encrypt(message).expect("Failed to encrypt data");
+        let elapsed = start.elapsed();
+        println!(
+            "prealloc {prealloc:<5} {:>4} allocations {:>4} MiB allocated {elapsed:?}",
+            ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+            (ALLOCATED.load(Ordering::Relaxed) - allocated) / (1024 * 1024),
+        );
+    }
+}
@@ -0,0 +1,65 @@
+//! Measures the throughput of `encrypt_bytes` and `decrypt_bytes` for every algorithm across
+//! message sizes, in memory so the disk adds no noise. Run with `cargo bench --bench throughput`;
+//! criterion compares each run with the one before it.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use symmetric_key_exercise::{CommonEncryptionOpts, SimpleCipherAlgorithm};
+
+const MESSAGE_LENGTHS: [(usize, &str); 3] = [
+    (1024, "1 KiB"),
+    (1024 * 1024, "1 MiB"),
+    (16 * 1024 * 1024, "16 MiB"),
+];
+
+fn opts(algorithm: SimpleCipherAlgorithm) -> CommonEncryptionOpts {
+    clap::Parser::parse_from([
+        "bench",
+        "--key",
+        "bench key",
+        "--nonce",
+        "bench nonce",
+        "--algorithm",
+        algorithm.name(),
+    ])
+}
+
+fn throughput(c: &mut Criterion) {
+    for (length, size) in MESSAGE_LENGTHS {
+        let message = vec![0x5a_u8; length];
+        let mut encrypt = c.benchmark_group(format!("encrypt_bytes {size}"));
+        encrypt.throughput(Throughput::Bytes(length as u64));
+        // A handful of samples is plenty to see a regression in the larger messages.
+        encrypt.sample_size(10);
+        for algorithm in SimpleCipherAlgorithm::ALL {
+            let opts = opts(algorithm);
+            encrypt.bench_function(BenchmarkId::from_parameter(algorithm), |b| {
+                b.iter(|| {
+                    opts.encrypt_bytes(&message)
+                        .expect("Failed to encrypt data")
+                })
+            });
+        }
+        encrypt.finish();
+
+        let mut decrypt = c.benchmark_group(format!("decrypt_bytes {size}"));
+        decrypt.throughput(Throughput::Bytes(length as u64));
+        decrypt.sample_size(10);
+        for algorithm in SimpleCipherAlgorithm::ALL {
+            let opts = opts(algorithm);
+            let (contents, nonce) = opts
+                .encrypt_bytes(&message)
+                .expect("Failed to encrypt data");
+            decrypt.bench_function(BenchmarkId::from_parameter(algorithm), |b| {
+                b.iter(|| {
+                    opts.decrypt_bytes(&contents, &nonce)
+                        .expect("Failed to decrypt data")
+                })
+            });
+        }
+        decrypt.finish();
+    }
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
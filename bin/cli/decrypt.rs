@@ -0,0 +1,145 @@
+//! The `decrypt` command, run by the `decrypt` binary and by `ske decrypt`.
+
+use clap::{Command, Parser};
+use std::{
+    fs,
+    io::{self, Write},
+    ops::ControlFlow,
+    path::PathBuf,
+    time::Duration,
+};
+
+use symmetric_key_exercise::{
+    header_json, self_check_timing, write_plaintext, CiphertextFormat, CommonEncryptionOpts,
+    ErrorStyle,
+};
+
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Parser, Debug)]
+pub struct DecryptOpt {
+    #[arg(long, exclusive = true)]
+    /// List the supported encrypted file formats and exit.
+    list_formats: bool,
+
+    #[arg(long, exclusive = true, hide = true)]
+    /// Development check that rejecting a wrong key and a corrupted tag take the same time.
+    self_check_timing: bool,
+
+    // Conflicting with the key options lets them be left out, as clap does not require an
+    // argument which conflicts with one given.
+    #[arg(
+        long,
+        conflicts_with_all = ["follow", "mmap", "output", "tee_plaintext", "key-source"]
+    )]
+    /// Print the headers of the encrypted file as JSON and exit. Nothing is decrypted so neither
+    /// the key nor the nonce is needed.
+    header_only: bool,
+
+    #[arg(long, conflicts_with = "tee_plaintext")]
+    /// Keep decrypting segments as they are appended to an encrypted log, like `tail -f`. A
+    /// partly written segment is printed once it is complete.
+    follow: bool,
+
+    #[arg(long, conflicts_with = "follow")]
+    /// Read the encrypted file through a memory map rather than copying it into memory, which is
+    /// quicker for large files. The file must not be truncated by other programs meanwhile.
+    mmap: bool,
+
+    #[arg(short, long, conflicts_with = "follow")]
+    /// Write the decrypted message to this file rather than standard output. Either way it
+    /// receives exactly the decrypted bytes with no trailing newline.
+    output: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Also write the decrypted message to this file, exactly as the decrypted bytes.
+    tee_plaintext: Option<PathBuf>,
+
+    // clap enforces the required shared arguments unless an exclusive flag such as
+    // `--list-formats` is given, which leaves them at their defaults.
+    #[command(flatten)]
+    shared: CommonEncryptionOpts,
+}
+
+impl DecryptOpt {
+    // A nonce embedded in the encrypted file is read from there, so no nonce option is needed.
+    pub fn relax_nonce(command: Command) -> Command {
+        ["no_nonce", "generate_nonce", "nonce"]
+            .into_iter()
+            .fold(command, |command, id| {
+                command.mut_arg(id, |arg| arg.required(false))
+            })
+    }
+
+    pub fn error_style(&self) -> ErrorStyle {
+        self.shared.error_style()
+    }
+}
+
+// This is what --header-only prints for the encrypted file.
+pub fn describe_header(opt: &DecryptOpt) -> anyhow::Result<serde_json::Value> {
+    Ok(header_json(&fs::read(opt.shared.encrypted_file())?)?)
+}
+
+pub fn run(opt: DecryptOpt) -> anyhow::Result<()> {
+    if opt.list_formats {
+        for format in CiphertextFormat::ALL {
+            println!("{:<12}{}", format.name(), format.description());
+        }
+        return Ok(());
+    }
+    if opt.header_only {
+        println!("{}", serde_json::to_string_pretty(&describe_header(&opt)?)?);
+        return Ok(());
+    }
+    if opt.self_check_timing {
+        let report = self_check_timing(1001)?;
+        println!("{report:?} ratio {:.3}", report.ratio());
+        if !report.within(1.5) {
+            anyhow::bail!("Decryption failures are distinguishable by timing");
+        }
+        return Ok(());
+    }
+    let mut shared = opt.shared;
+    shared.prompt_for_key(false)?;
+    if opt.follow {
+        let mut stdout = io::stdout().lock();
+        let mut written = Ok(());
+        shared.follow(FOLLOW_POLL_INTERVAL, |segment| {
+            written = stdout.write_all(&segment).and_then(|()| stdout.flush());
+            match written {
+                Ok(()) => ControlFlow::Continue(()),
+                Err(_) => ControlFlow::Break(()),
+            }
+        })?;
+        written?;
+        return Ok(());
+    }
+    // The output is only replaced once the message has decrypted, so a wrong key leaves it as
+    // it was.
+    let mut output = opt
+        .output
+        .as_deref()
+        .map(|path| shared.create_output(path))
+        .transpose()?;
+    let mut writer: Box<dyn Write> = match &mut output {
+        Some(file) => Box::new(file),
+        None => Box::new(io::stdout().lock()),
+    };
+    // A streamed file is decrypted straight to the output rather than read into memory whole.
+    if !opt.mmap && opt.tee_plaintext.is_none() && shared.is_streamed()? {
+        shared.decrypt_file_to(&mut writer)?;
+    } else {
+        let plaintext = if opt.mmap {
+            shared.decrypt_mapped()?
+        } else {
+            shared.decrypt()?
+        };
+        write_plaintext(&plaintext, &mut writer, opt.tee_plaintext.as_deref())?;
+    }
+    drop(writer);
+    if let Some(output) = output {
+        output.commit()?;
+    }
+    Ok(())
+}
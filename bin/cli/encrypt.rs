@@ -0,0 +1,165 @@
+//! The `encrypt` command, run by the `encrypt` binary and by `ske encrypt`.
+
+use clap::Parser;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use symmetric_key_exercise::{
+    batch::{batch_inputs, Batch, BatchOutcome},
+    read_input, redacted, write_qr, CommonEncryptionOpts, ErrorStyle,
+};
+
+#[derive(Parser, Debug)]
+pub struct EncryptOpt {
+    #[arg(short, long, group = "message-source", required = true)]
+    /// The message to be encrypted.
+    message: Option<String>,
+
+    #[arg(
+        long,
+        visible_alias = "input-file",
+        group = "message-source",
+        required = true
+    )]
+    /// A file, FIFO or device to read the message to be encrypted from, as raw bytes.
+    message_file: Option<PathBuf>,
+
+    #[arg(long, group = "message-source", required = true, requires = "stream")]
+    /// Stream standard input to the encrypted file as it arrives, for input of unknown length
+    /// such as `tar -c dir | encrypt --stream --stdin-chunked`.
+    stdin_chunked: bool,
+
+    #[arg(
+        long,
+        group = "message-source",
+        required = true,
+        conflicts_with_all = [
+            "no_nonce",
+            "nonce",
+            "stream",
+            "detached_nonce",
+            "nonce_file",
+            "append"
+        ]
+    )]
+    /// Encrypt every file in this directory, or matching this glob pattern, to a file of the
+    /// same name with `.dat` added beside it, each with its own nonce embedded. The encrypted
+    /// file option is unused, files already ending in `.dat` are skipped, and a file which fails
+    /// does not stop the others.
+    batch: Option<String>,
+
+    #[arg(long, requires = "message_file")]
+    /// Give up with an error if the message file has not been read after this many seconds.
+    read_timeout: Option<u64>,
+
+    #[arg(long, conflicts_with_all = ["stream", "format", "armor"])]
+    /// Append the message to the encrypted file as a new segment of an encrypted log rather than
+    /// replacing it. Every segment needs the same key and nonce.
+    append: bool,
+
+    #[arg(long, requires = "generate_nonce")]
+    /// Print the approximate size of the nonce space covered by the nonce generator.
+    print_nonce_space_estimate: bool,
+
+    #[arg(long, requires = "detached_nonce")]
+    /// Also write the generated nonce, exactly as printed, as a QR code PNG to this file.
+    qr: Option<PathBuf>,
+
+    #[command(flatten)]
+    shared: CommonEncryptionOpts,
+}
+
+impl EncryptOpt {
+    pub fn error_style(&self) -> ErrorStyle {
+        self.shared.error_style()
+    }
+}
+
+pub fn run(mut opt: EncryptOpt) -> anyhow::Result<()> {
+    opt.shared.prompt_for_key(true)?;
+    if let Some(report) = opt.shared.entropy_report()? {
+        eprintln!("{report}");
+    }
+    if opt.print_nonce_space_estimate {
+        eprintln!(
+            "The nonce generator covers approximately 2^{:.1} nonces out of 2^192",
+            opt.shared.nonce_space_log2()
+        );
+    }
+    if let Some(pattern) = &opt.batch {
+        return encrypt_batch(&opt.shared, pattern);
+    }
+    if opt.stdin_chunked {
+        let nonce = opt.shared.encrypt_reader(&mut io::stdin().lock())?;
+        return print_nonce(nonce, opt.qr.as_deref());
+    }
+    if let (Some(path), None, false) = (&opt.message_file, opt.read_timeout, opt.append) {
+        let nonce = opt.shared.encrypt_file(path)?;
+        return print_nonce(nonce, opt.qr.as_deref());
+    }
+    let message = match (opt.message, opt.message_file) {
+        (Some(message), _) => message.into_bytes(),
+        (None, Some(path)) => read_input(&path, opt.read_timeout.map(Duration::from_secs))?,
+        (None, None) => unreachable!("clap requires a message source"),
+    };
+    let nonce = if opt.append {
+        opt.shared.append_bytes(&message)?
+    } else {
+        opt.shared.write_encrypted(&message)?
+    };
+    print_nonce(nonce, opt.qr.as_deref())
+}
+
+fn encrypt_batch(shared: &CommonEncryptionOpts, pattern: &str) -> anyhow::Result<()> {
+    let batch = Batch {
+        extension: "dat".to_string(),
+        embed_nonces: true,
+        ..Batch::default()
+    };
+    let results = shared.encrypt_batch(&batch_inputs(pattern)?, &batch)?;
+    let redact = shared.error_style().redact;
+    let (mut encrypted, mut skipped, mut failed) = (0, 0, 0);
+    for result in &results {
+        match &result.outcome {
+            BatchOutcome::Encrypted(entry) => {
+                encrypted += 1;
+                println!(
+                    "OK {} -> {}",
+                    result.input.display(),
+                    entry.output.display()
+                );
+            }
+            BatchOutcome::Skipped => {
+                skipped += 1;
+                println!("SKIP {}: already ends in .dat", result.input.display());
+            }
+            BatchOutcome::Failed(e) => {
+                failed += 1;
+                let reason = if redact {
+                    redacted(e).to_string()
+                } else {
+                    e.to_string()
+                };
+                println!("FAIL {}: {reason}", result.input.display());
+            }
+        }
+    }
+    println!("{encrypted} encrypted, {skipped} skipped, {failed} failed");
+    if failed > 0 {
+        anyhow::bail!("{failed} files failed to encrypt");
+    }
+    Ok(())
+}
+
+fn print_nonce(nonce: Option<String>, qr: Option<&Path>) -> anyhow::Result<()> {
+    if let Some(nonce) = nonce {
+        println!("The nonce for this message was generated and it is: {nonce}");
+        if let Some(qr) = qr {
+            write_qr(qr, &nonce)?;
+        }
+    }
+    Ok(())
+}
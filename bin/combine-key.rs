@@ -0,0 +1,17 @@
+use clap::Parser;
+
+use symmetric_key_exercise::combine_key;
+
+#[derive(Parser, Debug)]
+struct CombineKeyOpt {
+    #[arg(required = true)]
+    /// The key shares printed by `split-key`, at least as many as the threshold.
+    shares: Vec<String>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let opt = CombineKeyOpt::parse();
+    let key = combine_key(&opt.shares)?;
+    println!("{}", hex::encode(key));
+    Ok(())
+}
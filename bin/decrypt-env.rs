@@ -0,0 +1,25 @@
+use clap::{CommandFactory, Parser};
+use std::process::ExitCode;
+
+use symmetric_key_exercise::{env::export_lines, run_main, CommonEncryptionOpts};
+
+#[derive(Parser, Debug)]
+struct DecryptEnvOpt {
+    #[command(flatten)]
+    shared: CommonEncryptionOpts,
+}
+
+fn main() -> ExitCode {
+    run_main(
+        |defaults| defaults.apply_to_decrypt(DecryptEnvOpt::command()),
+        |opt: &DecryptEnvOpt| opt.shared.error_style(),
+        run,
+    )
+}
+
+fn run(mut opt: DecryptEnvOpt) -> anyhow::Result<()> {
+    opt.shared.prompt_for_key(false)?;
+    let vars = opt.shared.decrypt_env()?;
+    print!("{}", export_lines(&vars));
+    Ok(())
+}
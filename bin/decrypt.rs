@@ -1,16 +1,17 @@
-use clap::Parser;
+use clap::CommandFactory;
+use std::process::ExitCode;
 
-use symmetric_key_exercise::CommonEncryptionOpts;
+use symmetric_key_exercise::run_main;
 
-#[derive(Parser, Debug)]
-struct DecryptOpt {
-    #[command(flatten)]
-    shared: CommonEncryptionOpts,
-}
+#[path = "cli/decrypt.rs"]
+mod decrypt;
+
+use decrypt::DecryptOpt;
 
-fn main() -> anyhow::Result<()> {
-    let opt = DecryptOpt::parse();
-    let plaintext = opt.shared.decrypt()?;
-    println!("{plaintext}");
-    Ok(())
+fn main() -> ExitCode {
+    run_main(
+        |defaults| defaults.apply_to_decrypt(DecryptOpt::relax_nonce(DecryptOpt::command())),
+        DecryptOpt::error_style,
+        decrypt::run,
+    )
 }
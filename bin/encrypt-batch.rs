@@ -0,0 +1,89 @@
+use clap::{CommandFactory, Parser};
+use std::{path::PathBuf, process::ExitCode};
+
+use symmetric_key_exercise::{
+    batch::{write_manifest, Batch, BatchOutcome},
+    redacted, run_main, CommonEncryptionOpts,
+};
+
+#[derive(Parser, Debug)]
+struct EncryptBatchOpt {
+    #[arg(required = true)]
+    /// The files to encrypt. Each is encrypted to a file of the same name with `.ske` added.
+    files: Vec<PathBuf>,
+
+    #[arg(long, default_value = ".")]
+    /// The directory the encrypted files are written to. The encrypted file option is unused.
+    output_dir: PathBuf,
+
+    #[arg(long)]
+    /// Embed each file's nonce in its header, so every file decrypts with the key alone.
+    embed_nonce: bool,
+
+    #[arg(long)]
+    /// Write a JSON manifest of every file encrypted, with its output, key id, nonce and
+    /// plaintext hash. The key itself is never written.
+    write_manifest: Option<PathBuf>,
+
+    #[command(flatten)]
+    shared: CommonEncryptionOpts,
+}
+
+fn main() -> ExitCode {
+    run_main(
+        |defaults| defaults.apply(EncryptBatchOpt::command()),
+        |opt: &EncryptBatchOpt| opt.shared.error_style(),
+        run,
+    )
+}
+
+fn run(mut opt: EncryptBatchOpt) -> anyhow::Result<()> {
+    opt.shared.prompt_for_key(true)?;
+    if let Some(report) = opt.shared.entropy_report()? {
+        eprintln!("{report}");
+    }
+    let batch = Batch {
+        output_dir: Some(opt.output_dir),
+        embed_nonces: opt.embed_nonce,
+        ..Batch::default()
+    };
+    let results = opt.shared.encrypt_batch(&opt.files, &batch)?;
+    let redact = opt.shared.error_style().redact;
+    let mut manifest = Vec::with_capacity(results.len());
+    let mut failed = 0;
+    for result in results {
+        match result.outcome {
+            BatchOutcome::Encrypted(entry) => {
+                println!(
+                    "{} -> {} nonce {}",
+                    entry.input.display(),
+                    entry.output.display(),
+                    entry.nonce
+                );
+                manifest.push(entry);
+            }
+            BatchOutcome::Skipped => {
+                println!(
+                    "{} skipped, it already ends in .ske",
+                    result.input.display()
+                )
+            }
+            BatchOutcome::Failed(e) => {
+                failed += 1;
+                let reason = if redact {
+                    redacted(&e).to_string()
+                } else {
+                    e.to_string()
+                };
+                println!("{} failed: {reason}", result.input.display());
+            }
+        }
+    }
+    if let Some(path) = &opt.write_manifest {
+        write_manifest(path, &manifest)?;
+    }
+    if failed > 0 {
+        anyhow::bail!("{failed} files failed to encrypt");
+    }
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use clap::{CommandFactory, Parser};
+use std::{fs, path::PathBuf, process::ExitCode};
+
+use symmetric_key_exercise::{
+    env::{parse_assignment, parse_dotenv},
+    run_main, CommonEncryptionOpts,
+};
+
+#[derive(Parser, Debug)]
+struct EncryptEnvOpt {
+    /// Environment variables to encrypt as NAME=VALUE.
+    vars: Vec<String>,
+
+    #[arg(long)]
+    /// A dotenv file of NAME=VALUE lines to encrypt. Variables given as arguments follow it.
+    env_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    shared: CommonEncryptionOpts,
+}
+
+fn main() -> ExitCode {
+    run_main(
+        |defaults| defaults.apply(EncryptEnvOpt::command()),
+        |opt: &EncryptEnvOpt| opt.shared.error_style(),
+        run,
+    )
+}
+
+fn run(mut opt: EncryptEnvOpt) -> anyhow::Result<()> {
+    opt.shared.prompt_for_key(true)?;
+    if let Some(report) = opt.shared.entropy_report()? {
+        eprintln!("{report}");
+    }
+    let mut vars = match &opt.env_file {
+        Some(path) => parse_dotenv(&fs::read_to_string(path)?)?,
+        None => Vec::new(),
+    };
+    for assignment in &opt.vars {
+        vars.push(parse_assignment(assignment)?);
+    }
+    if vars.is_empty() {
+        anyhow::bail!("No environment variables were given");
+    }
+    let nonce = opt.shared.encrypt_env(&vars)?;
+    if let Some(nonce) = nonce {
+        println!("The nonce for this message was generated and it is: {nonce}");
+    }
+    Ok(())
+}
@@ -1,22 +1,17 @@
-use clap::Parser;
+use clap::CommandFactory;
+use std::process::ExitCode;
 
-use symmetric_key_exercise::CommonEncryptionOpts;
+use symmetric_key_exercise::run_main;
 
-#[derive(Parser, Debug)]
-struct EncryptOpt {
-    #[arg(short, long)]
-    /// The message to be encrypted.
-    message: String,
+#[path = "cli/encrypt.rs"]
+mod encrypt;
 
-    #[command(flatten)]
-    shared: CommonEncryptionOpts,
-}
+use encrypt::EncryptOpt;
 
-fn main() -> anyhow::Result<()> {
-    let opt = EncryptOpt::parse();
-    let nonce = opt.shared.encrypt(opt.message)?;
-    if let Some(nonce) = nonce {
-        println!("The nonce for this message was generated and it is: {nonce}");
-    }
-    Ok(())
+fn main() -> ExitCode {
+    run_main(
+        |defaults| defaults.apply(EncryptOpt::command()),
+        EncryptOpt::error_style,
+        encrypt::run,
+    )
 }
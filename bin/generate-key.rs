@@ -0,0 +1,30 @@
+use clap::Parser;
+use std::path::PathBuf;
+
+use symmetric_key_exercise::{generate_key, key_encodings, key_qr_text, write_qr};
+
+#[derive(Parser, Debug)]
+struct GenerateKeyOpt {
+    #[arg(short, long, default_value_t = 32, value_parser = clap::value_parser!(u8).range(1..=32))]
+    /// The number of random bytes in the key. Keys shorter than 32 bytes are padded with zero
+    /// bytes, so only use a shorter key where it has to be typed.
+    length: u8,
+
+    #[arg(long)]
+    /// A PNG file to also write the key to, as a QR code of its base64, so it can be scanned into
+    /// a phone instead of typed. The file holds the key, so a new one is only readable by its
+    /// owner.
+    qr: Option<PathBuf>,
+}
+
+// Nothing is written to disk unless --qr is given: the key is only printed, as hex and as both
+// variants of base64.
+fn main() -> anyhow::Result<()> {
+    let opt = GenerateKeyOpt::parse();
+    let key = generate_key(opt.length.into())?;
+    println!("{}", key_encodings(&key));
+    if let Some(qr) = &opt.qr {
+        write_qr(qr, &key_qr_text(&key))?;
+    }
+    Ok(())
+}
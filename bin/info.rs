@@ -0,0 +1,26 @@
+use clap::Parser;
+use std::{fs, path::PathBuf};
+
+use symmetric_key_exercise::Header;
+
+#[derive(Parser, Debug)]
+struct InfoOpt {
+    /// The encrypted file to describe. Nothing is decrypted so no key is needed.
+    encrypted_file: PathBuf,
+}
+
+fn main() -> anyhow::Result<()> {
+    let opt = InfoOpt::parse();
+    let (format, segments) = Header::parse_all(&fs::read(&opt.encrypted_file)?)?;
+    println!("format: {}", format.name());
+    if segments.is_empty() {
+        println!("no header");
+    }
+    for (index, (header, _)) in segments.iter().enumerate() {
+        println!("segment {index}:");
+        for (name, value) in header.fields() {
+            println!("    {name}: {value}");
+        }
+    }
+    Ok(())
+}
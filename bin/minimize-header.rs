@@ -0,0 +1,25 @@
+use clap::Parser;
+use std::{fs, path::PathBuf};
+
+use symmetric_key_exercise::minimize_headers;
+
+#[derive(Parser, Debug)]
+struct MinimizeHeaderOpt {
+    /// The encrypted file to remove the header metadata, such as the key id, from. It still
+    /// decrypts with the same key and nonce afterwards.
+    encrypted_file: PathBuf,
+
+    #[arg(short, long)]
+    /// Write the minimized file here instead of replacing the encrypted file.
+    output: Option<PathBuf>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let opt = MinimizeHeaderOpt::parse();
+    let minimized = minimize_headers(&fs::read(&opt.encrypted_file)?)?;
+    fs::write(
+        opt.output.as_ref().unwrap_or(&opt.encrypted_file),
+        minimized,
+    )?;
+    Ok(())
+}
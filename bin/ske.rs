@@ -0,0 +1,176 @@
+use clap::{Command, CommandFactory, Parser};
+use std::process::ExitCode;
+
+use symmetric_key_exercise::{run_main, ConfigDefaults};
+
+#[path = "cli/decrypt.rs"]
+mod decrypt;
+#[path = "cli/encrypt.rs"]
+mod encrypt;
+
+use decrypt::DecryptOpt;
+use encrypt::EncryptOpt;
+
+/// Encrypts and decrypts files with a symmetric key, taking the options of the `encrypt` and
+/// `decrypt` binaries.
+#[derive(Parser, Debug)]
+#[command(name = "ske")]
+enum Ske {
+    /// Encrypt a message to the encrypted file.
+    Encrypt(EncryptOpt),
+    /// Decrypt the encrypted file.
+    Decrypt(DecryptOpt),
+}
+
+// This is the command line of `ske` with the defaults file applied to both subcommands.
+fn command(defaults: &ConfigDefaults) -> Command {
+    Ske::command()
+        .mut_subcommand("encrypt", |command| defaults.apply(command))
+        .mut_subcommand("decrypt", |command| {
+            defaults.apply_to_decrypt(DecryptOpt::relax_nonce(command))
+        })
+}
+
+fn main() -> ExitCode {
+    run_main(
+        command,
+        |opt: &Ske| match opt {
+            Ske::Encrypt(opt) => opt.error_style(),
+            Ske::Decrypt(opt) => opt.error_style(),
+        },
+        |opt| match opt {
+            Ske::Encrypt(opt) => encrypt::run(opt),
+            Ske::Decrypt(opt) => decrypt::run(opt),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::FromArgMatches;
+    use std::path::PathBuf;
+
+    fn parse(args: &[&str]) -> clap::ArgMatches {
+        command(&ConfigDefaults::default())
+            .try_get_matches_from(args)
+            .expect("Failed to parse the command line")
+    }
+
+    #[test]
+    fn parses_both_subcommands() {
+        command(&ConfigDefaults::default()).debug_assert();
+
+        let matches = parse(&[
+            "ske", "encrypt", "-k", "baz", "-m", "foobar", "-g", "-e", "out.dat",
+        ]);
+        let ("encrypt", encrypt) = matches.subcommand().unwrap() else {
+            panic!("Not the encrypt subcommand");
+        };
+        assert_eq!(encrypt.get_one::<String>("message").unwrap(), "foobar");
+        assert!(encrypt.get_flag("generate_nonce"));
+        assert_eq!(
+            encrypt.get_one::<PathBuf>("encrypted_file").unwrap(),
+            &PathBuf::from("out.dat")
+        );
+        assert!(matches!(
+            Ske::from_arg_matches(&matches),
+            Ok(Ske::Encrypt(_))
+        ));
+
+        // Decryption needs no nonce option, as the nonce may be embedded.
+        let matches = parse(&["ske", "decrypt", "-k", "baz", "-o", "plain.txt"]);
+        let ("decrypt", decrypt) = matches.subcommand().unwrap() else {
+            panic!("Not the decrypt subcommand");
+        };
+        assert_eq!(
+            decrypt.get_one::<PathBuf>("output").unwrap(),
+            &PathBuf::from("plain.txt")
+        );
+        assert_eq!(
+            decrypt.get_one::<PathBuf>("encrypted_file").unwrap(),
+            &PathBuf::from("data.dat")
+        );
+        assert!(matches!(
+            Ske::from_arg_matches(&matches),
+            Ok(Ske::Decrypt(_))
+        ));
+        let matches = parse(&["ske", "decrypt", "-k", "baz", "-n", "given"]);
+        let (_, decrypt) = matches.subcommand().unwrap();
+        assert_eq!(decrypt.get_one::<String>("nonce").unwrap(), "given");
+
+        assert!(command(&ConfigDefaults::default())
+            .try_get_matches_from(["ske", "encrypt", "-k", "baz", "-g"])
+            .is_err());
+
+        // --batch takes the place of the message and needs a generated nonce to embed.
+        let matches = parse(&["ske", "encrypt", "-k", "baz", "-g", "--batch", "logs/*.log"]);
+        let (_, encrypt) = matches.subcommand().unwrap();
+        assert_eq!(encrypt.get_one::<String>("batch").unwrap(), "logs/*.log");
+        for args in [
+            &[
+                "ske", "encrypt", "-k", "baz", "-n", "given", "--batch", "logs",
+            ][..],
+            &[
+                "ske", "encrypt", "-k", "baz", "-g", "--batch", "logs", "-m", "foobar",
+            ],
+            &[
+                "ske",
+                "encrypt",
+                "-k",
+                "baz",
+                "-g",
+                "--batch",
+                "logs",
+                "--detached-nonce",
+            ],
+        ] {
+            assert!(command(&ConfigDefaults::default())
+                .try_get_matches_from(args)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn header_only_needs_no_key() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("other.dat");
+        let encrypted_file = encrypted_file.to_str().unwrap();
+        let matches = parse(&[
+            "ske",
+            "encrypt",
+            "-k",
+            "baz",
+            "-m",
+            "foobar",
+            "-g",
+            "--algorithm",
+            "aes256gcm",
+            "-e",
+            encrypted_file,
+        ]);
+        let Ok(Ske::Encrypt(opt)) = Ske::from_arg_matches(&matches) else {
+            panic!("Not the encrypt subcommand");
+        };
+        encrypt::run(opt).expect("Failed to encrypt");
+
+        let matches = parse(&["ske", "decrypt", "--header-only", "-e", encrypted_file]);
+        let Ok(Ske::Decrypt(opt)) = Ske::from_arg_matches(&matches) else {
+            panic!("Not the decrypt subcommand");
+        };
+        let described = decrypt::describe_header(&opt).expect("Failed to describe header");
+        assert_eq!(described["algorithm"], "aes256gcm");
+        decrypt::run(opt).expect("Failed to print header");
+
+        // Only the options which decrypt are refused alongside it.
+        for args in [
+            &["ske", "decrypt", "--header-only", "-k", "baz"][..],
+            &["ske", "decrypt", "--header-only", "-o", "plain.txt"],
+            &["ske", "decrypt", "--header-only", "--follow"],
+        ] {
+            assert!(command(&ConfigDefaults::default())
+                .try_get_matches_from(args)
+                .is_err());
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use clap::Parser;
+
+use symmetric_key_exercise::{key_from_string, split_key};
+
+#[derive(Parser, Debug)]
+struct SplitKeyOpt {
+    #[arg(short, long)]
+    /// The key to split. It must be less than 32 characters long.
+    key: String,
+
+    #[arg(short, long)]
+    /// The number of shares to split the key into.
+    shares: u8,
+
+    #[arg(short, long)]
+    /// The number of shares needed to reconstruct the key.
+    threshold: u8,
+}
+
+fn main() -> anyhow::Result<()> {
+    let opt = SplitKeyOpt::parse();
+    let key = key_from_string(&opt.key)?;
+    for share in split_key(&key, opt.threshold, opt.shares)? {
+        println!("{share}");
+    }
+    Ok(())
+}
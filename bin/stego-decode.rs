@@ -0,0 +1,25 @@
+use clap::{CommandFactory, Parser};
+use std::{io, process::ExitCode};
+
+use symmetric_key_exercise::{run_main, write_plaintext, CommonEncryptionOpts};
+
+#[derive(Parser, Debug)]
+struct StegoDecodeOpt {
+    #[command(flatten)]
+    shared: CommonEncryptionOpts,
+}
+
+fn main() -> ExitCode {
+    run_main(
+        |defaults| defaults.apply_to_decrypt(StegoDecodeOpt::command()),
+        |opt: &StegoDecodeOpt| opt.shared.error_style(),
+        run,
+    )
+}
+
+fn run(mut opt: StegoDecodeOpt) -> anyhow::Result<()> {
+    opt.shared.prompt_for_key(false)?;
+    let plaintext = opt.shared.stego_decode()?;
+    write_plaintext(&plaintext, &mut io::stdout().lock(), None)?;
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use clap::{CommandFactory, Parser};
+use std::{path::PathBuf, process::ExitCode};
+
+use symmetric_key_exercise::{run_main, CommonEncryptionOpts};
+
+#[derive(Parser, Debug)]
+struct StegoEncodeOpt {
+    #[arg(short, long)]
+    /// The message to be encrypted.
+    message: String,
+
+    #[arg(short, long)]
+    /// The PNG image the ciphertext is hidden in. The result is written to the encrypted file.
+    cover: PathBuf,
+
+    #[command(flatten)]
+    shared: CommonEncryptionOpts,
+}
+
+fn main() -> ExitCode {
+    run_main(
+        |defaults| defaults.apply(StegoEncodeOpt::command()),
+        |opt: &StegoEncodeOpt| opt.shared.error_style(),
+        run,
+    )
+}
+
+fn run(mut opt: StegoEncodeOpt) -> anyhow::Result<()> {
+    opt.shared.prompt_for_key(true)?;
+    if let Some(report) = opt.shared.entropy_report()? {
+        eprintln!("{report}");
+    }
+    let nonce = opt.shared.stego_encode(opt.message, &opt.cover)?;
+    if let Some(nonce) = nonce {
+        println!("The nonce for this message was generated and it is: {nonce}");
+    }
+    Ok(())
+}
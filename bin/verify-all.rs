@@ -0,0 +1,60 @@
+use clap::{CommandFactory, Parser};
+use std::{num::NonZeroUsize, path::PathBuf, process::ExitCode, thread};
+
+use symmetric_key_exercise::{redacted, run_main, CommonEncryptionOpts};
+
+#[derive(Parser, Debug)]
+struct VerifyAllOpt {
+    /// The directory whose files, including those in subdirectories, are authenticated.
+    dir: PathBuf,
+
+    #[arg(long)]
+    /// The number of files to verify at once. This defaults to the number of CPUs.
+    jobs: Option<NonZeroUsize>,
+
+    #[arg(long, default_value_t = 256 * 1024 * 1024)]
+    /// The largest file, in bytes, that is read whole to be verified. Streamed files are checked
+    /// a chunk at a time and may be any size.
+    max_file_size: u64,
+
+    #[command(flatten)]
+    shared: CommonEncryptionOpts,
+}
+
+fn main() -> ExitCode {
+    run_main(
+        |defaults| defaults.apply_to_decrypt(VerifyAllOpt::command()),
+        |opt: &VerifyAllOpt| opt.shared.error_style(),
+        run,
+    )
+}
+
+fn run(mut opt: VerifyAllOpt) -> anyhow::Result<()> {
+    opt.shared.prompt_for_key(false)?;
+    let jobs = opt
+        .jobs
+        .or_else(|| thread::available_parallelism().ok())
+        .unwrap_or(NonZeroUsize::MIN);
+    let verifications = opt.shared.verify_all(&opt.dir, jobs, opt.max_file_size)?;
+    let redact = opt.shared.error_style().redact;
+    let mut failed = 0;
+    for verification in &verifications {
+        match &verification.result {
+            Ok(()) => println!("PASS {}", verification.path.display()),
+            Err(e) => {
+                failed += 1;
+                let reason = if redact {
+                    redacted(e).to_string()
+                } else {
+                    e.to_string()
+                };
+                println!("FAIL {}: {reason}", verification.path.display());
+            }
+        }
+    }
+    println!("{} passed, {failed} failed", verifications.len() - failed);
+    if failed > 0 {
+        anyhow::bail!("{failed} files failed verification");
+    }
+    Ok(())
+}
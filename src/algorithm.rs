@@ -0,0 +1,332 @@
+use aes_gcm::Aes256Gcm;
+use aes_gcm_siv::Aes256GcmSiv;
+use chacha20poly1305::{
+    aead::{
+        consts::{U16, U32},
+        generic_array::typenum::Unsigned,
+        Aead, AeadInPlace, KeyInit, Nonce, Payload,
+    },
+    ChaCha20Poly1305, Error as ChachaError, Key, Tag, XChaCha20Poly1305, XNonce,
+};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::{fmt, str::FromStr};
+
+use crate::SimpleCipherError;
+
+/// The AEAD a message is encrypted with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SimpleCipherAlgorithm {
+    /// XChaCha20Poly1305 with a 24 byte nonce.
+    #[default]
+    #[value(name = "xchacha20poly1305")]
+    XChaCha20Poly1305,
+    /// The IETF ChaCha20Poly1305 of RFC 8439 with a 12 byte nonce, for interoperating with
+    /// implementations which lack XChaCha20.
+    #[value(name = "chacha20poly1305")]
+    ChaCha20Poly1305,
+    /// AES-256-GCM with a 12 byte nonce, for environments which mandate AES.
+    #[value(name = "aes256gcm")]
+    Aes256Gcm,
+    /// AES-256-GCM-SIV with a 12 byte nonce, which only reveals whether two messages encrypted
+    /// under the same nonce were equal rather than the key stream.
+    #[value(name = "aes256gcmsiv")]
+    Aes256GcmSiv,
+}
+
+impl SimpleCipherAlgorithm {
+    pub const ALL: [Self; 4] = [
+        Self::XChaCha20Poly1305,
+        Self::ChaCha20Poly1305,
+        Self::Aes256Gcm,
+        Self::Aes256GcmSiv,
+    ];
+
+    /// The name the algorithm is given as on the command line and shown as.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::XChaCha20Poly1305 => "xchacha20poly1305",
+            Self::ChaCha20Poly1305 => "chacha20poly1305",
+            Self::Aes256Gcm => "aes256gcm",
+            Self::Aes256GcmSiv => "aes256gcmsiv",
+        }
+    }
+
+    /// The byte the algorithm is recorded as in a streamed file or a header.
+    pub fn id(self) -> u8 {
+        match self {
+            Self::XChaCha20Poly1305 => 1,
+            Self::ChaCha20Poly1305 => 2,
+            Self::Aes256Gcm => 3,
+            Self::Aes256GcmSiv => 4,
+        }
+    }
+
+    /// The length of the algorithm's nonce in bytes. Nonces are held in 24 bytes whatever the
+    /// algorithm, and only the first `nonce_length` of them are used.
+    pub fn nonce_length(self) -> usize {
+        match self {
+            Self::XChaCha20Poly1305 => XChaCha20Poly1305::NONCE_LENGTH,
+            Self::ChaCha20Poly1305 => ChaCha20Poly1305::NONCE_LENGTH,
+            Self::Aes256Gcm => Aes256Gcm::NONCE_LENGTH,
+            Self::Aes256GcmSiv => Aes256GcmSiv::NONCE_LENGTH,
+        }
+    }
+
+    // These pick the backend of the algorithm at runtime.
+    pub(crate) fn encrypt(
+        self,
+        key: &Key,
+        nonce: &XNonce,
+        payload: Payload,
+    ) -> Result<Vec<u8>, ChachaError> {
+        match self {
+            Self::XChaCha20Poly1305 => {
+                <XChaCha20Poly1305 as AeadBackend>::encrypt(key, nonce, payload)
+            }
+            Self::ChaCha20Poly1305 => {
+                <ChaCha20Poly1305 as AeadBackend>::encrypt(key, nonce, payload)
+            }
+            Self::Aes256Gcm => <Aes256Gcm as AeadBackend>::encrypt(key, nonce, payload),
+            Self::Aes256GcmSiv => <Aes256GcmSiv as AeadBackend>::encrypt(key, nonce, payload),
+        }
+    }
+
+    pub(crate) fn decrypt(
+        self,
+        key: &Key,
+        nonce: &XNonce,
+        payload: Payload,
+    ) -> Result<Vec<u8>, ChachaError> {
+        match self {
+            Self::XChaCha20Poly1305 => {
+                <XChaCha20Poly1305 as AeadBackend>::decrypt(key, nonce, payload)
+            }
+            Self::ChaCha20Poly1305 => {
+                <ChaCha20Poly1305 as AeadBackend>::decrypt(key, nonce, payload)
+            }
+            Self::Aes256Gcm => <Aes256Gcm as AeadBackend>::decrypt(key, nonce, payload),
+            Self::Aes256GcmSiv => <Aes256GcmSiv as AeadBackend>::decrypt(key, nonce, payload),
+        }
+    }
+
+    pub(crate) fn encrypt_in_place_detached(
+        self,
+        key: &Key,
+        nonce: &XNonce,
+        aad: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<Tag, ChachaError> {
+        match self {
+            Self::XChaCha20Poly1305 => {
+                <XChaCha20Poly1305 as AeadBackend>::encrypt_in_place_detached(
+                    key, nonce, aad, buffer,
+                )
+            }
+            Self::ChaCha20Poly1305 => <ChaCha20Poly1305 as AeadBackend>::encrypt_in_place_detached(
+                key, nonce, aad, buffer,
+            ),
+            Self::Aes256Gcm => {
+                <Aes256Gcm as AeadBackend>::encrypt_in_place_detached(key, nonce, aad, buffer)
+            }
+            Self::Aes256GcmSiv => {
+                <Aes256GcmSiv as AeadBackend>::encrypt_in_place_detached(key, nonce, aad, buffer)
+            }
+        }
+    }
+
+    pub(crate) fn encrypt_many(
+        self,
+        key: &Key,
+        aad: &[u8],
+        records: &[(&[u8], XNonce)],
+    ) -> Result<Vec<Vec<u8>>, ChachaError> {
+        match self {
+            Self::XChaCha20Poly1305 => {
+                <XChaCha20Poly1305 as AeadBackend>::encrypt_many(key, aad, records)
+            }
+            Self::ChaCha20Poly1305 => {
+                <ChaCha20Poly1305 as AeadBackend>::encrypt_many(key, aad, records)
+            }
+            Self::Aes256Gcm => <Aes256Gcm as AeadBackend>::encrypt_many(key, aad, records),
+            Self::Aes256GcmSiv => <Aes256GcmSiv as AeadBackend>::encrypt_many(key, aad, records),
+        }
+    }
+
+    /// The algorithm recorded as `id`.
+    pub fn from_id(id: u8) -> Result<Self, SimpleCipherError> {
+        Self::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.id() == id)
+            .ok_or_else(|| SimpleCipherError::UnknownAlgorithm(format!("id {id}")))
+    }
+}
+
+impl fmt::Display for SimpleCipherAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// Names are matched ignoring case and dashes so `XChaCha20-Poly1305` is accepted as well.
+impl FromStr for SimpleCipherAlgorithm {
+    type Err = SimpleCipherError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let normalized = name.replace('-', "").to_ascii_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.name() == normalized)
+            .ok_or_else(|| SimpleCipherError::UnknownAlgorithm(name.to_string()))
+    }
+}
+
+/// An AEAD a message can be encrypted with, keyed with the 32 byte derived key. Its nonces are
+/// the first `NONCE_LENGTH` bytes of a 24 byte nonce.
+pub(crate) trait AeadBackend {
+    const NONCE_LENGTH: usize;
+
+    fn encrypt(key: &Key, nonce: &XNonce, payload: Payload) -> Result<Vec<u8>, ChachaError>;
+
+    fn decrypt(key: &Key, nonce: &XNonce, payload: Payload) -> Result<Vec<u8>, ChachaError>;
+
+    fn encrypt_in_place_detached(
+        key: &Key,
+        nonce: &XNonce,
+        aad: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<Tag, ChachaError>;
+
+    // This encrypts every message under its nonce with one keyed instance, so the key schedule is
+    // set up once for the whole batch.
+    fn encrypt_many(
+        key: &Key,
+        aad: &[u8],
+        records: &[(&[u8], XNonce)],
+    ) -> Result<Vec<Vec<u8>>, ChachaError>;
+}
+
+// Every AEAD of the RustCrypto crates with a 32 byte key and a 16 byte tag is a backend.
+impl<C> AeadBackend for C
+where
+    C: KeyInit<KeySize = U32> + AeadInPlace<TagSize = U16>,
+{
+    const NONCE_LENGTH: usize = C::NonceSize::USIZE;
+
+    fn encrypt(key: &Key, nonce: &XNonce, payload: Payload) -> Result<Vec<u8>, ChachaError> {
+        C::new(key).encrypt(
+            Nonce::<C>::from_slice(&nonce[..Self::NONCE_LENGTH]),
+            payload,
+        )
+    }
+
+    fn decrypt(key: &Key, nonce: &XNonce, payload: Payload) -> Result<Vec<u8>, ChachaError> {
+        C::new(key).decrypt(
+            Nonce::<C>::from_slice(&nonce[..Self::NONCE_LENGTH]),
+            payload,
+        )
+    }
+
+    fn encrypt_in_place_detached(
+        key: &Key,
+        nonce: &XNonce,
+        aad: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<Tag, ChachaError> {
+        let nonce = Nonce::<C>::from_slice(&nonce[..Self::NONCE_LENGTH]);
+        C::new(key).encrypt_in_place_detached(nonce, aad, buffer)
+    }
+
+    fn encrypt_many(
+        key: &Key,
+        aad: &[u8],
+        records: &[(&[u8], XNonce)],
+    ) -> Result<Vec<Vec<u8>>, ChachaError> {
+        let cipher = C::new(key);
+        records
+            .iter()
+            .map(|(msg, nonce)| {
+                let nonce = Nonce::<C>::from_slice(&nonce[..Self::NONCE_LENGTH]);
+                cipher.encrypt(nonce, Payload { msg, aad })
+            })
+            .collect()
+    }
+}
+
+/// The names of every algorithm, for error messages.
+pub(crate) fn algorithm_names() -> String {
+    SimpleCipherAlgorithm::ALL
+        .map(SimpleCipherAlgorithm::name)
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_backend_round_trips() {
+        let key = Key::from([7; 32]);
+        let nonce = XNonce::from([9; 24]);
+        fn payload(msg: &[u8]) -> Payload<'_, '_> {
+            Payload { msg, aad: b"aad" }
+        }
+        for algorithm in SimpleCipherAlgorithm::ALL {
+            let ciphertext = algorithm
+                .encrypt(&key, &nonce, payload(b"foobar"))
+                .expect("Failed to encrypt data");
+            assert_eq!(ciphertext.len(), b"foobar".len() + 16);
+            assert_eq!(
+                algorithm
+                    .decrypt(&key, &nonce, payload(&ciphertext))
+                    .expect("Failed to decrypt data"),
+                b"foobar"
+            );
+
+            let mut buffer = *b"foobar";
+            let tag = algorithm
+                .encrypt_in_place_detached(&key, &nonce, b"aad", &mut buffer)
+                .expect("Failed to encrypt data");
+            assert_eq!([&buffer, tag.as_slice()].concat(), ciphertext);
+
+            // Only the first `nonce_length` bytes of the nonce are used.
+            let mut unused = nonce;
+            unused[algorithm.nonce_length()..].fill(0);
+            assert_eq!(
+                algorithm.encrypt(&key, &unused, payload(b"foobar")),
+                Ok(ciphertext)
+            );
+        }
+    }
+
+    #[test]
+    fn parse_algorithm_names() {
+        for algorithm in SimpleCipherAlgorithm::ALL {
+            assert_eq!(
+                algorithm
+                    .to_string()
+                    .parse::<SimpleCipherAlgorithm>()
+                    .unwrap(),
+                algorithm
+            );
+            assert_eq!(
+                <SimpleCipherAlgorithm as ValueEnum>::from_str(algorithm.name(), false).unwrap(),
+                algorithm
+            );
+        }
+        assert_eq!(
+            "XChaCha20-Poly1305"
+                .parse::<SimpleCipherAlgorithm>()
+                .unwrap(),
+            SimpleCipherAlgorithm::XChaCha20Poly1305
+        );
+
+        let error = "rot13".parse::<SimpleCipherAlgorithm>().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unknown algorithm rot13, expected one of: xchacha20poly1305, chacha20poly1305, aes256gcm, \
+             aes256gcmsiv"
+        );
+    }
+}
@@ -0,0 +1,185 @@
+//! Writing the encrypted file so an interrupted encryption never leaves a partly written file in
+//! its place. A whole file is written to a temporary file beside it, which replaces it only once
+//! everything has been written.
+
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use tempfile::NamedTempFile;
+
+use crate::{parts::PartWriter, retry_if_busy, SimpleCipherError};
+
+/// A file written to a temporary file beside `path`, which replaces it only on
+/// [`AtomicFile::commit`]. Dropping it uncommitted leaves `path` as it was and removes the
+/// temporary file. The temporary file takes the permissions of the file it replaces, or is only
+/// readable by its owner when `path` does not exist yet.
+pub struct AtomicFile {
+    file: NamedTempFile,
+    path: PathBuf,
+}
+
+impl AtomicFile {
+    pub fn create(path: &Path, retry_on_busy: bool) -> io::Result<Self> {
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let file = retry_if_busy(retry_on_busy, || NamedTempFile::new_in(dir))?;
+        #[cfg(unix)]
+        if let Ok(metadata) = std::fs::metadata(path) {
+            file.as_file().set_permissions(metadata.permissions())?;
+        }
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Renames the temporary file over `path` once everything has been written. A
+    /// `decrypt --mmap` of the file being replaced keeps reading the old one.
+    pub fn commit(self) -> Result<(), SimpleCipherError> {
+        self.file.as_file().sync_all()?;
+        self.file.persist(self.path).map_err(|e| e.error)?;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+pub(crate) enum EncryptedFileWriter {
+    Whole(AtomicFile),
+    // Parts are written in place as the channels they are for only need them once complete.
+    Parts(PartWriter),
+}
+
+impl EncryptedFileWriter {
+    pub(crate) fn create(path: &Path, retry_on_busy: bool) -> io::Result<Self> {
+        AtomicFile::create(path, retry_on_busy).map(Self::Whole)
+    }
+
+    pub(crate) fn commit(self) -> Result<(), SimpleCipherError> {
+        match self {
+            Self::Whole(file) => file.commit(),
+            Self::Parts(mut parts) => Ok(parts.flush()?),
+        }
+    }
+}
+
+impl Write for EncryptedFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Whole(file) => file.write(buf),
+            Self::Parts(parts) => parts.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Whole(file) => file.flush(),
+            Self::Parts(parts) => parts.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CommonEncryptionOpts, NonceSource, SimpleCipherError};
+    use std::{
+        fs,
+        io::{self, Write},
+    };
+
+    // This reads a megabyte of message and then fails as an interrupted pipe would.
+    struct FailingReader(usize);
+
+    impl io::Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0 == 0 {
+                return Err(io::Error::other("the writer went away"));
+            }
+            let length = buf.len().min(self.0);
+            buf[..length].fill(b'x');
+            self.0 -= length;
+            Ok(length)
+        }
+    }
+
+    #[test]
+    fn interrupted_encryption_leaves_no_partial_file() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Provided("interrupted".to_string()),
+            stream: true,
+            force: true,
+            ..Default::default()
+        };
+        let error = opts
+            .encrypt_reader(&mut FailingReader(1 << 20))
+            .unwrap_err();
+        assert!(matches!(error, SimpleCipherError::IO(_)), "{error:?}");
+        assert_eq!(fs::read_dir(tmpdir.path()).unwrap().count(), 0);
+
+        // An encrypted file being replaced is left whole.
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        let before = fs::read(&opts.encrypted_file).unwrap();
+        assert!(opts.encrypt_reader(&mut FailingReader(1 << 20)).is_err());
+        assert_eq!(fs::read(&opts.encrypted_file).unwrap(), before);
+        assert_eq!(fs::read_dir(tmpdir.path()).unwrap().count(), 1);
+        assert_eq!(opts.decrypt().expect("Failed to decrypt data"), b"foobar");
+    }
+
+    #[test]
+    fn output_is_replaced_only_once_decrypted() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Provided("output".to_string()),
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        let output = tmpdir.path().join("plain.txt");
+        fs::write(&output, "earlier").unwrap();
+
+        let wrong_key = CommonEncryptionOpts {
+            key: Some("wrong".to_string().into()),
+            ..opts.clone()
+        };
+        let file = wrong_key
+            .create_output(&output)
+            .expect("Failed to create output");
+        assert!(wrong_key.decrypt().is_err());
+        drop(file);
+        assert_eq!(fs::read(&output).unwrap(), b"earlier");
+        assert_eq!(fs::read_dir(tmpdir.path()).unwrap().count(), 2);
+
+        let mut file = opts
+            .create_output(&output)
+            .expect("Failed to create output");
+        file.write_all(&opts.decrypt().expect("Failed to decrypt data"))
+            .unwrap();
+        file.commit().expect("Failed to commit output");
+        assert_eq!(fs::read(&output).unwrap(), b"foobar");
+
+        let same = tmpdir.path().join(".").join("encyrpted.dat");
+        assert!(matches!(
+            opts.create_output(&same),
+            Err(SimpleCipherError::OutputIsEncryptedFile(_))
+        ));
+    }
+}
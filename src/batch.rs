@@ -0,0 +1,640 @@
+//! Encrypting several files at once, with a manifest recording what was encrypted.
+
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, BufReader, ErrorKind, Read, Seek, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use crate::{
+    atomic::EncryptedFileWriter, frame, segments::segment_nonce, stream::STREAM_MAGIC,
+    CiphertextFormat, CommonEncryptionOpts, SimpleCipherAlgorithm, SimpleCipherError,
+};
+
+/// The manifest entry of a single encrypted file. It holds everything needed to find and check
+/// the file again except the key, which is never written to the manifest.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ManifestEntry {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub algorithm: SimpleCipherAlgorithm,
+    /// The fingerprint of the key as lower case hex.
+    pub key_id: String,
+    /// The nonce of this file, encoded as with `--nonce-encoding`.
+    pub nonce: String,
+    /// The BLAKE3 hash of the plaintext as lower case hex.
+    pub plaintext_blake3: String,
+}
+
+/// How [`CommonEncryptionOpts::encrypt_batch`] names and encrypts each of its inputs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Batch {
+    /// The directory every encrypted file is written to, or `None` to write each beside its
+    /// input.
+    pub output_dir: Option<PathBuf>,
+    /// The extension added to the name of each input, such as `a.txt.ske` for `a.txt`. Inputs
+    /// already ending with it are skipped as the outputs of an earlier batch.
+    pub extension: String,
+    /// Whether every file embeds its nonce in its header, so it decrypts with the key alone,
+    /// whatever the nonce options.
+    pub embed_nonces: bool,
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Self {
+            output_dir: None,
+            extension: "ske".to_string(),
+            embed_nonces: false,
+        }
+    }
+}
+
+impl Batch {
+    // This is the file `input` is encrypted to, with the extension added.
+    fn output(&self, input: &Path) -> Result<PathBuf, SimpleCipherError> {
+        let mut name = input
+            .file_name()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "Not a file name"))?
+            .to_os_string();
+        name.push(".");
+        name.push(&self.extension);
+        Ok(match &self.output_dir {
+            Some(output_dir) => output_dir.join(name),
+            None => input.with_file_name(name),
+        })
+    }
+
+    fn skips(&self, input: &Path) -> bool {
+        input
+            .extension()
+            .is_some_and(|extension| *extension == *self.extension)
+    }
+}
+
+/// What became of one input of [`CommonEncryptionOpts::encrypt_batch`].
+#[derive(Debug)]
+pub enum BatchOutcome {
+    Encrypted(ManifestEntry),
+    /// The input already has the batch extension so was not encrypted again.
+    Skipped,
+    Failed(SimpleCipherError),
+}
+
+/// One input of [`CommonEncryptionOpts::encrypt_batch`] and what became of it.
+#[derive(Debug)]
+pub struct BatchResult {
+    pub input: PathBuf,
+    pub outcome: BatchOutcome,
+}
+
+/// The files `pattern` names for a batch: the files directly inside it if it is a directory,
+/// otherwise every file matching it as a glob, in a stable order.
+pub fn batch_inputs(pattern: &str) -> Result<Vec<PathBuf>, SimpleCipherError> {
+    let mut inputs = Vec::new();
+    if Path::new(pattern).is_dir() {
+        for entry in fs::read_dir(pattern)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                inputs.push(entry.path());
+            }
+        }
+    } else {
+        for path in glob::glob(pattern)? {
+            let path = path.map_err(io::Error::from)?;
+            if !path.is_dir() {
+                inputs.push(path);
+            }
+        }
+    }
+    inputs.sort();
+    Ok(inputs)
+}
+
+impl CommonEncryptionOpts {
+    /// Encrypts each of `inputs` to a file of the same name with the batch extension added. The
+    /// nonce of each file is the nonce given with the index of the input mixed in, like the
+    /// segments of a log, so no two files share a nonce, and a generated nonce is drawn afresh
+    /// for every file. A file which fails to encrypt is reported in its result without stopping
+    /// the rest, but inputs which would be encrypted to the same file fail the whole batch
+    /// before anything is written.
+    pub fn encrypt_batch(
+        &self,
+        inputs: &[PathBuf],
+        batch: &Batch,
+    ) -> Result<Vec<BatchResult>, SimpleCipherError> {
+        let mut outputs = HashSet::new();
+        for input in inputs.iter().filter(|input| !batch.skips(input)) {
+            let output = batch.output(input)?;
+            if !outputs.insert(output.clone()) {
+                return Err(SimpleCipherError::BatchOutput(output));
+            }
+        }
+        let mut results = Vec::with_capacity(inputs.len());
+        for (index, input) in inputs.iter().enumerate() {
+            let outcome = if batch.skips(input) {
+                BatchOutcome::Skipped
+            } else {
+                match self.encrypt_batch_file(input, index as u64, batch) {
+                    Ok(entry) => BatchOutcome::Encrypted(entry),
+                    Err(e) => BatchOutcome::Failed(e),
+                }
+            };
+            results.push(BatchResult {
+                input: input.clone(),
+                outcome,
+            });
+        }
+        Ok(results)
+    }
+
+    fn encrypt_batch_file(
+        &self,
+        input: &Path,
+        index: u64,
+        batch: &Batch,
+    ) -> Result<ManifestEntry, SimpleCipherError> {
+        let output = batch.output(input)?;
+        if output.exists() && !self.force {
+            return Err(SimpleCipherError::OutputExists(output));
+        }
+        let plaintext = fs::read(input)?;
+        self.check_not_encrypted(&plaintext)?;
+        let nonce_length = self.algorithm().nonce_length();
+        let (ciphertext, nonce, header) =
+            self.seal_with(&plaintext, batch.embed_nonces, |nonce| {
+                segment_nonce(nonce, nonce_length, index)
+            })?;
+        let format = self.format().unwrap_or(CiphertextFormat::Raw);
+        let mut file = EncryptedFileWriter::create(&output, self.retry_on_busy)?;
+        file.write_all(&format.encode(&frame(&ciphertext, &header)))?;
+        file.commit()?;
+        Ok(ManifestEntry {
+            input: input.to_path_buf(),
+            output,
+            algorithm: self.algorithm(),
+            key_id: header.key_id.map(hex::encode).unwrap_or_default(),
+            nonce: self.encode_nonce(&segment_nonce(&nonce, nonce_length, index)[..nonce_length]),
+            plaintext_blake3: blake3::hash(&plaintext).to_hex().to_string(),
+        })
+    }
+}
+
+/// The outcome of authenticating one file with [`CommonEncryptionOpts::verify_all`].
+#[derive(Debug)]
+pub struct Verification {
+    pub path: PathBuf,
+    pub result: Result<(), SimpleCipherError>,
+}
+
+// This lists every file under `dir`, descending into subdirectories, in a stable order.
+fn files_under(dir: &Path) -> Result<Vec<PathBuf>, SimpleCipherError> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+impl CommonEncryptionOpts {
+    /// Authenticates every file under `dir` with the key and nonce, on up to `workers` threads,
+    /// and reports whether each decrypted. Plaintext is thrown away and streamed files are
+    /// verified chunk by chunk so a large file does not have to fit in memory. Other files are
+    /// read whole, so those longer than `max_size` bytes fail with
+    /// [`SimpleCipherError::FileTooLarge`] instead.
+    pub fn verify_all(
+        &self,
+        dir: &Path,
+        workers: NonZeroUsize,
+        max_size: u64,
+    ) -> Result<Vec<Verification>, SimpleCipherError> {
+        let files = files_under(dir)?;
+        let next = AtomicUsize::new(0);
+        let mut verifications: Vec<Verification> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers.get().min(files.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut verified = Vec::new();
+                        while let Some(path) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                            verified.push(Verification {
+                                path: path.clone(),
+                                result: self.verify_file(path, max_size),
+                            });
+                        }
+                        verified
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("verify workers do not panic"))
+                .collect()
+        });
+        verifications.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(verifications)
+    }
+
+    fn verify_file(&self, path: &Path, max_size: u64) -> Result<(), SimpleCipherError> {
+        let mut file = fs::File::open(path)?;
+        let mut magic = [0_u8; STREAM_MAGIC.len()];
+        let streamed = file.read_exact(&mut magic).is_ok() && &magic == STREAM_MAGIC;
+        file.rewind()?;
+        if streamed {
+            return self.decrypt_stream(&mut BufReader::new(file), &mut io::sink());
+        }
+        let too_large = |size| SimpleCipherError::FileTooLarge {
+            size,
+            max: max_size,
+        };
+        let size = file.metadata()?.len();
+        if size > max_size {
+            return Err(too_large(size));
+        }
+        // The file may grow after its size was checked, so never read more than one byte past
+        // the limit.
+        let mut contents = Vec::new();
+        file.take(max_size.saturating_add(1))
+            .read_to_end(&mut contents)?;
+        if contents.len() as u64 > max_size {
+            return Err(too_large(contents.len() as u64));
+        }
+        self.open(self.decryption_nonce()?.as_ref(), &contents)?;
+        Ok(())
+    }
+}
+
+/// Writes `manifest` to `path` as a pretty printed JSON array.
+pub fn write_manifest(path: &Path, manifest: &[ManifestEntry]) -> Result<(), SimpleCipherError> {
+    let mut json = serde_json::to_string_pretty(manifest).expect("manifest entries serialize");
+    json.push('\n');
+    fs::write(path, json)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NonceSource;
+
+    #[test]
+    fn manifest_lists_every_file() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let inputs: Vec<PathBuf> = ["a.txt", "b.txt", "c.txt"]
+            .iter()
+            .map(|name| {
+                let path = tmpdir.path().join(name);
+                fs::write(&path, format!("contents of {name}")).expect("Failed to write input");
+                path
+            })
+            .collect();
+        let output_dir = tmpdir.path().join("out");
+        fs::create_dir(&output_dir).expect("Failed to create output dir");
+        let opts = CommonEncryptionOpts {
+            key: Some("a batch key".to_string().into()),
+            nonce: NonceSource::Provided("batch nonce".to_string()),
+            ..Default::default()
+        };
+        let batch = Batch {
+            output_dir: Some(output_dir),
+            ..Batch::default()
+        };
+        let manifest: Vec<ManifestEntry> = opts
+            .encrypt_batch(&inputs, &batch)
+            .expect("Failed to encrypt batch")
+            .into_iter()
+            .map(|result| match result.outcome {
+                BatchOutcome::Encrypted(entry) => entry,
+                outcome => panic!("{outcome:?}"),
+            })
+            .collect();
+        let manifest_file = tmpdir.path().join("manifest.json");
+        write_manifest(&manifest_file, &manifest).expect("Failed to write manifest");
+
+        let json = fs::read_to_string(&manifest_file).expect("Failed to read manifest");
+        assert!(!json.contains("a batch key"));
+        let entries: Vec<serde_json::Value> =
+            serde_json::from_str(&json).expect("Manifest is not JSON");
+        assert_eq!(entries.len(), inputs.len());
+        for (entry, input) in entries.iter().zip(&inputs) {
+            let mut fields: Vec<&str> = entry
+                .as_object()
+                .expect("Entry is an object")
+                .keys()
+                .map(String::as_str)
+                .collect();
+            fields.sort_unstable();
+            assert_eq!(
+                fields,
+                [
+                    "algorithm",
+                    "input",
+                    "key_id",
+                    "nonce",
+                    "output",
+                    "plaintext_blake3"
+                ]
+            );
+            assert_eq!(entry["input"], input.to_str().unwrap());
+            assert_eq!(entry["algorithm"], "xchacha20poly1305");
+
+            // Each file decrypts with the nonce recorded for it.
+            let output = PathBuf::from(entry["output"].as_str().unwrap());
+            let opts = CommonEncryptionOpts {
+                key: Some("a batch key".to_string().into()),
+                encrypted_file: output,
+                nonce: NonceSource::Provided(entry["nonce"].as_str().unwrap().to_string()),
+                ..Default::default()
+            };
+            let plaintext = opts.decrypt().expect("Failed to decrypt batch file");
+            assert_eq!(plaintext, fs::read(input).unwrap());
+            assert_eq!(
+                entry["plaintext_blake3"],
+                blake3::hash(&plaintext).to_hex().as_str()
+            );
+        }
+        assert_ne!(entries[0]["nonce"], entries[1]["nonce"]);
+    }
+
+    #[test]
+    fn each_file_decrypts_with_its_embedded_nonce() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let inputs: Vec<PathBuf> = ["a.txt", "b.txt", "c.txt"]
+            .iter()
+            .map(|name| {
+                let path = tmpdir.path().join(name);
+                fs::write(&path, "the same contents").expect("Failed to write input");
+                path
+            })
+            .collect();
+        fs::create_dir(tmpdir.path().join("nested")).expect("Failed to create nested dir");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            nonce: NonceSource::Generated,
+            ..Default::default()
+        };
+        let batch = Batch {
+            extension: "dat".to_string(),
+            embed_nonces: true,
+            ..Batch::default()
+        };
+        let dir = batch_inputs(tmpdir.path().to_str().unwrap()).expect("Failed to list inputs");
+        assert_eq!(dir, inputs);
+        let results = opts
+            .encrypt_batch(&dir, &batch)
+            .expect("Failed to encrypt batch");
+        let mut outputs = Vec::new();
+        for (result, input) in results.iter().zip(&inputs) {
+            let BatchOutcome::Encrypted(entry) = &result.outcome else {
+                panic!("{result:?}");
+            };
+            assert_eq!(&result.input, input);
+            assert_eq!(entry.output, input.with_extension("txt.dat"));
+            let opts = CommonEncryptionOpts {
+                key: Some("baz".to_string().into()),
+                encrypted_file: entry.output.clone(),
+                ..Default::default()
+            };
+            assert_eq!(
+                opts.decrypt().expect("Failed to decrypt batch file"),
+                b"the same contents"
+            );
+            outputs.push(fs::read(&entry.output).unwrap());
+        }
+        // Every file has its own nonce, so the same contents encrypt differently.
+        assert_ne!(outputs[0], outputs[1]);
+        assert_ne!(outputs[1], outputs[2]);
+
+        // The outputs already exist, which fails those files without stopping the batch, and
+        // the outputs themselves are reported as skipped.
+        fs::remove_file(&inputs[1]).unwrap();
+        fs::write(tmpdir.path().join("d.txt"), "new").unwrap();
+        let pattern = tmpdir.path().join("*");
+        let inputs = batch_inputs(pattern.to_str().unwrap()).expect("Failed to list inputs");
+        let results = opts
+            .encrypt_batch(&inputs, &batch)
+            .expect("Failed to encrypt batch");
+        let outcome: Vec<(&str, &str)> = results
+            .iter()
+            .map(|result| {
+                let name = result.input.file_name().unwrap().to_str().unwrap();
+                let outcome = match &result.outcome {
+                    BatchOutcome::Encrypted(_) => "encrypted",
+                    BatchOutcome::Skipped => "skipped",
+                    BatchOutcome::Failed(SimpleCipherError::OutputExists(_)) => "exists",
+                    BatchOutcome::Failed(e) => panic!("{e:?}"),
+                };
+                (name, outcome)
+            })
+            .collect();
+        assert_eq!(
+            outcome,
+            [
+                ("a.txt", "exists"),
+                ("a.txt.dat", "skipped"),
+                ("b.txt.dat", "skipped"),
+                ("c.txt", "exists"),
+                ("c.txt.dat", "skipped"),
+                ("d.txt", "encrypted"),
+            ]
+        );
+        assert!(matches!(
+            batch_inputs("["),
+            Err(SimpleCipherError::BatchPattern(_))
+        ));
+
+        // Two inputs of the same name fail before anything is written.
+        let nested = tmpdir.path().join("nested").join("d.txt");
+        fs::write(&nested, "nested").unwrap();
+        let colliding = Batch {
+            output_dir: Some(tmpdir.path().join("nested")),
+            ..batch
+        };
+        let inputs = [tmpdir.path().join("d.txt"), nested];
+        assert!(matches!(
+            opts.encrypt_batch(&inputs, &colliding),
+            Err(SimpleCipherError::BatchOutput(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn batch_outputs_keep_the_mode_they_replace() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let inputs = [tmpdir.path().join("new.txt"), tmpdir.path().join("old.txt")];
+        for input in &inputs {
+            fs::write(input, "a batch file").expect("Failed to write input");
+        }
+        let replaced = tmpdir.path().join("old.txt.ske");
+        fs::write(&replaced, "earlier").unwrap();
+        fs::set_permissions(&replaced, fs::Permissions::from_mode(0o640)).unwrap();
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            nonce: NonceSource::Provided("batch nonce".to_string()),
+            force: true,
+            ..Default::default()
+        };
+        let results = opts
+            .encrypt_batch(&inputs, &Batch::default())
+            .expect("Failed to encrypt batch");
+        assert!(results
+            .iter()
+            .all(|result| matches!(result.outcome, BatchOutcome::Encrypted(_))));
+        let mode = |name: &str| {
+            let metadata = fs::metadata(tmpdir.path().join(name)).unwrap();
+            metadata.permissions().mode() & 0o777
+        };
+        assert_eq!(mode("new.txt.ske"), 0o600);
+        assert_eq!(mode("old.txt.ske"), 0o640);
+        assert_ne!(fs::read(&replaced).unwrap(), b"earlier");
+    }
+
+    #[test]
+    fn verify_all_reports_tampered_files() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let nested = tmpdir.path().join("nested");
+        fs::create_dir(&nested).expect("Failed to create nested dir");
+        let encrypt = |path: PathBuf, stream: bool| {
+            let opts = CommonEncryptionOpts {
+                key: Some("baz".to_string().into()),
+                encrypted_file: path,
+                nonce: NonceSource::Provided("verify nonce".to_string()),
+                stream,
+                chunk_size: Some(64),
+                ..Default::default()
+            };
+            let _ = opts
+                .encrypt("intact or not ".repeat(20))
+                .expect("Failed to encrypt data");
+        };
+        encrypt(tmpdir.path().join("a.dat"), false);
+        encrypt(tmpdir.path().join("b.dat"), true);
+        encrypt(nested.join("c.dat"), false);
+        encrypt(nested.join("d.dat"), true);
+        for tampered in [tmpdir.path().join("b.dat"), nested.join("c.dat")] {
+            let mut contents = fs::read(&tampered).unwrap();
+            let last = contents.len() - 1;
+            contents[last] ^= 1;
+            fs::write(&tampered, contents).unwrap();
+        }
+        fs::write(tmpdir.path().join("plain.txt"), "not encrypted").unwrap();
+
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            nonce: NonceSource::Provided("verify nonce".to_string()),
+            ..Default::default()
+        };
+        let verifications = opts
+            .verify_all(tmpdir.path(), NonZeroUsize::new(3).unwrap(), u64::MAX)
+            .expect("Failed to verify directory");
+        let outcome: Vec<(String, bool)> = verifications
+            .iter()
+            .map(|verification| {
+                let path = verification.path.strip_prefix(tmpdir.path()).unwrap();
+                (path.display().to_string(), verification.result.is_ok())
+            })
+            .collect();
+        assert_eq!(
+            outcome,
+            [
+                ("a.dat".to_string(), true),
+                ("b.dat".to_string(), false),
+                ("nested/c.dat".to_string(), false),
+                ("nested/d.dat".to_string(), true),
+                ("plain.txt".to_string(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn verify_all_refuses_oversized_files() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let message = "small or not ".repeat(20);
+        for (name, stream) in [("one-shot.dat", false), ("streamed.dat", true)] {
+            let opts = CommonEncryptionOpts {
+                key: Some("baz".to_string().into()),
+                encrypted_file: tmpdir.path().join(name),
+                nonce: NonceSource::Provided("verify nonce".to_string()),
+                stream,
+                chunk_size: Some(64),
+                ..Default::default()
+            };
+            let _ = opts
+                .encrypt(message.clone())
+                .expect("Failed to encrypt data");
+        }
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            nonce: NonceSource::Provided("verify nonce".to_string()),
+            ..Default::default()
+        };
+        let verifications = opts
+            .verify_all(tmpdir.path(), NonZeroUsize::MIN, 100)
+            .expect("Failed to verify directory");
+        assert!(matches!(
+            verifications[0].result,
+            Err(SimpleCipherError::FileTooLarge { max: 100, .. })
+        ));
+        // Streamed files are checked a chunk at a time, so the limit does not apply to them.
+        assert!(verifications[1].result.is_ok());
+    }
+
+    // Streamed files go through the same key checks in verify-all as in decrypt.
+    #[test]
+    fn verify_all_checks_streamed_keys() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("streamed.dat"),
+            nonce: NonceSource::Provided("verify nonce".to_string()),
+            stream: true,
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("streamed ".repeat(20))
+            .expect("Failed to encrypt data");
+        let verify = |opts: CommonEncryptionOpts| {
+            let mut verifications = opts
+                .verify_all(tmpdir.path(), NonZeroUsize::MIN, u64::MAX)
+                .expect("Failed to verify directory");
+            assert_eq!(verifications.len(), 1);
+            verifications.remove(0).result
+        };
+        assert!(verify(opts.clone()).is_ok());
+        let committing = CommonEncryptionOpts {
+            key_committing: true,
+            ..opts.clone()
+        };
+        assert!(matches!(
+            verify(committing.clone()),
+            Err(SimpleCipherError::KeyCommitmentMissing)
+        ));
+        assert!(matches!(
+            committing.decrypt(),
+            Err(SimpleCipherError::KeyCommitmentMissing)
+        ));
+        let expecting = CommonEncryptionOpts {
+            expect_key_fingerprint: Some("0000000000000000".to_string()),
+            ..opts
+        };
+        assert!(matches!(
+            verify(expecting),
+            Err(SimpleCipherError::KeyFingerprintMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,138 @@
+//! A builder of [`CommonEncryptionOpts`] for library users, which checks that exactly one nonce
+//! choice was made when it builds rather than when the nonce is first needed.
+
+use std::path::PathBuf;
+use zeroize::Zeroizing;
+
+use crate::{CommonEncryptionOpts, NonceSource, SimpleCipherError};
+
+/// Builds [`CommonEncryptionOpts`] with one of [`CipherBuilder::with_generated_nonce`],
+/// [`CipherBuilder::with_nonce`], [`CipherBuilder::no_nonce`] or
+/// [`CipherBuilder::with_embedded_nonce`]. Every other option has its command line default.
+#[derive(Clone, Debug, Default)]
+pub struct CipherBuilder {
+    opts: CommonEncryptionOpts,
+    // These are the nonce choices made, as the flag each stands for.
+    nonce_choices: Vec<&'static str>,
+}
+
+impl CipherBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The key string, as `--key` takes it.
+    pub fn key(mut self, key: impl Into<String>) -> Self {
+        self.opts.key = Some(Zeroizing::new(key.into()));
+        self
+    }
+
+    /// The file the message is encrypted to or decrypted from.
+    pub fn encrypted_file(mut self, encrypted_file: impl Into<PathBuf>) -> Self {
+        self.opts.encrypted_file = encrypted_file.into();
+        self
+    }
+
+    /// Encrypts with a fresh nonce, embedded in the header of the encrypted file.
+    pub fn with_generated_nonce(mut self) -> Self {
+        self.opts.nonce = NonceSource::Generated;
+        self.nonce_choices.push("--generate-nonce");
+        self
+    }
+
+    /// Encrypts or decrypts with `nonce`, as `--nonce` takes it.
+    pub fn with_nonce(mut self, nonce: impl Into<String>) -> Self {
+        self.opts.nonce = NonceSource::Provided(nonce.into());
+        self.nonce_choices.push("--nonce");
+        self
+    }
+
+    /// Encrypts or decrypts with the all zero nonce. **This is not recommended.**
+    pub fn no_nonce(mut self) -> Self {
+        self.opts.nonce = NonceSource::Zero;
+        self.nonce_choices.push("--no-nonce");
+        self
+    }
+
+    /// Decrypts with the nonce embedded in the header of the encrypted file.
+    pub fn with_embedded_nonce(mut self) -> Self {
+        self.nonce_choices.push("the embedded nonce");
+        self
+    }
+
+    /// Returns the options, or an error unless exactly one nonce choice was made.
+    pub fn build(self) -> Result<CommonEncryptionOpts, SimpleCipherError> {
+        match self.nonce_choices.as_slice() {
+            [] => Err(SimpleCipherError::NonceChoiceUndeteremined),
+            [_] => Ok(self.opts),
+            choices => Err(SimpleCipherError::NonceChoiceConflict(choices.join(", "))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_each_nonce_choice() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let builder = CipherBuilder::new()
+            .key("baz")
+            .encrypted_file(tmpdir.path().join("encyrpted.dat"));
+        let choices = [
+            (
+                builder.clone().with_generated_nonce(),
+                NonceSource::Generated,
+            ),
+            (
+                builder.clone().with_nonce("built"),
+                NonceSource::Provided("built".to_string()),
+            ),
+            (builder.clone().no_nonce(), NonceSource::Zero),
+        ];
+        for (builder, source) in choices {
+            let opts = builder.build().expect("Failed to build options");
+            assert_eq!(opts.nonce_source(), &source);
+            let _ = opts
+                .encrypt("foobar".to_string())
+                .expect("Failed to encrypt data");
+            let decrypting = match source {
+                NonceSource::Generated => CipherBuilder::new()
+                    .key("baz")
+                    .encrypted_file(&opts.encrypted_file)
+                    .with_embedded_nonce()
+                    .build()
+                    .expect("Failed to build options"),
+                _ => opts,
+            };
+            assert_eq!(
+                decrypting.decrypt().expect("Failed to decrypt data"),
+                b"foobar"
+            );
+            std::fs::remove_file(&decrypting.encrypted_file).unwrap();
+        }
+    }
+
+    #[test]
+    fn conflicting_nonce_choices_fail_to_build() {
+        assert!(matches!(
+            CipherBuilder::new().key("baz").build(),
+            Err(SimpleCipherError::NonceChoiceUndeteremined)
+        ));
+        let error = CipherBuilder::new()
+            .no_nonce()
+            .with_nonce("built")
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "More than one nonce choice was made: --no-nonce, --nonce"
+        );
+        assert!(CipherBuilder::new()
+            .with_generated_nonce()
+            .with_embedded_nonce()
+            .build()
+            .is_err());
+    }
+}
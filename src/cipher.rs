@@ -0,0 +1,366 @@
+//! The cryptographic state of one shot encryption, a derived key and the settings which change
+//! the ciphertext, for library users who have a key rather than command line options.
+
+use chacha20poly1305::{aead::Payload, Key, XNonce};
+use std::fmt;
+use zeroize::Zeroizing;
+
+use crate::{
+    frame, header, kdf, unframe_and_decrypt, Checksum, Compression, Header, KdfParams,
+    SimpleCipherAlgorithm, SimpleCipherError, TagPosition, TAG_LENGTH,
+};
+
+/// What the AEAD authenticates for one message, as returned by
+/// [`CommonEncryptionOpts::authenticated_bytes`](crate::CommonEncryptionOpts::authenticated_bytes).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthenticatedBytes {
+    /// The header but for its checksum and key id, followed by the `--aad`. Version 1 headers
+    /// are not authenticated, leaving only the `--aad`.
+    pub associated_data: Vec<u8>,
+    /// The message as it is encrypted, after any compression.
+    pub message: Vec<u8>,
+}
+
+// This is what the last `Cipher::seal` on this thread authenticated, so tests can compare it.
+#[cfg(test)]
+thread_local! {
+    pub(crate) static LAST_SEALED: std::cell::RefCell<Option<AuthenticatedBytes>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+/// Encrypts and decrypts the contents of raw encrypted files under a key which has already been
+/// derived. [`CommonEncryptionOpts::cipher`](crate::CommonEncryptionOpts::cipher) builds one
+/// from the command line options; every setting otherwise has its command line default.
+/// The key is wiped from memory when the cipher is dropped.
+pub struct Cipher {
+    key: Zeroizing<Key>,
+    algorithm: SimpleCipherAlgorithm,
+    kdf: KdfParams,
+    key_committing: bool,
+    tag_position: TagPosition,
+    compression: Compression,
+    aad: Vec<u8>,
+}
+
+// The key is never printed, even in debug output.
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cipher")
+            .field("algorithm", &self.algorithm)
+            .field("kdf", &self.kdf)
+            .field("key_committing", &self.key_committing)
+            .field("tag_position", &self.tag_position)
+            .field("compression", &self.compression)
+            .field("aad", &self.aad)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Cipher {
+    pub fn new(key: impl Into<Zeroizing<Key>>) -> Self {
+        Self {
+            key: key.into(),
+            algorithm: SimpleCipherAlgorithm::default(),
+            kdf: KdfParams::None,
+            key_committing: false,
+            tag_position: TagPosition::default(),
+            compression: Compression::None,
+            aad: Vec::new(),
+        }
+    }
+
+    /// The AEAD to encrypt with, as `--algorithm` takes it. Decryption refuses a file recorded
+    /// with a different one.
+    pub fn with_algorithm(mut self, algorithm: SimpleCipherAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// The KDF parameters the key was derived with, recorded in the header so the key string
+    /// decrypts the file as well as the key.
+    pub fn with_kdf(mut self, kdf: KdfParams) -> Self {
+        self.kdf = kdf;
+        self
+    }
+
+    /// Whether to record a commitment to the key, as `--key-committing` does. Decryption then
+    /// refuses contents without one.
+    pub fn with_key_commitment(mut self, key_committing: bool) -> Self {
+        self.key_committing = key_committing;
+        self
+    }
+
+    /// Where the authentication tag is placed, as `--tag-position` takes it.
+    pub fn with_tag_position(mut self, tag_position: TagPosition) -> Self {
+        self.tag_position = tag_position;
+        self
+    }
+
+    /// How the message is compressed before it is encrypted, as `--compress` takes it.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Associated data authenticated along with every message, as `--aad` takes it.
+    pub fn with_aad(mut self, aad: impl Into<Vec<u8>>) -> Self {
+        self.aad = aad.into();
+        self
+    }
+
+    /// Encrypts `plaintext` under `nonce` to the contents of a raw encrypted file, header
+    /// included. The nonce is not embedded so decryption needs it as well.
+    pub fn encrypt_bytes(
+        &self,
+        nonce: &XNonce,
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, SimpleCipherError> {
+        let header = self.header(None);
+        Ok(frame(&self.seal(nonce, &header, plaintext)?, &header))
+    }
+
+    /// Encrypts each message under its nonce like [`Cipher::encrypt_bytes`], setting up the
+    /// AEAD once for the whole batch rather than once per message, for many small records.
+    pub fn encrypt_many(
+        &self,
+        records: &[(&[u8], XNonce)],
+    ) -> Result<Vec<Vec<u8>>, SimpleCipherError> {
+        let compressed = records
+            .iter()
+            .map(|(message, _)| self.compression.compress(message))
+            .collect::<Result<Vec<_>, _>>()?;
+        let messages: Vec<(&[u8], XNonce)> = compressed
+            .iter()
+            .zip(records)
+            .map(|(message, (_, nonce))| (message.as_ref(), *nonce))
+            .collect();
+        let header = self.header(None);
+        let ciphertexts = self.algorithm.encrypt_many(
+            &self.key,
+            &header.associated_data(&self.aad),
+            &messages,
+        )?;
+        Ok(ciphertexts
+            .into_iter()
+            .map(|mut ciphertext| {
+                self.tag_position.place(&mut ciphertext);
+                frame(&ciphertext, &header)
+            })
+            .collect())
+    }
+
+    /// Decrypts the contents of a raw encrypted file, using `nonce` unless the header embeds
+    /// one.
+    pub fn decrypt_bytes(
+        &self,
+        nonce: &XNonce,
+        contents: &[u8],
+    ) -> Result<Vec<u8>, SimpleCipherError> {
+        unframe_and_decrypt(
+            contents,
+            Some(nonce),
+            Some(self.algorithm),
+            self.tag_position,
+            &self.aad,
+            self.key_committing,
+            |_| Ok(self.key.clone()),
+        )
+    }
+
+    // This is the header of a ciphertext from this cipher but for its checksum, embedding
+    // `nonce` if given.
+    pub(crate) fn header(&self, nonce: Option<&XNonce>) -> Header {
+        Header {
+            version: header::VERSION,
+            checksum: None,
+            kdf: self.kdf,
+            key_id: Some(kdf::key_id(&self.key)),
+            key_commitment: self
+                .key_committing
+                .then(|| *kdf::key_commitment(&self.key).as_bytes()),
+            nonce: nonce.map(|nonce| (*nonce).into()),
+            algorithm: self.algorithm,
+            compression: self.compression,
+        }
+    }
+
+    // This is what `seal` authenticates for `plaintext` under `header`.
+    pub(crate) fn authenticated(
+        &self,
+        header: &Header,
+        plaintext: &[u8],
+    ) -> Result<AuthenticatedBytes, SimpleCipherError> {
+        Ok(AuthenticatedBytes {
+            associated_data: header.associated_data(&self.aad),
+            message: self.compression.compress(plaintext)?.into_owned(),
+        })
+    }
+
+    // This compresses and encrypts `plaintext` under `nonce`, authenticating `header` along
+    // with the associated data, with the tag in its position.
+    pub(crate) fn seal(
+        &self,
+        nonce: &XNonce,
+        header: &Header,
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, SimpleCipherError> {
+        let authenticated = self.authenticated(header, plaintext)?;
+        #[cfg(test)]
+        LAST_SEALED.with(|sealed| *sealed.borrow_mut() = Some(authenticated.clone()));
+        let payload = Payload {
+            msg: &authenticated.message,
+            aad: &authenticated.associated_data,
+        };
+        let mut ciphertext = self.algorithm.encrypt(&self.key, nonce, payload)?;
+        self.tag_position.place(&mut ciphertext);
+        Ok(ciphertext)
+    }
+
+    // This is `frame` of `seal` for --prealloc. The encrypted file is allocated once at its final
+    // size and the plaintext is encrypted in place after `header`, which is written last once
+    // the checksum is known. The header has the same length either way as its fields are fixed
+    // size.
+    pub(crate) fn seal_framed(
+        &self,
+        nonce: &XNonce,
+        header: Header,
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, SimpleCipherError> {
+        let message = self.compression.compress(plaintext)?;
+        let ciphertext_length = message.len() + TAG_LENGTH;
+        let placeholder = Header {
+            checksum: Some(Checksum {
+                length: ciphertext_length as u64,
+                crc32: 0,
+            }),
+            ..header.clone()
+        };
+        let header_length = placeholder.to_bytes().len();
+        let mut contents = Vec::with_capacity(header_length + ciphertext_length);
+        contents.resize(header_length, 0);
+        contents.extend_from_slice(&message);
+        let tag = self.algorithm.encrypt_in_place_detached(
+            &self.key,
+            nonce,
+            &header.associated_data(&self.aad),
+            &mut contents[header_length..],
+        )?;
+        contents.extend_from_slice(&tag);
+        self.tag_position.place(&mut contents[header_length..]);
+        let header = Header {
+            checksum: Some(Checksum::of(&contents[header_length..])),
+            ..header
+        };
+        contents[..header_length].copy_from_slice(&header.to_bytes());
+        Ok(contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{key_from_string, CommonEncryptionOpts, NonceSource};
+
+    #[test]
+    fn key_is_wiped_on_drop() {
+        fn wiped_on_drop<T: zeroize::ZeroizeOnDrop>(_: &T) {}
+        let cipher = Cipher::new(key_from_string("baz").unwrap());
+        wiped_on_drop(&cipher.key);
+    }
+
+    #[test]
+    fn cipher_round_trips_without_options() {
+        let nonce = XNonce::from([0x24; 24]);
+        for algorithm in SimpleCipherAlgorithm::ALL {
+            let cipher = Cipher::new(Key::from([0x42; 32]))
+                .with_algorithm(algorithm)
+                .with_compression(Compression::Zstd)
+                .with_aad("context");
+            let contents = cipher
+                .encrypt_bytes(&nonce, b"foobar")
+                .expect("Failed to encrypt");
+            assert_eq!(
+                cipher
+                    .decrypt_bytes(&nonce, &contents)
+                    .expect("Failed to decrypt"),
+                b"foobar"
+            );
+            let other_aad = Cipher::new(Key::from([0x42; 32]))
+                .with_algorithm(algorithm)
+                .with_compression(Compression::Zstd)
+                .with_aad("other");
+            assert!(other_aad
+                .decrypt_bytes(&nonce, &contents)
+                .unwrap_err()
+                .is_auth_failure());
+        }
+        let cipher = Cipher::new(Key::from([0x42; 32]));
+        assert!(!format!("{cipher:?}").contains("key:"));
+    }
+
+    #[test]
+    fn batch_decrypts_record_by_record() {
+        let records: Vec<(Vec<u8>, XNonce)> = (0..20_u8)
+            .map(|i| (format!("record {i}").into_bytes(), XNonce::from([i; 24])))
+            .collect();
+        let borrowed: Vec<(&[u8], XNonce)> = records
+            .iter()
+            .map(|(message, nonce)| (message.as_slice(), *nonce))
+            .collect();
+        for algorithm in SimpleCipherAlgorithm::ALL {
+            let cipher = Cipher::new(Key::from([0x42; 32]))
+                .with_algorithm(algorithm)
+                .with_tag_position(TagPosition::Prefix)
+                .with_aad("batch");
+            let encrypted = cipher
+                .encrypt_many(&borrowed)
+                .expect("Failed to encrypt batch");
+            assert_eq!(encrypted.len(), records.len());
+            for ((message, nonce), contents) in records.iter().zip(&encrypted) {
+                assert_eq!(
+                    contents,
+                    &cipher.encrypt_bytes(nonce, message).unwrap(),
+                    "{algorithm}"
+                );
+                assert_eq!(
+                    &cipher
+                        .decrypt_bytes(nonce, contents)
+                        .expect("Failed to decrypt record"),
+                    message
+                );
+            }
+        }
+        assert!(Cipher::new(Key::from([0x42; 32]))
+            .encrypt_many(&[])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn cipher_interoperates_with_options() {
+        let nonce = XNonce::from([0x24; 24]);
+        let cipher = Cipher::new(key_from_string("baz").unwrap()).with_key_commitment(true);
+        let contents = cipher
+            .encrypt_bytes(&nonce, b"foobar")
+            .expect("Failed to encrypt");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            nonce: NonceSource::Provided("interop".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            opts.decrypt_bytes(&contents, &nonce)
+                .expect("Failed to decrypt"),
+            b"foobar"
+        );
+
+        let (contents, nonce) = opts.encrypt_bytes(b"bazqux").expect("Failed to encrypt");
+        let built = opts.cipher().expect("Failed to build cipher");
+        assert_eq!(
+            built
+                .decrypt_bytes(&nonce, &contents)
+                .expect("Failed to decrypt"),
+            b"bazqux"
+        );
+    }
+}
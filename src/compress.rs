@@ -0,0 +1,104 @@
+//! Compression of the message before it is encrypted. The method is recorded in the header so
+//! decryption decompresses the message again without being told.
+
+use clap::ValueEnum;
+use flate2::{read::GzDecoder, write::GzEncoder};
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    io::{Read, Write},
+};
+
+use crate::SimpleCipherError;
+
+// zstd's own default, which compresses text well without being slow.
+const ZSTD_LEVEL: i32 = 3;
+
+/// How a message is compressed before it is encrypted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// The message is encrypted as it is.
+    #[default]
+    None,
+    /// Zstandard, which is quicker and usually smaller.
+    Zstd,
+    /// Gzip, for decompressing with common tools once decrypted.
+    Gzip,
+}
+
+impl Compression {
+    const ALL: [Self; 3] = [Self::None, Self::Zstd, Self::Gzip];
+
+    /// The byte the compression is recorded as in a header.
+    pub fn id(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Zstd => 1,
+            Self::Gzip => 2,
+        }
+    }
+
+    /// The compression recorded as `id`.
+    pub fn from_id(id: u8) -> Result<Self, SimpleCipherError> {
+        Self::ALL
+            .into_iter()
+            .find(|compression| compression.id() == id)
+            .ok_or(SimpleCipherError::UnknownCompression(id))
+    }
+
+    pub(crate) fn compress(self, message: &[u8]) -> Result<Cow<'_, [u8]>, SimpleCipherError> {
+        Ok(match self {
+            Self::None => Cow::Borrowed(message),
+            Self::Zstd => Cow::Owned(zstd::encode_all(message, ZSTD_LEVEL)?),
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(message)?;
+                Cow::Owned(encoder.finish()?)
+            }
+        })
+    }
+
+    pub(crate) fn decompress(self, plaintext: Vec<u8>) -> Result<Vec<u8>, SimpleCipherError> {
+        let decompressed = match self {
+            Self::None => return Ok(plaintext),
+            Self::Zstd => zstd::decode_all(plaintext.as_slice()),
+            Self::Gzip => {
+                let mut message = Vec::new();
+                GzDecoder::new(plaintext.as_slice())
+                    .read_to_end(&mut message)
+                    .map(|_| message)
+            }
+        };
+        decompressed.map_err(SimpleCipherError::Decompression)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_compression_round_trips() {
+        let message = "foobar ".repeat(100);
+        for compression in Compression::ALL {
+            assert_eq!(Compression::from_id(compression.id()).unwrap(), compression);
+            let compressed = compression.compress(message.as_bytes()).unwrap();
+            if compression != Compression::None {
+                assert!(compressed.len() < message.len() / 4, "{compression:?}");
+            }
+            assert_eq!(
+                compression.decompress(compressed.into_owned()).unwrap(),
+                message.as_bytes()
+            );
+        }
+        assert!(matches!(
+            Compression::Zstd.decompress(b"not zstd".to_vec()),
+            Err(SimpleCipherError::Decompression(_))
+        ));
+        assert!(matches!(
+            Compression::from_id(9),
+            Err(SimpleCipherError::UnknownCompression(9))
+        ));
+    }
+}
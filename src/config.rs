@@ -0,0 +1,252 @@
+use clap::Command;
+use serde::Deserialize;
+use std::{env, fs, path::PathBuf};
+
+use crate::SimpleCipherError;
+
+/// This environment variable overrides the location of the defaults file.
+pub const CONFIG_ENV: &str = "SKX_CONFIG";
+const CONFIG_DIR: &str = "symmetric-key-exercise";
+const CONFIG_FILE: &str = "config.toml";
+
+// Secrets are never read from the defaults file, even if a user puts them there.
+const SECRET_KEYS: &[&str] = &["key", "previous-key"];
+
+/// Default values for command line options loaded from a TOML file. Explicit command line flags
+/// always take precedence over these.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConfigDefaults {
+    pub encrypted_file: Option<PathBuf>,
+    pub format: Option<String>,
+    pub algorithm: Option<String>,
+    pub key_format: Option<String>,
+    pub min_key_length: Option<usize>,
+    pub nonce_generator: Option<String>,
+    pub nonce_display_width: Option<usize>,
+    pub nonce_encoding: Option<String>,
+    pub tag_position: Option<String>,
+    pub compress: Option<String>,
+    pub kdf: Option<String>,
+    pub key_hash_algorithm: Option<String>,
+    pub argon2_memory: Option<u32>,
+    pub argon2_iterations: Option<u32>,
+    pub scrypt_log_n: Option<u8>,
+    pub scrypt_r: Option<u32>,
+    pub scrypt_p: Option<u32>,
+    pub pbkdf2_iterations: Option<u32>,
+    pub limit_memory: Option<u64>,
+    pub chunk_size: Option<u32>,
+    pub rekey_interval: Option<u32>,
+    pub max_concurrency: Option<usize>,
+    pub max_decrypt_attempts: Option<usize>,
+    pub split_output_size: Option<u64>,
+    pub key_prompt_confirm_timeout: Option<u64>,
+}
+
+impl ConfigDefaults {
+    /// Returns the defaults file location: `$SKX_CONFIG` if set, otherwise `config.toml` in the
+    /// `symmetric-key-exercise` directory of `$XDG_CONFIG_HOME` or `~/.config`.
+    pub fn path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os(CONFIG_ENV) {
+            return Some(PathBuf::from(path));
+        }
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_home.join(CONFIG_DIR).join(CONFIG_FILE))
+    }
+
+    /// Loads the defaults file. A missing file at the standard location is not an error but a
+    /// missing file named by `$SKX_CONFIG` is.
+    pub fn load() -> Result<Self, SimpleCipherError> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        if env::var_os(CONFIG_ENV).is_none() && !path.exists() {
+            return Ok(Self::default());
+        }
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, SimpleCipherError> {
+        let table: toml::Table = contents.parse()?;
+        if let Some(secret) = SECRET_KEYS.iter().find(|key| table.contains_key(**key)) {
+            return Err(SimpleCipherError::ConfigSecret(secret.to_string()));
+        }
+        Ok(table.try_into()?)
+    }
+
+    /// Sets these defaults as the default values of the matching arguments of `cmd`. Values
+    /// are validated by clap when the command line is parsed, exactly like explicit flags.
+    pub fn apply(&self, mut cmd: Command) -> Command {
+        let defaults = [
+            (
+                "encrypted_file",
+                self.encrypted_file
+                    .as_ref()
+                    .map(|path| path.to_string_lossy().into_owned()),
+            ),
+            ("format", self.format.clone()),
+            ("algorithm", self.algorithm.clone()),
+            ("key_format", self.key_format.clone()),
+            (
+                "min_key_length",
+                self.min_key_length.map(|length| length.to_string()),
+            ),
+            ("nonce_generator", self.nonce_generator.clone()),
+            ("nonce_encoding", self.nonce_encoding.clone()),
+            (
+                "nonce_display_width",
+                self.nonce_display_width.map(|width| width.to_string()),
+            ),
+            ("tag_position", self.tag_position.clone()),
+            ("compress", self.compress.clone()),
+            ("kdf", self.kdf.clone()),
+            ("key_hash_algorithm", self.key_hash_algorithm.clone()),
+            (
+                "argon2_memory",
+                self.argon2_memory.map(|memory| memory.to_string()),
+            ),
+            (
+                "argon2_iterations",
+                self.argon2_iterations
+                    .map(|iterations| iterations.to_string()),
+            ),
+            (
+                "scrypt_log_n",
+                self.scrypt_log_n.map(|log_n| log_n.to_string()),
+            ),
+            ("scrypt_r", self.scrypt_r.map(|r| r.to_string())),
+            ("scrypt_p", self.scrypt_p.map(|p| p.to_string())),
+            (
+                "pbkdf2_iterations",
+                self.pbkdf2_iterations
+                    .map(|iterations| iterations.to_string()),
+            ),
+            (
+                "limit_memory",
+                self.limit_memory.map(|limit| limit.to_string()),
+            ),
+            ("chunk_size", self.chunk_size.map(|size| size.to_string())),
+            (
+                "rekey_interval",
+                self.rekey_interval.map(|interval| interval.to_string()),
+            ),
+            (
+                "max_concurrency",
+                self.max_concurrency.map(|workers| workers.to_string()),
+            ),
+            (
+                "max_decrypt_attempts",
+                self.max_decrypt_attempts.map(|max| max.to_string()),
+            ),
+            (
+                "split_output_size",
+                self.split_output_size.map(|size| size.to_string()),
+            ),
+            (
+                "key_prompt_confirm_timeout",
+                self.key_prompt_confirm_timeout
+                    .map(|timeout| timeout.to_string()),
+            ),
+        ];
+        for (id, value) in defaults {
+            if let Some(value) = value {
+                cmd = cmd.mut_arg(id, |arg| arg.default_value(value));
+            }
+        }
+        cmd
+    }
+
+    /// Like [`ConfigDefaults::apply`] for commands which decrypt. The algorithm is read from the
+    /// header there, and a default would be checked against it as if `--algorithm` had been
+    /// given, so files encrypted with any other algorithm would fail. It is not applied.
+    pub fn apply_to_decrypt(&self, cmd: Command) -> Command {
+        Self {
+            algorithm: None,
+            ..self.clone()
+        }
+        .apply(cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CiphertextFormat, CommonEncryptionOpts, SimpleCipherAlgorithm};
+    use clap::{CommandFactory, FromArgMatches, Parser};
+
+    #[derive(Parser, Debug)]
+    struct Opt {
+        #[command(flatten)]
+        shared: CommonEncryptionOpts,
+    }
+
+    fn parse(defaults: &ConfigDefaults, args: &[&str]) -> CommonEncryptionOpts {
+        let matches = defaults
+            .apply(Opt::command())
+            .try_get_matches_from(args)
+            .expect("Failed to parse arguments");
+        Opt::from_arg_matches(&matches)
+            .expect("Failed to build options")
+            .shared
+    }
+
+    #[test]
+    fn config_default_is_applied_and_overridden() {
+        let defaults = ConfigDefaults::from_toml("format = \"pem\"\nchunk-size = 2048\n")
+            .expect("Failed to parse config");
+
+        let opts = parse(&defaults, &["test", "-k", "baz", "--no-nonce"]);
+        assert_eq!(opts.format, Some(CiphertextFormat::Pem));
+        assert_eq!(opts.chunk_size, Some(2048));
+
+        let opts = parse(
+            &defaults,
+            &["test", "-k", "baz", "--no-nonce", "--format", "hex"],
+        );
+        assert_eq!(opts.format, Some(CiphertextFormat::Hex));
+    }
+
+    #[test]
+    fn config_algorithm_is_not_checked_on_decrypt() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("encrypted.dat");
+        let encrypted_file = encrypted_file.to_str().unwrap();
+        let defaults = ConfigDefaults::from_toml("algorithm = \"aes256gcm\"\n")
+            .expect("Failed to parse config");
+        let args = ["test", "-k", "baz", "-n", "config", "-e", encrypted_file];
+
+        let opts = parse(&ConfigDefaults::default(), &args);
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        assert_eq!(
+            parse(&defaults, &args).algorithm,
+            Some(SimpleCipherAlgorithm::Aes256Gcm)
+        );
+
+        let matches = defaults
+            .apply_to_decrypt(Opt::command())
+            .try_get_matches_from(args)
+            .expect("Failed to parse arguments");
+        let opts = Opt::from_arg_matches(&matches).unwrap().shared;
+        assert_eq!(opts.algorithm, None);
+        assert_eq!(opts.decrypt().expect("Failed to decrypt data"), b"foobar");
+    }
+
+    #[test]
+    fn config_rejects_secrets() {
+        let out = ConfigDefaults::from_toml("key = \"hunter2\"\n");
+        assert_eq!(
+            format!("{:?}", out.unwrap_err()),
+            format!("{:?}", SimpleCipherError::ConfigSecret("key".to_string()))
+        );
+    }
+
+    #[test]
+    fn config_rejects_unknown_options() {
+        assert!(ConfigDefaults::from_toml("not-an-option = 1\n").is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use base64::{
+    alphabet,
+    engine::{
+        general_purpose::{
+            GeneralPurpose, GeneralPurposeConfig, STANDARD as BASE64, URL_SAFE_NO_PAD,
+        },
+        DecodePaddingMode,
+    },
+    DecodeError, Engine,
+};
+use clap::ValueEnum;
+use zeroize::Zeroizing;
+
+use crate::SimpleCipherError;
+
+const PEM_BEGIN: &str = "-----BEGIN SKE MESSAGE-----";
+const PEM_END: &str = "-----END SKE MESSAGE-----";
+const PEM_LINE_WIDTH: usize = 64;
+
+// Base64 ciphertext is decoded with or without padding, as URL-safe base64 is written without.
+const ANY_PADDING_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Decodes base64 in either the standard or the URL-safe alphabet with `engine`, so a value is
+/// read back whichever variant wrote it.
+pub(crate) fn decode_base64(engine: &GeneralPurpose, text: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    // The copy may be of a key, so it is wiped once decoded.
+    let standard: Zeroizing<Vec<u8>> = Zeroizing::new(
+        text.iter()
+            .map(|c| match c {
+                b'-' => b'+',
+                b'_' => b'/',
+                c => *c,
+            })
+            .collect(),
+    );
+    engine.decode(&*standard)
+}
+
+/// The encoding used when writing the ciphertext to the encrypted file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CiphertextFormat {
+    /// The raw ciphertext bytes.
+    Raw,
+    /// Lower case hex of the ciphertext bytes.
+    Hex,
+    /// Standard base64 of the ciphertext bytes.
+    Base64,
+    /// URL-safe base64 of the ciphertext bytes, using `-` and `_` and no padding.
+    Base64Url,
+    /// Base64 wrapped at 64 columns between `-----BEGIN SKE MESSAGE-----` markers.
+    Pem,
+}
+
+impl CiphertextFormat {
+    pub const ALL: [CiphertextFormat; 5] = [
+        Self::Raw,
+        Self::Hex,
+        Self::Base64,
+        Self::Base64Url,
+        Self::Pem,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Raw => "raw",
+            Self::Hex => "hex",
+            Self::Base64 => "base64",
+            Self::Base64Url => "base64-url",
+            Self::Pem => "pem",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Raw => "the raw ciphertext bytes",
+            Self::Hex => "lower case hex of the ciphertext bytes",
+            Self::Base64 => "standard base64 of the ciphertext bytes",
+            Self::Base64Url => "URL-safe base64 of the ciphertext bytes without padding",
+            Self::Pem => "base64 wrapped at 64 columns between SKE MESSAGE markers",
+        }
+    }
+
+    // This sniffs the contents of an encrypted file to guess which encoding was used to write it.
+    // The checks go from most to least specific as every hex string is also valid base64 and
+    // every file is valid raw.
+    pub fn detect(data: &[u8]) -> Self {
+        let trimmed = data.trim_ascii();
+        if trimmed.starts_with(PEM_BEGIN.as_bytes()) {
+            return Self::Pem;
+        }
+        if trimmed.is_empty() {
+            return Self::Raw;
+        }
+        if trimmed.len().is_multiple_of(2) && trimmed.iter().all(u8::is_ascii_hexdigit) {
+            return Self::Hex;
+        }
+        let is_base64_char = |c: &u8| c.is_ascii_alphanumeric() || matches!(c, b'+' | b'/' | b'=');
+        if trimmed.len().is_multiple_of(4) && trimmed.iter().all(is_base64_char) {
+            return Self::Base64;
+        }
+        // Unpadded base64 is never one character past a multiple of four.
+        let is_url_char = |c: &u8| c.is_ascii_alphanumeric() || matches!(c, b'-' | b'_');
+        if trimmed.len() % 4 != 1 && trimmed.iter().all(is_url_char) {
+            return Self::Base64Url;
+        }
+        Self::Raw
+    }
+
+    pub fn encode(&self, ciphertext: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Raw => ciphertext.to_vec(),
+            Self::Hex => hex::encode(ciphertext).into_bytes(),
+            Self::Base64 => BASE64.encode(ciphertext).into_bytes(),
+            Self::Base64Url => URL_SAFE_NO_PAD.encode(ciphertext).into_bytes(),
+            Self::Pem => {
+                let encoded = BASE64.encode(ciphertext);
+                let mut armored = format!("{PEM_BEGIN}\n");
+                for line in encoded.as_bytes().chunks(PEM_LINE_WIDTH) {
+                    // The base64 alphabet is ascii so every chunk is valid utf8.
+                    armored.push_str(std::str::from_utf8(line).expect("base64 is ascii"));
+                    armored.push('\n');
+                }
+                armored.push_str(PEM_END);
+                armored.push('\n');
+                armored.into_bytes()
+            }
+        }
+    }
+
+    pub fn decode(&self, data: &[u8]) -> Result<Vec<u8>, SimpleCipherError> {
+        match self {
+            Self::Raw => Ok(data.to_vec()),
+            Self::Hex => Ok(hex::decode(data.trim_ascii())?),
+            Self::Base64 | Self::Base64Url => {
+                Ok(decode_base64(&ANY_PADDING_BASE64, data.trim_ascii())?)
+            }
+            Self::Pem => {
+                let text =
+                    std::str::from_utf8(data).map_err(|_| SimpleCipherError::PemMalformed)?;
+                let body = text
+                    .trim()
+                    .strip_prefix(PEM_BEGIN)
+                    .and_then(|rest| rest.strip_suffix(PEM_END))
+                    .ok_or(SimpleCipherError::PemMalformed)?;
+                let body: String = body.split_whitespace().collect();
+                Ok(BASE64.decode(body)?)
+            }
+        }
+    }
+
+    // This decodes the file using the detected format. If the detected format fails to decode,
+    // the data was probably raw ciphertext which happened to look like text.
+    pub fn decode_detected(data: &[u8]) -> Vec<u8> {
+        Self::detect(data)
+            .decode(data)
+            .unwrap_or_else(|_| data.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CIPHERTEXT: &[u8] = &[0xff, 0x00, 0x13, 0x37, 0xca, 0xfe, 0x10, 0x20, 0x30];
+
+    #[test]
+    fn detect_each_format() {
+        for format in CiphertextFormat::ALL {
+            let encoded = format.encode(CIPHERTEXT);
+            assert_eq!(CiphertextFormat::detect(&encoded), format);
+            assert_eq!(CiphertextFormat::decode_detected(&encoded), CIPHERTEXT);
+        }
+    }
+
+    #[test]
+    fn misdetection_falls_back_to_raw() {
+        // This looks like a PEM block but the body is not base64.
+        let data = format!("{PEM_BEGIN}\n!!!!\n{PEM_END}\n").into_bytes();
+        assert_eq!(CiphertextFormat::detect(&data), CiphertextFormat::Pem);
+        assert_eq!(CiphertextFormat::decode_detected(&data), data);
+    }
+
+    #[test]
+    fn pem_lines_are_wrapped() {
+        let encoded = CiphertextFormat::Pem.encode(&[0xab; 100]);
+        let encoded = String::from_utf8(encoded).expect("PEM is not utf8");
+        assert!(encoded.lines().all(|line| line.len() <= PEM_LINE_WIDTH));
+    }
+
+    #[test]
+    fn base64_decodes_either_alphabet() {
+        let standard = CiphertextFormat::Base64.encode(CIPHERTEXT);
+        let url_safe = CiphertextFormat::Base64Url.encode(CIPHERTEXT);
+        assert_eq!(url_safe, b"_wATN8r-ECAw");
+        for format in [CiphertextFormat::Base64, CiphertextFormat::Base64Url] {
+            assert_eq!(format.decode(&standard).unwrap(), CIPHERTEXT);
+            assert_eq!(format.decode(&url_safe).unwrap(), CIPHERTEXT);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! A rough estimate of how guessable a `--key` is, to point out weak keys.
+
+/// Keys estimated below this many bits are reported as weak.
+pub const LOW_ENTROPY_BITS: f64 = 64.0;
+
+// Each class of character found in the key widens the pool every character is assumed to be
+// drawn from.
+type CharacterClass = (fn(&char) -> bool, f64);
+const CHARACTER_CLASSES: [CharacterClass; 4] = [
+    (char::is_ascii_lowercase, 26.0),
+    (char::is_ascii_uppercase, 26.0),
+    (char::is_ascii_digit, 10.0),
+    (char::is_ascii_punctuation, 33.0),
+];
+// Spaces and anything outside ascii.
+const OTHER_CHARACTERS: f64 = 64.0;
+
+/// Estimates the entropy of `key` in bits. Every character is given the smaller of the bits of
+/// the pool of its character classes and the Shannon entropy of the characters in the key, so
+/// long keys from a wide alphabet score high and repetitive ones do not. This is a heuristic for
+/// warnings only and is capped at the 256 bits of the key it is derived into.
+pub fn estimate_entropy_bits(key: &str) -> f64 {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.is_empty() {
+        return 0.0;
+    }
+    let mut pool: f64 = CHARACTER_CLASSES
+        .iter()
+        .filter(|(class, _)| chars.iter().any(class))
+        .map(|(_, size)| size)
+        .sum();
+    if chars
+        .iter()
+        .any(|c| !CHARACTER_CLASSES.iter().any(|(class, _)| class(c)))
+    {
+        pool += OTHER_CHARACTERS;
+    }
+
+    let mut counts = std::collections::HashMap::new();
+    for c in &chars {
+        *counts.entry(c).or_insert(0_usize) += 1;
+    }
+    let length = chars.len() as f64;
+    let shannon: f64 = counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / length;
+            -p * p.log2()
+        })
+        .sum();
+    (length * pool.log2().min(shannon)).min(256.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{distributions::Alphanumeric, Rng};
+
+    #[test]
+    fn weak_keys_estimate_low() {
+        for key in ["password", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa", "hunter", ""] {
+            let bits = estimate_entropy_bits(key);
+            assert!(bits < LOW_ENTROPY_BITS, "{key:?} {bits}");
+        }
+    }
+
+    #[test]
+    fn random_keys_estimate_high() {
+        let key: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        let bits = estimate_entropy_bits(&key);
+        assert!(bits >= 128.0, "{bits}");
+    }
+}
@@ -0,0 +1,153 @@
+//! Encrypting environment variables into a file that decrypts to sourceable `export` lines.
+
+use crate::{CommonEncryptionOpts, SimpleCipherError};
+
+/// A single `NAME=VALUE` environment variable.
+pub type EnvVar = (String, String);
+
+// Names end up unquoted in `export` lines so they are restricted to what a shell accepts.
+fn validate_name(name: &str) -> Result<(), SimpleCipherError> {
+    let mut chars = name.chars();
+    let valid_start = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    if valid_start && chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Ok(())
+    } else {
+        Err(SimpleCipherError::InvalidEnvName(name.to_string()))
+    }
+}
+
+/// Parses a single `NAME=VALUE` assignment as given on the command line.
+pub fn parse_assignment(assignment: &str) -> Result<EnvVar, SimpleCipherError> {
+    let (name, value) = assignment
+        .split_once('=')
+        .ok_or_else(|| SimpleCipherError::InvalidEnvName(assignment.to_string()))?;
+    validate_name(name)?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Parses the contents of a dotenv file. Blank lines and `#` comments are skipped, an
+/// `export ` prefix is allowed and values may be wrapped in single or double quotes.
+pub fn parse_dotenv(contents: &str) -> Result<Vec<EnvVar>, SimpleCipherError> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+            let (name, value) = parse_assignment(line)?;
+            let value = value.trim();
+            let unquoted = ['"', '\'']
+                .iter()
+                .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+                .unwrap_or(value);
+            Ok((name, unquoted.to_string()))
+        })
+        .collect()
+}
+
+/// Quotes `value` for a POSIX shell by wrapping it in single quotes. Embedded single quotes are
+/// closed, escaped and reopened.
+pub fn shell_escape(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Formats `vars` as `export NAME='VALUE'` lines suitable for `eval`.
+pub fn export_lines(vars: &[EnvVar]) -> String {
+    vars.iter()
+        .map(|(name, value)| format!("export {name}={}\n", shell_escape(value)))
+        .collect()
+}
+
+impl CommonEncryptionOpts {
+    /// Encrypts `vars` to the encrypted file as a single structured message.
+    pub fn encrypt_env(&self, vars: &[EnvVar]) -> Result<Option<String>, SimpleCipherError> {
+        for (name, _) in vars {
+            validate_name(name)?;
+        }
+        self.encrypt(serde_json::to_string(vars)?)
+    }
+
+    /// Decrypts environment variables written by `encrypt_env`.
+    pub fn decrypt_env(&self) -> Result<Vec<EnvVar>, SimpleCipherError> {
+        let vars: Vec<EnvVar> = serde_json::from_slice(&self.decrypt()?)?;
+        for (name, _) in &vars {
+            validate_name(name)?;
+        }
+        Ok(vars)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NonceSource;
+
+    #[test]
+    fn parse_dotenv_file() {
+        let vars = parse_dotenv(
+            "# a comment\n\nPLAIN=value\nexport EXPORTED=1\nQUOTED=\"with spaces\"\nSINGLE='x y'\n",
+        )
+        .expect("Failed to parse dotenv");
+        assert_eq!(
+            vars,
+            vec![
+                ("PLAIN".to_string(), "value".to_string()),
+                ("EXPORTED".to_string(), "1".to_string()),
+                ("QUOTED".to_string(), "with spaces".to_string()),
+                ("SINGLE".to_string(), "x y".to_string()),
+            ]
+        );
+        assert!(parse_dotenv("NOT VALID=1\n").is_err());
+        assert!(parse_assignment("1ABC=1").is_err());
+    }
+
+    #[test]
+    fn encrypt_and_decrypt_env() {
+        let vars = vec![
+            ("SIMPLE".to_string(), "value".to_string()),
+            ("SPACES".to_string(), "a value with spaces".to_string()),
+            ("QUOTES".to_string(), r#"it's "quoted""#.to_string()),
+            (
+                "SHELL".to_string(),
+                "$(echo injected) `ls` $HOME; exit 1".to_string(),
+            ),
+        ];
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("env.dat"),
+            nonce: NonceSource::Provided("env nonce".to_string()),
+            ..Default::default()
+        };
+        let _ = opts.encrypt_env(&vars).expect("Failed to encrypt env");
+        let output = opts.decrypt_env().expect("Failed to decrypt env");
+        assert_eq!(output, vars);
+    }
+
+    // Sourcing the export lines in a real shell yields the original values.
+    #[cfg(unix)]
+    #[test]
+    fn export_lines_are_sourceable() {
+        let vars = vec![
+            ("SPACES".to_string(), "a value with spaces".to_string()),
+            ("QUOTES".to_string(), r#"it's "quoted""#.to_string()),
+            (
+                "SHELL".to_string(),
+                "$(echo injected) `ls` $HOME; exit 1".to_string(),
+            ),
+        ];
+        let script = format!(
+            "{}printf '%s\\n' \"$SPACES\" \"$QUOTES\" \"$SHELL\"",
+            export_lines(&vars)
+        );
+        let printed = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(script)
+            .output()
+            .expect("Failed to run sh");
+        let expected: String = vars.iter().map(|(_, value)| format!("{value}\n")).collect();
+        assert_eq!(String::from_utf8_lossy(&printed.stdout), expected);
+    }
+}
@@ -0,0 +1,719 @@
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+use crate::{
+    kdf::{KeyId, KEY_ID_LENGTH},
+    stream::STREAM_MAGIC,
+    CiphertextFormat, Compression, KdfParams, SimpleCipherAlgorithm, SimpleCipherError,
+    NONCE_LENGTH,
+};
+
+/// Every one shot encrypted file written by this version starts with these bytes. Files without
+/// them are treated as bare ciphertext from before the header existed.
+pub const MAGIC: &[u8; 4] = b"SKE1";
+pub const VERSION: u8 = 2;
+// Headers of this version are not authenticated by the AEAD, so fields such as the compression
+// could be removed unnoticed. They are still decrypted, but nothing writes them any more.
+const UNAUTHENTICATED_VERSION: u8 = 1;
+
+// The header is the magic, the version and then a list of fields, each a one byte tag, a two
+// byte big endian length and the value, terminated by the end tag.
+const TAG_END: u8 = 0;
+const TAG_CHECKSUM: u8 = 1;
+const TAG_KDF: u8 = 2;
+const TAG_KEY_ID: u8 = 3;
+const TAG_KEY_COMMITMENT: u8 = 4;
+const TAG_NONCE: u8 = 5;
+const TAG_ALGORITHM: u8 = 6;
+const TAG_COMPRESSION: u8 = 7;
+const KEY_COMMITMENT_LENGTH: usize = blake3::OUT_LEN;
+
+/// The length and CRC32 of the stored ciphertext. This is checked before the AEAD so a
+/// truncated or damaged file is reported as such rather than as a wrong key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checksum {
+    pub length: u64,
+    pub crc32: u32,
+}
+
+impl Checksum {
+    pub fn of(ciphertext: &[u8]) -> Self {
+        Self {
+            length: ciphertext.len() as u64,
+            crc32: crc32fast::hash(ciphertext),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; 12] {
+        let mut bytes = [0_u8; 12];
+        bytes[..8].copy_from_slice(&self.length.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.crc32.to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, SimpleCipherError> {
+        if bytes.len() != 12 {
+            return Err(SimpleCipherError::HeaderMalformed);
+        }
+        Ok(Self {
+            length: u64::from_be_bytes(bytes[..8].try_into().expect("8 bytes")),
+            crc32: u32::from_be_bytes(bytes[8..].try_into().expect("4 bytes")),
+        })
+    }
+}
+
+/// A header and the ciphertext it describes.
+pub type Segment = (Header, Vec<u8>);
+
+/// The metadata written in front of a one shot ciphertext.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// The version of the header format. From version 2 the AEAD authenticates every field but
+    /// the checksum and key id, so they cannot be changed or removed without decryption failing.
+    pub version: u8,
+    pub checksum: Option<Checksum>,
+    /// How the key was derived from the key string. Files without this field used `--kdf none`.
+    pub kdf: KdfParams,
+    /// The fingerprint of the key the ciphertext was encrypted under, if it was recorded.
+    pub key_id: Option<KeyId>,
+    /// A commitment to the key, written with `--key-committing` and checked on decryption.
+    pub key_commitment: Option<[u8; KEY_COMMITMENT_LENGTH]>,
+    /// The nonce the ciphertext was encrypted under, embedded when it was generated so
+    /// decryption needs no nonce option.
+    pub nonce: Option<[u8; NONCE_LENGTH]>,
+    /// The AEAD the ciphertext was encrypted with, which also fixes the length of its nonce.
+    /// Files without this field used XChaCha20Poly1305.
+    pub algorithm: SimpleCipherAlgorithm,
+    /// How the message was compressed before it was encrypted. Files without this field were
+    /// not compressed.
+    pub compression: Compression,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self {
+            version: VERSION,
+            checksum: None,
+            kdf: KdfParams::None,
+            key_id: None,
+            key_commitment: None,
+            nonce: None,
+            algorithm: SimpleCipherAlgorithm::default(),
+            compression: Compression::None,
+        }
+    }
+}
+
+impl Header {
+    /// Builds the header for `ciphertext`.
+    pub fn for_ciphertext(ciphertext: &[u8]) -> Self {
+        Self {
+            checksum: Some(Checksum::of(ciphertext)),
+            ..Self::default()
+        }
+    }
+
+    /// The associated data the AEAD authenticates for the ciphertext after this header: the
+    /// header without its checksum, which is computed from the ciphertext, or its key id, which
+    /// `minimized` removes, followed by `aad`. Version 1 headers only authenticate `aad`.
+    pub(crate) fn associated_data(&self, aad: &[u8]) -> Vec<u8> {
+        if self.version == UNAUTHENTICATED_VERSION {
+            return aad.to_vec();
+        }
+        let mut bytes = Self {
+            checksum: None,
+            key_id: None,
+            ..self.clone()
+        }
+        .to_bytes();
+        bytes.extend_from_slice(aad);
+        bytes
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // This is room for every field, so building the header allocates once.
+        let mut bytes = Vec::with_capacity(128);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(self.version);
+        if let Some(checksum) = self.checksum {
+            push_field(&mut bytes, TAG_CHECKSUM, &checksum.to_bytes());
+        }
+        if self.kdf != KdfParams::None {
+            push_field(&mut bytes, TAG_KDF, &self.kdf.to_bytes());
+        }
+        if let Some(key_id) = self.key_id {
+            push_field(&mut bytes, TAG_KEY_ID, &key_id);
+        }
+        if let Some(commitment) = self.key_commitment {
+            push_field(&mut bytes, TAG_KEY_COMMITMENT, &commitment);
+        }
+        if let Some(nonce) = self.nonce {
+            push_field(&mut bytes, TAG_NONCE, &nonce);
+        }
+        if self.algorithm != SimpleCipherAlgorithm::default() {
+            push_field(&mut bytes, TAG_ALGORITHM, &[self.algorithm.id()]);
+        }
+        if self.compression != Compression::None {
+            push_field(&mut bytes, TAG_COMPRESSION, &[self.compression.id()]);
+        }
+        bytes.push(TAG_END);
+        bytes
+    }
+
+    /// Splits `contents` into its header and the ciphertext that follows. Contents without the
+    /// magic have no header and are returned whole.
+    pub fn parse(contents: &[u8]) -> Result<(Option<Self>, &[u8]), SimpleCipherError> {
+        if !contents.starts_with(MAGIC.as_slice()) {
+            return Ok((None, contents));
+        }
+        match Self::parse_prefix(contents)? {
+            Some((header, length)) => Ok((Some(header), &contents[length..])),
+            None => Err(SimpleCipherError::HeaderMalformed),
+        }
+    }
+
+    /// The length of the header and ciphertext at the start of `contents`, for contents holding
+    /// several of them back to back. This is `None` while `contents` ends before the header does.
+    pub fn segment_length(contents: &[u8]) -> Result<Option<usize>, SimpleCipherError> {
+        let Some((header, length)) = Self::parse_prefix(contents)? else {
+            return Ok(None);
+        };
+        let checksum = header.checksum.ok_or(SimpleCipherError::HeaderMalformed)?;
+        let ciphertext_length =
+            usize::try_from(checksum.length).map_err(|_| SimpleCipherError::HeaderMalformed)?;
+        Ok(Some(length + ciphertext_length))
+    }
+
+    // This parses the header at the start of `contents` and returns it with its length, or
+    // `None` if `contents` ends part way through it.
+    fn parse_prefix(contents: &[u8]) -> Result<Option<(Self, usize)>, SimpleCipherError> {
+        let prefix = &contents[..contents.len().min(MAGIC.len())];
+        if !MAGIC.starts_with(prefix) {
+            return Err(SimpleCipherError::HeaderMalformed);
+        }
+        let Some((&version, mut rest)) = contents[prefix.len()..].split_first() else {
+            return Ok(None);
+        };
+        if version != VERSION && version != UNAUTHENTICATED_VERSION {
+            return Err(SimpleCipherError::UnsupportedVersion(version));
+        }
+        let mut header = Self {
+            version,
+            ..Self::default()
+        };
+        loop {
+            let Some((&tag, after_tag)) = rest.split_first() else {
+                return Ok(None);
+            };
+            if tag == TAG_END {
+                return Ok(Some((header, contents.len() - after_tag.len())));
+            }
+            if after_tag.len() < 2 {
+                return Ok(None);
+            }
+            let length = u16::from_be_bytes([after_tag[0], after_tag[1]]) as usize;
+            let Some(value) = after_tag.get(2..2 + length) else {
+                return Ok(None);
+            };
+            match tag {
+                TAG_CHECKSUM => header.checksum = Some(Checksum::from_bytes(value)?),
+                TAG_KDF => header.kdf = KdfParams::from_bytes(value)?,
+                TAG_KEY_ID if value.len() == KEY_ID_LENGTH => {
+                    header.key_id = Some(value.try_into().expect("key id length"))
+                }
+                TAG_KEY_COMMITMENT if value.len() == KEY_COMMITMENT_LENGTH => {
+                    header.key_commitment = Some(value.try_into().expect("commitment length"))
+                }
+                TAG_NONCE if value.len() == NONCE_LENGTH => {
+                    header.nonce = Some(value.try_into().expect("nonce length"))
+                }
+                TAG_ALGORITHM if value.len() == 1 => {
+                    header.algorithm = SimpleCipherAlgorithm::from_id(value[0])?
+                }
+                TAG_COMPRESSION if value.len() == 1 => {
+                    header.compression = Compression::from_id(value[0])?
+                }
+                TAG_KEY_ID | TAG_KEY_COMMITMENT | TAG_NONCE | TAG_ALGORITHM | TAG_COMPRESSION => {
+                    return Err(SimpleCipherError::HeaderMalformed)
+                }
+                tag => return Err(SimpleCipherError::UnknownHeaderField(tag)),
+            }
+            rest = &after_tag[2 + length..];
+        }
+    }
+
+    /// This header without the metadata which decryption does not need. The checksum, KDF
+    /// parameters, key commitment, nonce, algorithm and compression are kept as decryption
+    /// depends on or checks them.
+    pub fn minimized(&self) -> Self {
+        Self {
+            key_id: None,
+            ..self.clone()
+        }
+    }
+
+    /// The fields of this header as names and displayable values, for `info`. The metadata
+    /// `minimized` removes is marked as such.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = Vec::new();
+        if let Some(checksum) = self.checksum {
+            fields.push((
+                "checksum",
+                format!("{} bytes, crc32 {:08x}", checksum.length, checksum.crc32),
+            ));
+        }
+        fields.push(("algorithm", self.algorithm.to_string()));
+        if self.compression != Compression::None {
+            fields.push((
+                "compression",
+                format!("{:?}", self.compression).to_lowercase(),
+            ));
+        }
+        fields.push(("kdf", format!("{:?}", self.kdf)));
+        if let Some(commitment) = self.key_commitment {
+            fields.push(("key commitment", hex::encode(commitment)));
+        }
+        if let Some(nonce) = self.nonce {
+            fields.push(("nonce", hex::encode(nonce)));
+        }
+        if let Some(key_id) = self.key_id {
+            fields.push(("key id (metadata)", hex::encode(key_id)));
+        }
+        fields
+    }
+
+    /// The fields of this header as a JSON object, for `decrypt --header-only`. Byte values are
+    /// lower case hex and absent fields are `null`.
+    pub fn to_json(&self) -> Value {
+        let kdf = match self.kdf {
+            KdfParams::None => json!({ "name": "none" }),
+            KdfParams::Hash(algorithm) => json!({
+                "name": "hash",
+                "hash": algorithm.to_possible_value().map(|value| value.get_name().to_string()),
+            }),
+            KdfParams::Argon2 {
+                salt,
+                memory,
+                iterations,
+                parallelism,
+            } => json!({
+                "name": "argon2",
+                "salt": hex::encode(salt),
+                "memory_kib": memory,
+                "iterations": iterations,
+                "parallelism": parallelism,
+            }),
+            KdfParams::Scrypt { salt, log_n, r, p } => json!({
+                "name": "scrypt",
+                "salt": hex::encode(salt),
+                "log_n": log_n,
+                "r": r,
+                "p": p,
+            }),
+            KdfParams::Pbkdf2 { salt, iterations } => json!({
+                "name": "pbkdf2",
+                "salt": hex::encode(salt),
+                "iterations": iterations,
+            }),
+        };
+        json!({
+            "ciphertext_length": self.checksum.map(|checksum| checksum.length),
+            "crc32": self.checksum.map(|checksum| format!("{:08x}", checksum.crc32)),
+            "algorithm": self.algorithm,
+            "nonce_length": self.algorithm.nonce_length(),
+            "compression": self.compression,
+            "kdf": kdf,
+            "key_id": self.key_id.map(hex::encode),
+            "key_commitment": self.key_commitment.map(hex::encode),
+            "nonce": self
+                .nonce
+                .map(|nonce| hex::encode(&nonce[..self.algorithm.nonce_length()])),
+        })
+    }
+
+    /// Parses every header of the encrypted file `contents`, in whichever format it was written,
+    /// along with the ciphertext following each. Streamed files and files from before the header
+    /// existed have none.
+    pub fn parse_all(
+        contents: &[u8],
+    ) -> Result<(CiphertextFormat, Vec<Segment>), SimpleCipherError> {
+        let format = if contents.starts_with(MAGIC.as_slice()) || contents.starts_with(STREAM_MAGIC)
+        {
+            CiphertextFormat::Raw
+        } else {
+            CiphertextFormat::detect(contents)
+        };
+        let decoded = format.decode(contents)?;
+        let mut segments = Vec::new();
+        let mut rest = decoded.as_slice();
+        while rest.starts_with(MAGIC.as_slice()) {
+            let (header, length) =
+                Self::parse_prefix(rest)?.ok_or(SimpleCipherError::HeaderMalformed)?;
+            // Only the segments of a log need a checksum to find the next header.
+            let end = match header.checksum {
+                Some(checksum) => usize::try_from(checksum.length)
+                    .ok()
+                    .and_then(|ciphertext| length.checked_add(ciphertext))
+                    .filter(|end| *end <= rest.len())
+                    .ok_or(SimpleCipherError::HeaderMalformed)?,
+                None => rest.len(),
+            };
+            segments.push((header, rest[length..end].to_vec()));
+            rest = &rest[end..];
+        }
+        if !segments.is_empty() && !rest.is_empty() {
+            return Err(SimpleCipherError::HeaderMalformed);
+        }
+        Ok((format, segments))
+    }
+
+    /// Checks `ciphertext` against the recorded checksum, if any.
+    pub fn verify(&self, ciphertext: &[u8]) -> Result<(), SimpleCipherError> {
+        match self.checksum {
+            Some(checksum) if checksum != Checksum::of(ciphertext) => {
+                Err(SimpleCipherError::Corrupted {
+                    expected: checksum.length,
+                    actual: ciphertext.len() as u64,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether `data` is the contents of a file written by this crate, in any format. Files written
+/// before the header existed are not recognised.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    let starts_with_magic =
+        |data: &[u8]| data.starts_with(MAGIC.as_slice()) || data.starts_with(STREAM_MAGIC);
+    starts_with_magic(data) || starts_with_magic(&CiphertextFormat::decode_detected(data))
+}
+
+/// Describes the encrypted file `contents` as JSON without decrypting it, so no key is needed:
+/// its format, whether it was streamed, the algorithm and the header of every segment.
+pub fn header_json(contents: &[u8]) -> Result<Value, SimpleCipherError> {
+    let (format, segments) = Header::parse_all(contents)?;
+    let segments: Vec<Value> = segments
+        .iter()
+        .map(|(header, _)| header.to_json())
+        .collect();
+    Ok(json!({
+        "format": format.name(),
+        "streamed": contents.starts_with(STREAM_MAGIC),
+        // Every segment of a log is encrypted with the same algorithm.
+        "algorithm": segments
+            .first()
+            .map(|segment| segment["algorithm"].clone())
+            .unwrap_or(json!(SimpleCipherAlgorithm::default())),
+        "segments": segments,
+    }))
+}
+
+/// Rewrites the encrypted file `contents` with the metadata of every header removed, keeping its
+/// format and everything needed to decrypt it. Files without a header are returned as they are.
+pub fn minimize_headers(contents: &[u8]) -> Result<Vec<u8>, SimpleCipherError> {
+    let (format, segments) = Header::parse_all(contents)?;
+    if segments.is_empty() {
+        return Ok(contents.to_vec());
+    }
+    let mut minimized = Vec::with_capacity(contents.len());
+    for (header, ciphertext) in segments {
+        minimized.extend_from_slice(&header.minimized().to_bytes());
+        minimized.extend_from_slice(&ciphertext);
+    }
+    Ok(format.encode(&minimized))
+}
+
+fn push_field(bytes: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    let length = u16::try_from(value.len()).expect("header fields are short");
+    bytes.push(tag);
+    bytes.extend_from_slice(&length.to_be_bytes());
+    bytes.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_round_trip() {
+        let ciphertext = b"not really a ciphertext";
+        let mut contents = Header::for_ciphertext(ciphertext).to_bytes();
+        contents.extend_from_slice(ciphertext);
+
+        let (header, body) = Header::parse(&contents).expect("Failed to parse header");
+        assert_eq!(header, Some(Header::for_ciphertext(ciphertext)));
+        assert_eq!(body, ciphertext);
+        header.unwrap().verify(body).expect("Checksum mismatch");
+    }
+
+    #[test]
+    fn header_records_kdf_and_key_id() {
+        let header = Header {
+            kdf: KdfParams::Hash(crate::KeyHashAlgorithm::Sha512),
+            key_id: Some([9; KEY_ID_LENGTH]),
+            key_commitment: Some([3; KEY_COMMITMENT_LENGTH]),
+            ..Header::for_ciphertext(b"ciphertext")
+        };
+        let contents = header.to_bytes();
+        let (parsed, _) = Header::parse(&contents).expect("Failed to parse header");
+        assert_eq!(parsed, Some(header));
+    }
+
+    #[test]
+    fn salted_kdf_params_round_trip() {
+        use rand::rngs::OsRng;
+
+        for kdf in [
+            KdfParams::argon2(64, 1, &mut OsRng),
+            KdfParams::scrypt(4, 8, 1, &mut OsRng),
+            KdfParams::pbkdf2(crate::kdf::DEFAULT_PBKDF2_ITERATIONS, &mut OsRng),
+        ] {
+            let header = Header {
+                kdf,
+                ..Header::for_ciphertext(b"ciphertext")
+            };
+            let (parsed, _) = Header::parse(&header.to_bytes()).expect("Failed to parse header");
+            assert_eq!(parsed, Some(header));
+        }
+    }
+
+    // A file whose magic is damaged is refused, or under --legacy read as bare ciphertext, which
+    // then fails to authenticate rather than decrypting to anything.
+    #[test]
+    fn corrupted_magic_fails_to_decrypt() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = crate::CommonEncryptionOpts {
+            key: Some("correct horse battery staple".to_string().into()),
+            encrypted_file: tmpdir.path().join("encrypted.dat"),
+            nonce: crate::NonceSource::Provided("magic nonce".to_string()),
+            kdf: crate::Kdf::Argon2,
+            argon2_memory: Some(64),
+            argon2_iterations: Some(1),
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        let mut contents = std::fs::read(&opts.encrypted_file).unwrap();
+        contents[0] ^= 0x20;
+        assert_eq!(Header::parse(&contents).unwrap().0, None);
+        std::fs::write(&opts.encrypted_file, &contents).unwrap();
+        assert!(matches!(opts.decrypt(), Err(SimpleCipherError::BadMagic)));
+
+        let legacy = crate::CommonEncryptionOpts {
+            legacy: true,
+            ..opts.clone()
+        };
+        assert!(legacy.decrypt().unwrap_err().is_auth_failure());
+        contents[0] ^= 0x20;
+        std::fs::write(&opts.encrypted_file, &contents).unwrap();
+        for opts in [opts, legacy] {
+            assert_eq!(opts.decrypt().expect("Failed to decrypt data"), b"foobar");
+        }
+    }
+
+    #[test]
+    fn newer_version_is_refused() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = crate::CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encrypted.dat"),
+            nonce: crate::NonceSource::Provided("version nonce".to_string()),
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        let mut contents = std::fs::read(&opts.encrypted_file).unwrap();
+        assert_eq!(contents[MAGIC.len()], VERSION);
+        contents[MAGIC.len()] = VERSION + 1;
+        std::fs::write(&opts.encrypted_file, &contents).unwrap();
+        for legacy in [false, true] {
+            let opts = crate::CommonEncryptionOpts {
+                legacy,
+                ..opts.clone()
+            };
+            assert!(matches!(
+                opts.decrypt(),
+                Err(SimpleCipherError::UnsupportedVersion(version)) if version == VERSION + 1
+            ));
+        }
+    }
+
+    #[test]
+    fn segment_length_waits_for_a_complete_header() {
+        let ciphertext = b"not really a ciphertext";
+        let mut contents = Header::for_ciphertext(ciphertext).to_bytes();
+        let header_length = contents.len();
+        contents.extend_from_slice(ciphertext);
+        for end in 0..header_length {
+            assert_eq!(Header::segment_length(&contents[..end]).unwrap(), None);
+        }
+        let length = Some(header_length + ciphertext.len());
+        assert_eq!(
+            Header::segment_length(&contents[..header_length]).unwrap(),
+            length
+        );
+        assert_eq!(Header::segment_length(&contents).unwrap(), length);
+        assert!(Header::segment_length(b"SKX").is_err());
+    }
+
+    #[test]
+    fn no_header() {
+        let (header, body) = Header::parse(b"bare").expect("Failed to parse header");
+        assert_eq!(header, None);
+        assert_eq!(body, b"bare");
+    }
+
+    #[test]
+    fn truncated_ciphertext_fails_checksum() {
+        let ciphertext = b"not really a ciphertext";
+        let header = Header::for_ciphertext(ciphertext);
+        let out = header.verify(&ciphertext[..10]);
+        assert_eq!(
+            format!("{:?}", out.unwrap_err()),
+            format!(
+                "{:?}",
+                SimpleCipherError::Corrupted {
+                    expected: ciphertext.len() as u64,
+                    actual: 10
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn malformed_header() {
+        let mut contents = MAGIC.to_vec();
+        assert!(Header::parse(&contents).is_err());
+        contents.push(VERSION + 1);
+        assert_eq!(
+            format!("{:?}", Header::parse(&contents).unwrap_err()),
+            format!("{:?}", SimpleCipherError::UnsupportedVersion(VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn header_fields_are_authenticated() {
+        use crate::{frame, Cipher};
+        use chacha20poly1305::{aead::Payload, Key, XNonce};
+
+        let key = Key::from([0x42; 32]);
+        let nonce = XNonce::from([0x24; 24]);
+        let cipher = Cipher::new(key).with_compression(Compression::Gzip);
+        let contents = cipher
+            .encrypt_bytes(&nonce, b"foobar")
+            .expect("Failed to encrypt");
+        let (header, ciphertext) = Header::parse(&contents).unwrap();
+        let header = header.unwrap();
+        let stripped = [
+            Header {
+                compression: Compression::None,
+                ..header.clone()
+            },
+            Header {
+                version: UNAUTHENTICATED_VERSION,
+                ..header.clone()
+            },
+            Header {
+                key_commitment: Some([0; KEY_COMMITMENT_LENGTH]),
+                ..header.clone()
+            },
+        ];
+        for tampered in stripped {
+            let error = cipher
+                .decrypt_bytes(&nonce, &frame(ciphertext, &tampered))
+                .unwrap_err();
+            assert!(
+                matches!(
+                    error,
+                    SimpleCipherError::AuthenticationFailed
+                        | SimpleCipherError::KeyCommitmentMismatch
+                ),
+                "{tampered:?} {error:?}"
+            );
+        }
+        // The key id is metadata which may be removed.
+        let minimized = frame(ciphertext, &header.minimized());
+        assert_eq!(cipher.decrypt_bytes(&nonce, &minimized).unwrap(), b"foobar");
+
+        // Files written before the header was authenticated still decrypt.
+        let ciphertext = crate::SimpleCipherAlgorithm::default()
+            .encrypt(
+                &key,
+                &nonce,
+                Payload {
+                    msg: b"foobar",
+                    aad: b"",
+                },
+            )
+            .unwrap();
+        let legacy = Header {
+            version: UNAUTHENTICATED_VERSION,
+            ..Header::default()
+        };
+        let contents = frame(&ciphertext, &legacy);
+        assert_eq!(contents[MAGIC.len()], UNAUTHENTICATED_VERSION);
+        assert_eq!(
+            Cipher::new(key).decrypt_bytes(&nonce, &contents).unwrap(),
+            b"foobar"
+        );
+    }
+
+    #[test]
+    fn minimized_files_still_decrypt() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        for format in [CiphertextFormat::Raw, CiphertextFormat::Pem] {
+            let opts = crate::CommonEncryptionOpts {
+                key: Some("baz".to_string().into()),
+                encrypted_file: tmpdir.path().join("encrypted.dat"),
+                nonce: crate::NonceSource::Provided("minimized nonce".to_string()),
+                format: Some(format),
+                force: true,
+                ..Default::default()
+            };
+            let _ = opts
+                .encrypt("foobar".to_string())
+                .expect("Failed to encrypt data");
+            let contents = std::fs::read(&opts.encrypted_file).unwrap();
+            let (_, segments) = Header::parse_all(&contents).unwrap();
+            assert!(segments[0].0.key_id.is_some());
+
+            let minimized = minimize_headers(&contents).expect("Failed to minimize header");
+            std::fs::write(&opts.encrypted_file, &minimized).unwrap();
+            assert_eq!(opts.decrypt().expect("Failed to decrypt data"), b"foobar");
+            let (detected, segments) = Header::parse_all(&minimized).unwrap();
+            assert_eq!(detected, format);
+            let fields = segments[0].0.fields();
+            assert!(fields.iter().all(|(name, _)| !name.contains("metadata")));
+            assert!(fields.iter().any(|(name, _)| *name == "checksum"));
+        }
+    }
+
+    #[test]
+    fn header_json_needs_no_key() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = crate::CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encrypted.dat"),
+            nonce: crate::NonceSource::Provided("header nonce".to_string()),
+            kdf: crate::Kdf::Hash,
+            format: Some(CiphertextFormat::Hex),
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        let contents = std::fs::read(&opts.encrypted_file).unwrap();
+
+        let described = header_json(&contents).expect("Failed to describe header");
+        assert_eq!(described["format"], "hex");
+        assert_eq!(described["streamed"], false);
+        assert_eq!(described["algorithm"], "xchacha20poly1305");
+        let segment = &described["segments"][0];
+        assert_eq!(segment["ciphertext_length"], "foobar".len() + 16);
+        assert_eq!(segment["kdf"], json!({ "name": "hash", "hash": "sha256" }));
+        assert_eq!(segment["key_commitment"], Value::Null);
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    fs,
+    io::{self, BufRead},
+    path::Path,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+use zeroize::Zeroizing;
+
+use crate::SimpleCipherError;
+
+/// Reads the whole of `path`. With a timeout the read happens on a helper thread so a FIFO or
+/// device whose writer never shows up, or never closes, returns
+/// `SimpleCipherError::InputTimeout` rather than blocking forever.
+pub fn read_input(path: &Path, timeout: Option<Duration>) -> Result<Vec<u8>, SimpleCipherError> {
+    let Some(timeout) = timeout else {
+        return Ok(fs::read(path)?);
+    };
+    let (sender, receiver) = mpsc::channel();
+    let path = path.to_path_buf();
+    // Opening a FIFO blocks until there is a writer so the open has to happen on the helper
+    // thread as well. A stalled thread is left behind on timeout as there is no portable way to
+    // cancel a blocking read.
+    thread::spawn(move || {
+        let _ = sender.send(fs::read(path));
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(contents) => Ok(contents?),
+        Err(_) => Err(SimpleCipherError::InputTimeout(timeout)),
+    }
+}
+
+/// Reads a line from `reader`, such as the answer to a prompt, without its line ending. With a
+/// timeout an unattended run which triggers the prompt fails with
+/// `SimpleCipherError::InputTimeout` instead of waiting for an answer which never comes.
+pub(crate) fn read_line<R: BufRead + Send + 'static>(
+    reader: R,
+    timeout: Option<Duration>,
+) -> Result<String, SimpleCipherError> {
+    let read = move || {
+        let mut reader = reader;
+        let mut line = String::new();
+        reader
+            .read_line(&mut line)
+            .map(|_| line.trim_end().to_string())
+    };
+    let Some(timeout) = timeout else {
+        return Ok(read()?);
+    };
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(read());
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(line) => Ok(line?),
+        Err(_) => Err(SimpleCipherError::InputTimeout(timeout)),
+    }
+}
+
+/// Asks for a passphrase with `ask`, which shows its prompt and reads the answer, such as
+/// `rpassword::prompt_password` reading the terminal without echo. With `confirm` the passphrase
+/// is asked for twice and `SimpleCipherError::PassphraseMismatch` returned unless both match, so
+/// a typo does not encrypt under a passphrase nobody knows. With a timeout each prompt which is
+/// not answered in time fails with `SimpleCipherError::InputTimeout`, like `read_line`.
+pub(crate) fn read_passphrase<F>(
+    mut ask: F,
+    confirm: bool,
+    timeout: Option<Duration>,
+) -> Result<Zeroizing<String>, SimpleCipherError>
+where
+    F: FnMut(&str) -> io::Result<String> + Send + 'static,
+{
+    let prompts: &'static [&'static str] = if confirm {
+        &["Passphrase: ", "Confirm passphrase: "]
+    } else {
+        &["Passphrase: "]
+    };
+    let Some(timeout) = timeout else {
+        let passphrase = Zeroizing::new(ask(prompts[0])?);
+        if confirm && *passphrase != *Zeroizing::new(ask(prompts[1])?) {
+            return Err(SimpleCipherError::PassphraseMismatch);
+        }
+        return Ok(passphrase);
+    };
+    // The prompts are asked on a helper thread which sends each answer as it is read. As with
+    // `read_input` a stalled thread is left behind on timeout, and the terminal may be left
+    // without echo as the process exits.
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for prompt in prompts {
+            let answer = ask(prompt).map(Zeroizing::new);
+            let failed = answer.is_err();
+            if sender.send(answer).is_err() || failed {
+                break;
+            }
+        }
+    });
+    let answer = || match receiver.recv_timeout(timeout) {
+        Ok(answer) => Ok(answer?),
+        Err(_) => Err(SimpleCipherError::InputTimeout(timeout)),
+    };
+    let passphrase = answer()?;
+    if confirm && *passphrase != *answer()? {
+        return Err(SimpleCipherError::PassphraseMismatch);
+    }
+    Ok(passphrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn read_input_with_timeout() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let path = tmpdir.path().join("message.txt");
+        fs::write(&path, b"foobar").expect("Failed to write message");
+        let contents =
+            read_input(&path, Some(Duration::from_secs(5))).expect("Failed to read message");
+        assert_eq!(contents, b"foobar");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fifo_without_writer_times_out() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let fifo = tmpdir.path().join("message.fifo");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .expect("Failed to run mkfifo");
+        assert!(status.success());
+
+        let timeout = Duration::from_millis(100);
+        let out = read_input(&fifo, Some(timeout));
+        assert_eq!(
+            format!("{:?}", out.unwrap_err()),
+            format!("{:?}", SimpleCipherError::InputTimeout(timeout))
+        );
+    }
+
+    #[test]
+    fn unanswered_prompt_times_out() {
+        let timeout = Duration::from_millis(100);
+        let answer = read_line(io::Cursor::new("y\n"), Some(timeout)).expect("Failed to read");
+        assert_eq!(answer, "y");
+
+        // Nothing is ever written to the pipe while its writer stays open, like a prompt no one
+        // is there to answer.
+        let (reader, writer) = io::pipe().expect("Failed to create pipe");
+        let out = read_line(io::BufReader::new(reader), Some(timeout));
+        assert_eq!(
+            format!("{:?}", out.unwrap_err()),
+            format!("{:?}", SimpleCipherError::InputTimeout(timeout))
+        );
+        drop(writer);
+    }
+
+    #[test]
+    fn confirmed_passphrase_must_match() {
+        let ask_from = |input: &'static [u8]| {
+            let mut reader = io::Cursor::new(input);
+            // This stands in for the terminal, one typed line per prompt.
+            move |_: &str| {
+                let mut line = String::new();
+                reader
+                    .read_line(&mut line)
+                    .map(|_| line.trim_end().to_string())
+            }
+        };
+        for timeout in [None, Some(Duration::from_secs(5))] {
+            let passphrase = read_passphrase(ask_from(b"baz\nbaz\n"), true, timeout).unwrap();
+            assert_eq!(passphrase.as_str(), "baz");
+            assert!(matches!(
+                read_passphrase(ask_from(b"baz\nbax\n"), true, timeout),
+                Err(SimpleCipherError::PassphraseMismatch)
+            ));
+            // Decryption asks once, so a second line is never read.
+            let passphrase = read_passphrase(ask_from(b"baz\nbax\n"), false, timeout).unwrap();
+            assert_eq!(passphrase.as_str(), "baz");
+        }
+    }
+
+    #[test]
+    fn unanswered_passphrase_prompt_times_out() {
+        let timeout = Duration::from_millis(100);
+        // Only the first prompt is answered while the writer stays open, so the key prompt is
+        // answered in time and without confirmation, but the confirmation prompt never is.
+        let ask_from = |answers: &[u8]| {
+            let (reader, mut writer) = io::pipe().expect("Failed to create pipe");
+            io::Write::write_all(&mut writer, answers).expect("Failed to write answers");
+            let mut reader = io::BufReader::new(reader);
+            let ask = move |_: &str| {
+                let mut line = String::new();
+                reader
+                    .read_line(&mut line)
+                    .map(|_| line.trim_end().to_string())
+            };
+            (ask, writer)
+        };
+        let (ask, writer) = ask_from(b"baz\n");
+        let passphrase = read_passphrase(ask, false, Some(timeout)).unwrap();
+        assert_eq!(passphrase.as_str(), "baz");
+        drop(writer);
+        for confirm in [false, true] {
+            let answers: &[u8] = if confirm { b"baz\n" } else { b"" };
+            let (ask, writer) = ask_from(answers);
+            assert_eq!(
+                format!(
+                    "{:?}",
+                    read_passphrase(ask, confirm, Some(timeout)).unwrap_err()
+                ),
+                format!("{:?}", SimpleCipherError::InputTimeout(timeout))
+            );
+            drop(writer);
+        }
+    }
+}
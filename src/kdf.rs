@@ -0,0 +1,591 @@
+use argon2::{Algorithm, Argon2, Block, Params, Version};
+use chacha20poly1305::Key;
+use clap::ValueEnum;
+use rand::RngCore;
+use sha2::{Digest, Sha256, Sha512};
+use std::num::NonZeroU32;
+use zeroize::Zeroizing;
+
+use crate::SimpleCipherError;
+
+pub const SALT_LENGTH: usize = 16;
+pub const DEFAULT_ARGON2_MEMORY: u32 = Params::DEFAULT_M_COST;
+pub const DEFAULT_ARGON2_ITERATIONS: u32 = Params::DEFAULT_T_COST;
+// N = 2^15 and r = 8 use 32 MiB, the interactive login parameters of the scrypt paper.
+pub const DEFAULT_SCRYPT_LOG_N: u8 = 15;
+pub const DEFAULT_SCRYPT_R: u32 = 8;
+pub const DEFAULT_SCRYPT_P: u32 = 1;
+// OWASP's recommendation for PBKDF2-HMAC-SHA256.
+pub const DEFAULT_PBKDF2_ITERATIONS: NonZeroU32 = NonZeroU32::new(600_000).unwrap();
+pub const KEY_ID_LENGTH: usize = 8;
+// These are the largest costs accepted from a header, which is not trusted until the key it
+// derives decrypts the file, so a crafted header cannot exhaust memory or hold the CPU for hours.
+// Each is far above its default, and encryption refuses more as the file could not be decrypted.
+pub const MAX_ARGON2_MEMORY: u32 = 2 * 1024 * 1024;
+pub const MAX_ARGON2_ITERATIONS: u32 = 32;
+pub const MAX_ARGON2_PARALLELISM: u32 = 64;
+// scrypt uses 128 * r * 2^log_n bytes, so this bounds log_n and r together.
+pub const MAX_SCRYPT_MEMORY: u64 = 2 << 30;
+pub const MAX_SCRYPT_P: u32 = 16;
+pub const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+
+/// A short identifier of a derived key, recorded in the header so the key a file was encrypted
+/// under can be recognised without trying it.
+pub type KeyId = [u8; KEY_ID_LENGTH];
+
+const KEY_COMMITMENT_MESSAGE: &[u8] = b"symmetric-key-exercise key commitment";
+
+/// How the `--key` string is turned into a 32 byte key.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Kdf {
+    /// The key is padded with zero bytes and must be at most 32 bytes long.
+    #[default]
+    None,
+    /// The key is hashed down to 32 bytes so it may be any length.
+    Hash,
+    /// The key is stretched with Argon2id and a random salt, for keys which are passphrases.
+    Argon2,
+    /// The key is stretched with scrypt and a random salt, for keys which are passphrases.
+    Scrypt,
+    /// The key is stretched with PBKDF2-HMAC-SHA256 and a random salt, for environments which
+    /// only allow FIPS approved algorithms.
+    Pbkdf2,
+}
+
+/// The hash used by `--kdf hash`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum KeyHashAlgorithm {
+    #[default]
+    Sha256,
+    /// SHA-512 truncated to its first 32 bytes.
+    Sha512,
+    Blake3,
+}
+
+impl KeyHashAlgorithm {
+    const ALL: [Self; 3] = [Self::Sha256, Self::Sha512, Self::Blake3];
+
+    fn id(self) -> u8 {
+        match self {
+            Self::Sha256 => 1,
+            Self::Sha512 => 2,
+            Self::Blake3 => 3,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|algorithm| algorithm.id() == id)
+    }
+
+    fn hash(self, key: &[u8]) -> Zeroizing<Key> {
+        Zeroizing::new(match self {
+            Self::Sha256 => Sha256::digest(key),
+            Self::Sha512 => *Key::from_slice(&Sha512::digest(key)[..32]),
+            Self::Blake3 => blake3::hash(key).as_bytes().to_owned().into(),
+        })
+    }
+}
+
+/// Everything needed to derive the same key from a key string again. This is recorded in the
+/// header of the encrypted file so decryption does not depend on repeating the options.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KdfParams {
+    #[default]
+    None,
+    Hash(KeyHashAlgorithm),
+    /// Argon2id with `memory` in KiB.
+    Argon2 {
+        salt: [u8; SALT_LENGTH],
+        memory: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+    /// scrypt with a cost of `2^log_n`, block size `r` and parallelism `p`.
+    Scrypt {
+        salt: [u8; SALT_LENGTH],
+        log_n: u8,
+        r: u32,
+        p: u32,
+    },
+    /// PBKDF2-HMAC-SHA256 with `iterations` rounds.
+    Pbkdf2 {
+        salt: [u8; SALT_LENGTH],
+        iterations: NonZeroU32,
+    },
+}
+
+const KDF_NONE: u8 = 0;
+const KDF_HASH: u8 = 1;
+const KDF_ARGON2: u8 = 2;
+const KDF_SCRYPT: u8 = 3;
+const KDF_PBKDF2: u8 = 4;
+
+fn random_salt(rng: &mut impl RngCore) -> [u8; SALT_LENGTH] {
+    let mut salt = [0_u8; SALT_LENGTH];
+    rng.fill_bytes(&mut salt);
+    salt
+}
+
+impl KdfParams {
+    /// Argon2id parameters with a fresh random salt drawn from `rng`.
+    pub fn argon2(memory: u32, iterations: u32, rng: &mut impl RngCore) -> Self {
+        Self::Argon2 {
+            salt: random_salt(rng),
+            memory,
+            iterations,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+
+    /// scrypt parameters with a fresh random salt drawn from `rng`.
+    pub fn scrypt(log_n: u8, r: u32, p: u32, rng: &mut impl RngCore) -> Self {
+        Self::Scrypt {
+            salt: random_salt(rng),
+            log_n,
+            r,
+            p,
+        }
+    }
+
+    /// PBKDF2-HMAC-SHA256 parameters with a fresh random salt drawn from `rng`.
+    pub fn pbkdf2(iterations: NonZeroU32, rng: &mut impl RngCore) -> Self {
+        Self::Pbkdf2 {
+            salt: random_salt(rng),
+            iterations,
+        }
+    }
+
+    /// Whether these parameters can only be reproduced from the header of an encrypted file.
+    pub fn needs_header(&self) -> bool {
+        matches!(
+            self,
+            Self::Argon2 { .. } | Self::Scrypt { .. } | Self::Pbkdf2 { .. }
+        )
+    }
+
+    // This refuses costs above the maximums, before anything is allocated for them.
+    pub(crate) fn check_limits(&self) -> Result<(), SimpleCipherError> {
+        let limits: &[(&'static str, u64, u64)] = match *self {
+            Self::None | Self::Hash(_) => &[],
+            Self::Argon2 {
+                memory,
+                iterations,
+                parallelism,
+                ..
+            } => &[
+                ("argon2 memory", memory.into(), MAX_ARGON2_MEMORY.into()),
+                (
+                    "argon2 iterations",
+                    iterations.into(),
+                    MAX_ARGON2_ITERATIONS.into(),
+                ),
+                (
+                    "argon2 parallelism",
+                    parallelism.into(),
+                    MAX_ARGON2_PARALLELISM.into(),
+                ),
+            ],
+            Self::Scrypt { log_n, r, p, .. } => {
+                let memory = 1_u64
+                    .checked_shl(log_n.into())
+                    .and_then(|n| n.checked_mul(128 * u64::from(r)))
+                    .unwrap_or(u64::MAX);
+                &[
+                    ("scrypt memory", memory, MAX_SCRYPT_MEMORY),
+                    ("scrypt p", p.into(), MAX_SCRYPT_P.into()),
+                ]
+            }
+            Self::Pbkdf2 { iterations, .. } => &[(
+                "pbkdf2 iterations",
+                iterations.get().into(),
+                MAX_PBKDF2_ITERATIONS.into(),
+            )],
+        };
+        match limits.iter().find(|(_, value, max)| value > max) {
+            Some(&(parameter, value, max)) => Err(SimpleCipherError::KdfLimit {
+                parameter,
+                value,
+                max,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // These are the bytes of the header field, an identifier for the KDF followed by its
+    // parameters.
+    pub(crate) fn to_bytes(self) -> Vec<u8> {
+        match self {
+            Self::None => vec![KDF_NONE],
+            Self::Hash(algorithm) => vec![KDF_HASH, algorithm.id()],
+            Self::Argon2 {
+                salt,
+                memory,
+                iterations,
+                parallelism,
+            } => {
+                let mut bytes = vec![KDF_ARGON2];
+                bytes.extend_from_slice(&salt);
+                for cost in [memory, iterations, parallelism] {
+                    bytes.extend_from_slice(&cost.to_be_bytes());
+                }
+                bytes
+            }
+            Self::Scrypt { salt, log_n, r, p } => {
+                let mut bytes = vec![KDF_SCRYPT];
+                bytes.extend_from_slice(&salt);
+                bytes.push(log_n);
+                bytes.extend_from_slice(&r.to_be_bytes());
+                bytes.extend_from_slice(&p.to_be_bytes());
+                bytes
+            }
+            Self::Pbkdf2 { salt, iterations } => {
+                let mut bytes = vec![KDF_PBKDF2];
+                bytes.extend_from_slice(&salt);
+                bytes.extend_from_slice(&iterations.get().to_be_bytes());
+                bytes
+            }
+        }
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, SimpleCipherError> {
+        let params = Self::parse(bytes)?;
+        params.check_limits()?;
+        Ok(params)
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self, SimpleCipherError> {
+        match bytes {
+            [KDF_NONE] => Ok(Self::None),
+            [KDF_HASH, id] => KeyHashAlgorithm::from_id(*id)
+                .map(Self::Hash)
+                .ok_or(SimpleCipherError::HeaderMalformed),
+            [KDF_ARGON2, rest @ ..] if rest.len() == SALT_LENGTH + 3 * 4 => {
+                let (salt, costs) = rest.split_at(SALT_LENGTH);
+                let cost = |i: usize| {
+                    u32::from_be_bytes(costs[4 * i..4 * i + 4].try_into().expect("4 bytes"))
+                };
+                Ok(Self::Argon2 {
+                    salt: salt.try_into().expect("salt length"),
+                    memory: cost(0),
+                    iterations: cost(1),
+                    parallelism: cost(2),
+                })
+            }
+            [KDF_SCRYPT, rest @ ..] if rest.len() == SALT_LENGTH + 1 + 2 * 4 => {
+                let (salt, costs) = rest.split_at(SALT_LENGTH);
+                let cost =
+                    |i: usize| u32::from_be_bytes(costs[i..i + 4].try_into().expect("4 bytes"));
+                Ok(Self::Scrypt {
+                    salt: salt.try_into().expect("salt length"),
+                    log_n: costs[0],
+                    r: cost(1),
+                    p: cost(5),
+                })
+            }
+            [KDF_PBKDF2, rest @ ..] if rest.len() == SALT_LENGTH + 4 => {
+                let (salt, iterations) = rest.split_at(SALT_LENGTH);
+                let iterations = u32::from_be_bytes(iterations.try_into().expect("4 bytes"));
+                Ok(Self::Pbkdf2 {
+                    salt: salt.try_into().expect("salt length"),
+                    iterations: NonZeroU32::new(iterations)
+                        .ok_or(SimpleCipherError::HeaderMalformed)?,
+                })
+            }
+            _ => Err(SimpleCipherError::HeaderMalformed),
+        }
+    }
+}
+
+/// Derives the encryption key from `passphrase` with the KDF selected by `params`. This is the
+/// only place keys are derived so the same passphrase and parameters always give the same key.
+pub fn derive_key(
+    passphrase: &[u8],
+    params: &KdfParams,
+) -> Result<Zeroizing<Key>, SimpleCipherError> {
+    match params {
+        KdfParams::None => crate::pad_key(passphrase),
+        KdfParams::Hash(algorithm) => Ok(algorithm.hash(passphrase)),
+        KdfParams::Argon2 {
+            salt,
+            memory,
+            iterations,
+            parallelism,
+        } => {
+            let params = Params::new(*memory, *iterations, *parallelism, Some(32))?;
+            // The memory is reserved up front, rather than by the argon2 crate, so an
+            // allocation refused under --limit-memory is an error instead of an abort.
+            let mut blocks = Vec::new();
+            blocks
+                .try_reserve_exact(*memory as usize)
+                .map_err(|_| SimpleCipherError::KdfMemory(*memory))?;
+            blocks.resize(*memory as usize, Block::default());
+            let mut derived = Zeroizing::new(Key::default());
+            Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                .hash_password_into_with_memory(passphrase, salt, &mut derived, &mut blocks)?;
+            Ok(derived)
+        }
+        KdfParams::Scrypt { salt, log_n, r, p } => {
+            // The scrypt crate only implements `Error` for its errors with the password hash
+            // string format, so its error is replaced rather than wrapped.
+            let params = scrypt::Params::new(*log_n, *r, *p, 32)
+                .map_err(|_| SimpleCipherError::ScryptParams)?;
+            let mut derived = Zeroizing::new(Key::default());
+            scrypt::scrypt(passphrase, salt, &params, &mut derived)
+                .expect("32 bytes is a valid scrypt output length");
+            Ok(derived)
+        }
+        KdfParams::Pbkdf2 { salt, iterations } => {
+            let mut derived = Zeroizing::new(Key::default());
+            pbkdf2::pbkdf2_hmac::<Sha256>(passphrase, salt, iterations.get(), &mut derived);
+            Ok(derived)
+        }
+    }
+}
+
+/// The fingerprint of `key`, its SHA-256 hash truncated to 8 bytes, which is the [`KeyId`]
+/// recorded in the headers of files encrypted under it. Fingerprints are displayed and compared
+/// as lower case hex.
+pub fn key_id(key: &Key) -> KeyId {
+    Sha256::digest(key)[..KEY_ID_LENGTH]
+        .try_into()
+        .expect("key id length")
+}
+
+/// A commitment to `key`, a MAC of a fixed string under it. XChaCha20Poly1305 does not commit to
+/// its key, so a ciphertext can be crafted to authenticate under two keys, but only one of them
+/// matches a commitment recorded alongside it.
+pub fn key_commitment(key: &Key) -> blake3::Hash {
+    blake3::keyed_hash(key.as_ref(), KEY_COMMITMENT_MESSAGE)
+}
+
+/// Caps the address space of this process at `megabytes` so a KDF asking for more memory than
+/// the machine can spare fails to allocate instead of drawing the OOM killer. The limit lasts
+/// for the rest of the process.
+#[cfg(unix)]
+pub fn limit_memory(megabytes: u64) -> Result<(), SimpleCipherError> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is a valid rlimit for getrlimit to write to and setrlimit to read.
+    unsafe {
+        if libc::getrlimit(libc::RLIMIT_AS, &mut limit) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let bytes = megabytes.saturating_mul(1024 * 1024) as libc::rlim_t;
+        limit.rlim_cur = bytes.min(limit.rlim_max);
+        if libc::setrlimit(libc::RLIMIT_AS, &limit) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn limit_memory(_megabytes: u64) -> Result<(), SimpleCipherError> {
+    Err(SimpleCipherError::MemoryLimitUnsupported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn hash_algorithms_derive_different_keys() {
+        let key = b"a key which is much longer than the thirty two bytes a key may be padded to";
+        let keys: Vec<Zeroizing<Key>> = KeyHashAlgorithm::ALL
+            .iter()
+            .map(|algorithm| derive_key(key, &KdfParams::Hash(*algorithm)).unwrap())
+            .collect();
+        assert_ne!(keys[0], keys[1]);
+        assert_ne!(keys[0], keys[2]);
+        assert_ne!(keys[1], keys[2]);
+        assert!(derive_key(key, &KdfParams::None).is_err());
+    }
+
+    #[test]
+    fn params_round_trip() {
+        for params in [
+            KdfParams::None,
+            KdfParams::Hash(KeyHashAlgorithm::Sha256),
+            KdfParams::Hash(KeyHashAlgorithm::Blake3),
+            KdfParams::argon2(64, 1, &mut OsRng),
+            KdfParams::scrypt(4, 8, 1, &mut OsRng),
+            KdfParams::pbkdf2(DEFAULT_PBKDF2_ITERATIONS, &mut OsRng),
+        ] {
+            assert_eq!(KdfParams::from_bytes(&params.to_bytes()).unwrap(), params);
+        }
+        assert!(KdfParams::from_bytes(&[KDF_HASH, 0]).is_err());
+        let mut no_iterations = vec![KDF_PBKDF2];
+        no_iterations.resize(1 + SALT_LENGTH + 4, 0);
+        assert!(KdfParams::from_bytes(&no_iterations).is_err());
+    }
+
+    // The header field of `params` parses only while every cost is within its maximum.
+    fn assert_limited(within: KdfParams, over: KdfParams, parameter: &str) {
+        assert_eq!(KdfParams::from_bytes(&within.to_bytes()).unwrap(), within);
+        match KdfParams::from_bytes(&over.to_bytes()) {
+            Err(SimpleCipherError::KdfLimit { parameter: p, .. }) => assert_eq!(p, parameter),
+            other => panic!("{over:?} was not refused: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn argon2_header_costs_are_limited() {
+        let params = |memory, iterations, parallelism| KdfParams::Argon2 {
+            salt: [0; SALT_LENGTH],
+            memory,
+            iterations,
+            parallelism,
+        };
+        let within = params(
+            MAX_ARGON2_MEMORY,
+            MAX_ARGON2_ITERATIONS,
+            MAX_ARGON2_PARALLELISM,
+        );
+        assert_limited(within, params(MAX_ARGON2_MEMORY + 1, 1, 1), "argon2 memory");
+        assert_limited(within, params(64, u32::MAX, 1), "argon2 iterations");
+        assert_limited(
+            within,
+            params(64, 1, MAX_ARGON2_PARALLELISM + 1),
+            "argon2 parallelism",
+        );
+    }
+
+    #[test]
+    fn scrypt_header_costs_are_limited() {
+        let params = |log_n, r, p| KdfParams::Scrypt {
+            salt: [0; SALT_LENGTH],
+            log_n,
+            r,
+            p,
+        };
+        // 2^21 blocks of 128 * 8 bytes is the maximum memory exactly.
+        let within = params(21, 8, MAX_SCRYPT_P);
+        assert_limited(within, params(40, 8, 1), "scrypt memory");
+        assert_limited(within, params(255, 8, 1), "scrypt memory");
+        assert_limited(within, params(15, u32::MAX, 1), "scrypt memory");
+        assert_limited(within, params(4, 8, MAX_SCRYPT_P + 1), "scrypt p");
+    }
+
+    #[test]
+    fn pbkdf2_header_iterations_are_limited() {
+        let params = |iterations| KdfParams::Pbkdf2 {
+            salt: [0; SALT_LENGTH],
+            iterations: NonZeroU32::new(iterations).unwrap(),
+        };
+        assert_limited(
+            params(MAX_PBKDF2_ITERATIONS),
+            params(MAX_PBKDF2_ITERATIONS + 1),
+            "pbkdf2 iterations",
+        );
+    }
+
+    #[test]
+    fn every_kdf_is_deterministic() {
+        let argon2 = KdfParams::Argon2 {
+            salt: [7; SALT_LENGTH],
+            memory: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let scrypt = KdfParams::Scrypt {
+            salt: [7; SALT_LENGTH],
+            log_n: 4,
+            r: 8,
+            p: 1,
+        };
+        let pbkdf2 = KdfParams::Pbkdf2 {
+            salt: [7; SALT_LENGTH],
+            iterations: NonZeroU32::new(10).unwrap(),
+        };
+        let mut params = vec![KdfParams::None, argon2, scrypt, pbkdf2];
+        params.extend(KeyHashAlgorithm::ALL.map(KdfParams::Hash));
+        for params in params {
+            let key = derive_key(b"passphrase", &params).expect("Failed to derive key");
+            assert_eq!(
+                key,
+                derive_key(b"passphrase", &params).unwrap(),
+                "{params:?}"
+            );
+            assert_ne!(
+                key,
+                derive_key(b"passphrasf", &params).unwrap(),
+                "{params:?}"
+            );
+        }
+
+        // Known answers pin the derivations down across versions.
+        assert_eq!(
+            hex::encode(derive_key(b"abc", &KdfParams::Hash(KeyHashAlgorithm::Sha256)).unwrap()),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex::encode(derive_key(b"abc", &KdfParams::Hash(KeyHashAlgorithm::Blake3)).unwrap()),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!(
+            &derive_key(b"abc", &KdfParams::None).unwrap()[..4],
+            b"abc\0"
+        );
+    }
+
+    #[test]
+    fn argon2_is_salted() {
+        let params = KdfParams::argon2(64, 1, &mut OsRng);
+        let key = derive_key(b"passphrase", &params).expect("Failed to derive key");
+        assert_ne!(
+            key,
+            derive_key(b"passphrase", &KdfParams::argon2(64, 1, &mut OsRng)).unwrap()
+        );
+    }
+
+    #[test]
+    fn argon2_salt_determines_the_key() {
+        let with_salt = |salt| KdfParams::Argon2 {
+            salt: [salt; SALT_LENGTH],
+            memory: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let key = derive_key(b"passphrase", &with_salt(1)).expect("Failed to derive key");
+        assert_eq!(key, derive_key(b"passphrase", &with_salt(1)).unwrap());
+        assert_ne!(key, derive_key(b"passphrase", &with_salt(2)).unwrap());
+        // Unlike --kdf none, a short passphrase leaves no predictable bytes in the key.
+        assert!(!key.ends_with(&[0; 4]));
+    }
+
+    // The limit applies to the whole process so it is only set in a child process running just
+    // this test.
+    #[cfg(unix)]
+    #[test]
+    fn kdf_over_memory_limit_fails() {
+        const CHILD: &str = "SKX_MEMORY_LIMIT_CHILD";
+        if std::env::var_os(CHILD).is_some() {
+            limit_memory(256).expect("Failed to limit memory");
+            let params = KdfParams::Argon2 {
+                salt: [0; SALT_LENGTH],
+                memory: 1024 * 1024,
+                iterations: 1,
+                parallelism: 1,
+            };
+            let out = derive_key(b"passphrase", &params);
+            assert_eq!(
+                format!("{:?}", out.unwrap_err()),
+                format!("{:?}", SimpleCipherError::KdfMemory(1024 * 1024))
+            );
+            return;
+        }
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "kdf::tests::kdf_over_memory_limit_fails"])
+            .env(CHILD, "1")
+            .output()
+            .expect("Failed to run child test");
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stdout)
+        );
+    }
+}
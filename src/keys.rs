@@ -0,0 +1,196 @@
+use base64::{
+    engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD},
+    Engine,
+};
+use chacha20poly1305::{
+    aead::{Aead, Payload},
+    Key, KeyInit, XChaCha20Poly1305, XNonce,
+};
+use rand::{rngs::OsRng, RngCore};
+use std::fmt;
+use zeroize::Zeroizing;
+
+use crate::{kdf, key_from_string, Header, SimpleCipherError, MAX_KEY_LENGTH, NONCE_LENGTH};
+
+/// A 256 bit encryption key. Callers with exactly 32 bytes convert them directly with `From`,
+/// skipping the padding applied to `--key` strings. The key, and every clone of it, is wiped
+/// from memory when it is dropped.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SymmetricKey(Zeroizing<[u8; 32]>);
+
+/// A 192 bit XChaCha20Poly1305 nonce.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Nonce([u8; NONCE_LENGTH]);
+
+impl From<[u8; 32]> for SymmetricKey {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(Zeroizing::new(bytes))
+    }
+}
+
+impl From<[u8; NONCE_LENGTH]> for Nonce {
+    fn from(bytes: [u8; NONCE_LENGTH]) -> Self {
+        Self(bytes)
+    }
+}
+
+// Keys are never printed, even in debug output.
+impl fmt::Debug for SymmetricKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SymmetricKey(..)")
+    }
+}
+
+impl SymmetricKey {
+    /// Pads `key` to 32 bytes exactly like `--key`.
+    pub fn from_string(key: &str) -> Result<Self, SimpleCipherError> {
+        Ok(Self(Zeroizing::new((*key_from_string(key)?).into())))
+    }
+
+    /// Encrypts `plaintext` to the contents of a raw encrypted file, header included.
+    pub fn encrypt(&self, nonce: &Nonce, plaintext: &[u8]) -> Result<Vec<u8>, SimpleCipherError> {
+        let cipher = XChaCha20Poly1305::new(self.as_key());
+        let header = Header {
+            key_id: Some(kdf::key_id(self.as_key())),
+            ..Header::default()
+        };
+        let payload = Payload {
+            msg: plaintext,
+            aad: &header.associated_data(b""),
+        };
+        let ciphertext = cipher.encrypt(nonce.as_nonce(), payload)?;
+        Ok(crate::frame(&ciphertext, &header))
+    }
+
+    /// Decrypts the contents of a raw encrypted file written by `encrypt` or `--format raw`.
+    pub fn decrypt(&self, nonce: &Nonce, contents: &[u8]) -> Result<Vec<u8>, SimpleCipherError> {
+        crate::unframe_and_decrypt(
+            contents,
+            Some(nonce.as_nonce()),
+            None,
+            crate::TagPosition::Suffix,
+            b"",
+            false,
+            |_| Ok(Zeroizing::new(*self.as_key())),
+        )
+    }
+
+    fn as_key(&self) -> &Key {
+        Key::from_slice(self.0.as_slice())
+    }
+}
+
+impl Nonce {
+    fn as_nonce(&self) -> &XNonce {
+        XNonce::from_slice(&self.0)
+    }
+}
+
+/// Draws a fresh key of `length` bytes, at most 32, from the operating system's random number
+/// generator. Keys shorter than 32 bytes are padded like `--key` strings when used.
+pub fn generate_key(length: usize) -> Result<Vec<u8>, SimpleCipherError> {
+    if length > MAX_KEY_LENGTH {
+        return Err(SimpleCipherError::KeyTooLong(length));
+    }
+    let mut key = vec![0_u8; length];
+    OsRng
+        .try_fill_bytes(&mut key)
+        .map_err(SimpleCipherError::RngUnavailable)?;
+    Ok(key)
+}
+
+/// The lines `generate-key` prints for `key`: its hex for `--key-format hex`, its base64 for
+/// `--key-format base64` and its URL-safe base64 for `--key-format base64-url`.
+pub fn key_encodings(key: &[u8]) -> String {
+    format!(
+        "hex: {}\nbase64: {}\nbase64url: {}",
+        hex::encode(key),
+        BASE64.encode(key),
+        URL_SAFE_NO_PAD.encode(key)
+    )
+}
+
+/// The text `generate-key --qr` renders as a QR code for `key`: its base64, which
+/// `--key-format base64` reads back.
+pub fn key_qr_text(key: &[u8]) -> Zeroizing<String> {
+    Zeroizing::new(BASE64.encode(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CommonEncryptionOpts, NonceSource};
+
+    #[test]
+    fn encrypt_with_array_key_and_nonce() {
+        let key = SymmetricKey::from([0x42; 32]);
+        let nonce = Nonce::from([0x24; NONCE_LENGTH]);
+        let contents = key.encrypt(&nonce, b"foobar").expect("Failed to encrypt");
+        let plaintext = key.decrypt(&nonce, &contents).expect("Failed to decrypt");
+        assert_eq!(plaintext, b"foobar");
+
+        let wrong_nonce = Nonce::from([0x25; NONCE_LENGTH]);
+        assert!(key
+            .decrypt(&wrong_nonce, &contents)
+            .unwrap_err()
+            .is_auth_failure());
+        assert_eq!(format!("{key:?}"), "SymmetricKey(..)");
+    }
+
+    #[test]
+    fn generated_key_decodes_to_its_length() {
+        for length in [32, 16, 1] {
+            let key = generate_key(length).expect("Failed to generate key");
+            let printed = key_encodings(&key);
+            let lines: Vec<&str> = printed.lines().collect();
+            let [hex_line, base64_line, base64_url_line] = lines[..] else {
+                panic!("{printed}");
+            };
+            let hex_key = hex::decode(hex_line.strip_prefix("hex: ").unwrap()).unwrap();
+            let base64_key = BASE64
+                .decode(base64_line.strip_prefix("base64: ").unwrap())
+                .unwrap();
+            let base64_url_key = URL_SAFE_NO_PAD
+                .decode(base64_url_line.strip_prefix("base64url: ").unwrap())
+                .unwrap();
+            assert_eq!(hex_key.len(), length);
+            assert_eq!(hex_key, key);
+            assert_eq!(base64_key, key);
+            assert_eq!(base64_url_key, key);
+        }
+        assert_ne!(generate_key(32).unwrap(), generate_key(32).unwrap());
+        assert!(matches!(
+            generate_key(33),
+            Err(SimpleCipherError::KeyTooLong(33))
+        ));
+    }
+
+    // A padded string key is interchangeable with the equivalent array.
+    #[test]
+    fn array_key_matches_string_key() {
+        let mut bytes = [0_u8; 32];
+        bytes[..3].copy_from_slice(b"baz");
+        assert_eq!(
+            SymmetricKey::from(bytes),
+            SymmetricKey::from_string("baz").expect("Failed to pad key")
+        );
+
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("encyrpted.dat");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Provided(vec!["j"; NONCE_LENGTH].join("")),
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        let contents = std::fs::read(&encrypted_file).expect("Failed to read encrypted file");
+        let nonce = Nonce::from([b'j'; NONCE_LENGTH]);
+        let plaintext = SymmetricKey::from(bytes)
+            .decrypt(&nonce, &contents)
+            .expect("Failed to decrypt");
+        assert_eq!(plaintext, b"foobar");
+    }
+}
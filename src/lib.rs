@@ -1,22 +1,98 @@
+use base64::{
+    alphabet,
+    engine::{
+        general_purpose::{
+            GeneralPurpose, GeneralPurposeConfig, STANDARD as BASE64, URL_SAFE_NO_PAD,
+        },
+        DecodePaddingMode,
+    },
+    Engine,
+};
 use chacha20poly1305::{
-    aead::{Aead, KeyInit},
+    aead::{AeadCore, Payload},
     Error as ChachaError, Key, XChaCha20Poly1305, XNonce,
 };
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use rand::seq::IteratorRandom;
-use std::{fs, path::PathBuf};
+use std::{
+    borrow::Cow,
+    fs,
+    io::{self, BufRead, IsTerminal, Read, Write},
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
 use thiserror::Error;
+use zeroize::Zeroizing;
+
+mod algorithm;
+mod atomic;
+pub mod batch;
+mod builder;
+mod cipher;
+mod compress;
+mod config;
+mod encoding;
+mod entropy;
+pub mod env;
+mod header;
+mod input;
+mod kdf;
+mod keys;
+mod mapped;
+mod parts;
+mod qr;
+mod report;
+mod rng;
+pub mod segments;
+mod shamir;
+pub mod stego;
+mod stream;
+mod timing;
+pub use algorithm::SimpleCipherAlgorithm;
+pub use atomic::AtomicFile;
+pub use builder::CipherBuilder;
+pub use cipher::{AuthenticatedBytes, Cipher};
+pub use compress::Compression;
+pub use config::ConfigDefaults;
+pub use encoding::CiphertextFormat;
+pub use entropy::{estimate_entropy_bits, LOW_ENTROPY_BITS};
+pub use header::{header_json, minimize_headers, Checksum, Header};
+pub use input::read_input;
+pub use kdf::{derive_key, Kdf, KdfParams, KeyHashAlgorithm};
+pub use keys::{generate_key, key_encodings, key_qr_text, Nonce, SymmetricKey};
+pub use qr::{qr_image, write_qr};
+pub use report::{report_error, run_main, ErrorStyle};
+pub use shamir::{combine_key, split_key};
+pub use stream::StreamParams;
+pub use timing::{self_check_timing, TimingReport};
 
 const MAX_KEY_LENGTH: usize = 32;
 const NONCE_LENGTH: usize = 24;
+// The Poly1305 tag appended to every ciphertext.
+const TAG_LENGTH: usize = 16;
+
+// Base64 nonces and keys are decoded leniently, accepting any padding and unused trailing bits,
+// unless --canonical-nonce is given.
+const LENIENT_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new()
+        .with_decode_allow_trailing_bits(true)
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
 
+/// Every fallible function of this library returns this error. Errors from other crates are kept
+/// as the `source` of the variant wrapping them so the whole chain is available to callers.
 #[derive(Debug, Error)]
 pub enum SimpleCipherError {
-    #[error(transparent)]
+    #[error("Encryption failed")]
     Chacha(#[from] ChachaError),
-    #[error(transparent)]
+    #[error("Decryption failed: wrong key or nonce, or corrupted data")]
+    AuthenticationFailed,
+    #[error("I/O error")]
     IO(#[from] std::io::Error),
-    #[error(transparent)]
+    #[error("Decrypted message is not valid UTF-8")]
     Utf8Conversion(#[from] std::string::FromUtf8Error),
     #[error("Key is {0} bytes long. Select a key that is less than 32 bytes long")]
     KeyTooLong(usize),
@@ -24,398 +100,4431 @@ pub enum SimpleCipherError {
     NonceGenerate,
     #[error("Must select no-nonce, a nonce string or a generated nonce")]
     NonceChoiceUndeteremined,
+    #[error("More than one nonce choice was made: {0}")]
+    NonceChoiceConflict(String),
     #[error("This nonce is {0} bytes long. Select a key that is less than 24 bytes long")]
     NonceTooLong(usize),
+    #[error("This nonce is {0} bytes long but --strict-nonce requires exactly 24 bytes")]
+    NonceTooShort(usize),
+    #[error(
+        "Nonce character {0:?} does not fit in a byte. Use --nonce-encoding hex for binary nonces"
+    )]
+    NonceCharacter(char),
+    #[error("Nonce file holds {0} bytes but the nonce is exactly {1} bytes")]
+    NonceFileLength(usize, usize),
+    #[error("Invalid hex")]
+    HexDecode(#[from] hex::FromHexError),
+    #[error("Invalid base64")]
+    Base64Decode(#[from] base64::DecodeError),
+    #[error("Encrypted file looks like PEM but is missing the SKE MESSAGE markers")]
+    PemMalformed,
+    #[error("Encrypted stream has a missing or unsupported preamble")]
+    StreamPreamble,
+    #[error("The stream chunk size {0} is not between 1 and {max}", max = stream::MAX_CHUNK_SIZE)]
+    StreamChunkSize(u32),
+    #[error("Encrypted stream ended before its last chunk")]
+    StreamTruncated,
+    #[error("Encrypted stream format version {0} is newer than this version understands")]
+    UnsupportedFormatVersion(u8),
+    #[error("Output directory {0:?} does not exist. Pass --mkdir to create it")]
+    OutputDirMissing(PathBuf),
+    #[error("Encrypted file {0:?} already exists. Pass --force to overwrite it")]
+    OutputExists(PathBuf),
+    #[error("The output {0:?} is the encrypted file being decrypted")]
+    OutputIsEncryptedFile(PathBuf),
+    #[error("Invalid defaults file")]
+    Config(#[from] toml::de::Error),
+    #[error("The defaults file must not contain the secret option `{0}`")]
+    ConfigSecret(String),
+    #[error("Timed out after {0:?} waiting for input")]
+    InputTimeout(std::time::Duration),
+    #[error("Image error")]
+    Image(#[from] image::ImageError),
+    #[error("Cover image holds {available} bytes but the ciphertext is {needed} bytes long")]
+    StegoCapacity { needed: usize, available: usize },
+    #[error("Image does not contain a hidden ciphertext")]
+    StegoPayload,
+    #[error("No key was given")]
+    KeyMissing,
+    #[error("Could not read the key from the environment variable {name}")]
+    KeyEnv {
+        name: String,
+        #[source]
+        source: std::env::VarError,
+    },
+    #[error("A hex key must be 64 hex digits, exactly 32 bytes")]
+    KeyHexInvalid,
+    #[error("The key is not valid base64")]
+    KeyBase64Invalid(#[source] base64::DecodeError),
+    #[error("Key is {0} bytes long but must be at least {1} bytes long")]
+    KeyTooShort(usize, usize),
+    #[error("Key share threshold {threshold} must be between 1 and the number of shares {shares}")]
+    KeyShareThreshold { threshold: u8, shares: u8 },
+    #[error("Key share is not a valid share or is from a different split")]
+    KeyShareInvalid,
+    #[error(
+        "At least {threshold} key shares are needed but only {given} distinct shares were given"
+    )]
+    NotEnoughKeyShares { threshold: u8, given: usize },
+    #[error("{0:?} is not a valid NAME=VALUE environment variable")]
+    InvalidEnvName(String),
+    #[error("Invalid environment variable payload")]
+    Json(#[from] serde_json::Error),
+    #[error("Encrypted file header is malformed")]
+    HeaderMalformed,
+    #[error("Encrypted file format version {0} is not supported by this version")]
+    UnsupportedVersion(u8),
+    #[error("Encrypted file does not start with the file format magic")]
+    BadMagic,
+    #[error("Encrypted file header has unknown field {0}")]
+    UnknownHeaderField(u8),
+    #[error("Encrypted file is truncated or corrupt: the header records {expected} bytes of ciphertext and {actual} bytes failed the checksum")]
+    Corrupted { expected: u64, actual: u64 },
+    #[error("The message is already encrypted. Pass --allow-double-encrypt to encrypt it again")]
+    AlreadyEncrypted,
+    #[error("Invalid Argon2 parameters")]
+    Argon2(#[from] argon2::Error),
+    #[error("Invalid scrypt parameters")]
+    ScryptParams,
+    #[error("The KDF could not allocate its {0} KiB of memory")]
+    KdfMemory(u32),
+    #[error("The KDF {parameter} of {value} is above the maximum of {max}")]
+    KdfLimit {
+        parameter: &'static str,
+        value: u64,
+        max: u64,
+    },
+    #[error("--limit-memory is only supported on Unix")]
+    MemoryLimitUnsupported,
+    #[error("Streamed files cannot record the salt this KDF needs")]
+    StreamKdf,
+    #[error("Streamed files are only encrypted with xchacha20poly1305, not {0}")]
+    StreamAlgorithm(SimpleCipherAlgorithm),
+    #[error("The encrypted file does not decrypt back to the message")]
+    VerifyMismatch,
+    #[error("Appending to an existing log needs the log's --nonce rather than a generated one")]
+    AppendNonce,
+    #[error("The nonce is not in the canonical form of its encoding")]
+    NonCanonicalNonce,
+    #[error("The key has fingerprint {actual}, not the expected {expected}")]
+    KeyFingerprintMismatch { expected: String, actual: String },
+    #[error("The key fingerprint was not confirmed")]
+    KeyNotConfirmed,
+    #[error("More than one input of the batch would be encrypted to {0}")]
+    BatchOutput(PathBuf),
+    #[error("The file is {size} bytes, above the maximum of {max}")]
+    FileTooLarge { size: u64, max: u64 },
+    #[error("Invalid batch pattern")]
+    BatchPattern(#[from] glob::PatternError),
+    #[error("Cannot encode as a QR code")]
+    Qr(#[from] qrcode::types::QrError),
+    #[error("The key does not match the key commitment of the encrypted file")]
+    KeyCommitmentMismatch,
+    #[error("The encrypted file has no key commitment to check")]
+    KeyCommitmentMissing,
+    #[error("Unknown algorithm {0}, expected one of: {names}", names = algorithm::algorithm_names())]
+    UnknownAlgorithm(String),
+    #[error("--algorithm {given} was given but the file was encrypted with {recorded}")]
+    AlgorithmMismatch {
+        given: SimpleCipherAlgorithm,
+        recorded: SimpleCipherAlgorithm,
+    },
+    #[error("Streamed files cannot be compressed")]
+    StreamCompression,
+    #[error("Unknown compression {0} in the header")]
+    UnknownCompression(u8),
+    #[error("The decrypted message failed to decompress")]
+    Decompression(#[source] std::io::Error),
+    #[error("The passphrases entered do not match")]
+    PassphraseMismatch,
+    #[error("The key is estimated at only {0:.0} bits of entropy")]
+    LowEntropyKey(f64),
+    #[error("Gave up after {0} keys failed to decrypt")]
+    TooManyKeyAttempts(usize),
+    #[error("The random number generator is unavailable")]
+    RngUnavailable(#[source] rand::Error),
+}
+
+impl SimpleCipherError {
+    /// Returns `true` when the ciphertext failed to authenticate, meaning the key or nonce is
+    /// wrong or the ciphertext was tampered with. Structural and IO errors return `false`.
+    pub fn is_auth_failure(&self) -> bool {
+        matches!(self, Self::AuthenticationFailed)
+    }
+
+    // A failure to authenticate or a mismatched key commitment, which another key may pass.
+    fn is_wrong_key(&self) -> bool {
+        self.is_auth_failure() || matches!(self, Self::KeyCommitmentMismatch)
+    }
+
+    /// A short description of the kind of error with none of its details, such as paths,
+    /// lengths or values read from input. This is what `--redact` displays.
+    pub fn category(&self) -> &'static str {
+        match self {
+            Self::Chacha(_) => "Encryption failed",
+            Self::AuthenticationFailed => "Authentication failed",
+            Self::IO(_) => "I/O error",
+            Self::Utf8Conversion(_) => "Decrypted message is not valid UTF-8",
+            Self::KeyTooLong(_) => "Key is too long",
+            Self::NonceGenerate => "Nonce generation not supported with decrypt",
+            Self::NonceChoiceUndeteremined => "No nonce was selected",
+            Self::NonceChoiceConflict(_) => "Conflicting nonce choices",
+            Self::NonceTooLong(_) => "Nonce is too long",
+            Self::NonceTooShort(_) => "Nonce is too short",
+            Self::NonceCharacter(_) => "Invalid nonce character",
+            Self::NonceFileLength(..) => "Invalid nonce file",
+            Self::HexDecode(_) => "Invalid hex",
+            Self::Base64Decode(_) => "Invalid base64",
+            Self::PemMalformed => "Malformed PEM",
+            Self::StreamPreamble => "Invalid stream preamble",
+            Self::StreamChunkSize(_) => "Invalid stream chunk size",
+            Self::StreamTruncated => "Encrypted stream is truncated",
+            Self::UnsupportedFormatVersion(_) => "Unsupported stream format version",
+            Self::OutputDirMissing(_) => "Output directory does not exist",
+            Self::OutputExists(_) => "Encrypted file already exists",
+            Self::OutputIsEncryptedFile(_) => "Output is the encrypted file",
+            Self::Config(_) => "Invalid defaults file",
+            Self::ConfigSecret(_) => "Defaults file contains a secret",
+            Self::InputTimeout(_) => "Timed out reading input",
+            Self::Image(_) => "Image error",
+            Self::StegoCapacity { .. } => "Cover image is too small",
+            Self::StegoPayload => "Image holds no hidden ciphertext",
+            Self::KeyMissing => "No key was given",
+            Self::KeyEnv { .. } => "Key environment variable is missing",
+            Self::KeyHexInvalid => "Invalid hex key",
+            Self::KeyBase64Invalid(_) => "Invalid base64 key",
+            Self::KeyTooShort(..) => "Key is too short",
+            Self::KeyShareThreshold { .. } => "Invalid key share threshold",
+            Self::KeyShareInvalid => "Invalid key share",
+            Self::NotEnoughKeyShares { .. } => "Not enough key shares",
+            Self::InvalidEnvName(_) => "Invalid environment variable",
+            Self::Json(_) => "Invalid environment variable payload",
+            Self::HeaderMalformed => "Malformed file header",
+            Self::UnsupportedVersion(_) => "Unsupported file format version",
+            Self::BadMagic => "Not an encrypted file",
+            Self::UnknownHeaderField(_) => "Unknown file header field",
+            Self::Corrupted { .. } => "Encrypted file is truncated or corrupt",
+            Self::AlreadyEncrypted => "Message is already encrypted",
+            Self::Argon2(_) => "Invalid Argon2 parameters",
+            Self::ScryptParams => "Invalid scrypt parameters",
+            Self::KdfMemory(_) => "The KDF ran out of memory",
+            Self::KdfLimit { .. } => "KDF parameters too large",
+            Self::MemoryLimitUnsupported => "Memory limits are not supported",
+            Self::StreamKdf => "KDF not supported with streaming",
+            Self::StreamAlgorithm(_) => "Algorithm not supported with streaming",
+            Self::VerifyMismatch => "Encrypted file failed verification",
+            Self::AppendNonce => "Appending needs the log's nonce",
+            Self::NonCanonicalNonce => "Nonce is not canonically encoded",
+            Self::KeyFingerprintMismatch { .. } => "Key fingerprint mismatch",
+            Self::KeyNotConfirmed => "Key not confirmed",
+            Self::BatchOutput(_) => "Batch outputs collide",
+            Self::FileTooLarge { .. } => "File too large",
+            Self::BatchPattern(_) => "Invalid batch pattern",
+            Self::Qr(_) => "QR code error",
+            Self::KeyCommitmentMismatch => "Key commitment mismatch",
+            Self::KeyCommitmentMissing => "Key commitment missing",
+            Self::UnknownAlgorithm(_) => "Unknown algorithm",
+            Self::AlgorithmMismatch { .. } => "Algorithm mismatch",
+            Self::TooManyKeyAttempts(_) => "Too many keys failed to decrypt",
+            Self::RngUnavailable(_) => "The random number generator is unavailable",
+            Self::LowEntropyKey(_) => "Key entropy is low",
+            Self::StreamCompression => "Compression not supported with streaming",
+            Self::UnknownCompression(_) => "Unknown compression",
+            Self::Decompression(_) => "Decompression failed",
+            Self::PassphraseMismatch => "Passphrases do not match",
+        }
+    }
+}
+
+/// Describes `error` by its category alone for `--redact`. Errors from outside this crate are
+/// not described at all.
+pub fn redacted(error: &(dyn std::error::Error + 'static)) -> &'static str {
+    match error.downcast_ref::<SimpleCipherError>() {
+        Some(error) => error.category(),
+        None => "Error",
+    }
+}
+
+/// This function simply takes a string, converts it to bytes, and pads the vec to be 32 bytes
+/// long as this key is 32 bytes long.
+pub fn key_from_string(key: &str) -> Result<Zeroizing<Key>, SimpleCipherError> {
+    pad_key(key.as_bytes())
+}
+
+fn pad_key(key: &[u8]) -> Result<Zeroizing<Key>, SimpleCipherError> {
+    if key.len() > MAX_KEY_LENGTH {
+        return Err(SimpleCipherError::KeyTooLong(key.len()));
+    }
+    // The key is copied straight into the zero filled result, so no other copy is left behind.
+    let mut padded = Zeroizing::new(Key::default());
+    padded[..key.len()].copy_from_slice(key);
+    Ok(padded)
+}
+
+// This is the fingerprint of a key as it is displayed and compared, the lower case hex of its
+// key id.
+fn key_fingerprint(key: &Key) -> String {
+    hex::encode(kdf::key_id(key))
+}
+
+fn check_key_length(length: usize, min: usize) -> Result<(), SimpleCipherError> {
+    if length < min {
+        return Err(SimpleCipherError::KeyTooShort(length, min));
+    }
+    Ok(())
+}
+
+// This prepends the header to a one shot ciphertext, giving the contents of the encrypted file
+// before any text encoding.
+fn frame(ciphertext: &[u8], header: &Header) -> Vec<u8> {
+    let header = Header {
+        checksum: Some(Checksum::of(ciphertext)),
+        ..header.clone()
+    };
+    let mut contents = header.to_bytes();
+    contents.extend_from_slice(ciphertext);
+    contents
+}
+
+// This checks the header of one shot file contents, if any, and decrypts the ciphertext after it,
+// with its tag at `tag_position` and authenticating `aad`. The key is asked for with the header,
+// or `None` for a file without one. A nonce embedded in the header is used over `nonce`, which
+// is only needed for files without one. With `require_commitment` a file without a key
+// commitment is refused rather than decrypted unchecked.
+fn unframe_and_decrypt<F>(
+    contents: &[u8],
+    nonce: Option<&XNonce>,
+    algorithm: Option<SimpleCipherAlgorithm>,
+    tag_position: TagPosition,
+    aad: &[u8],
+    require_commitment: bool,
+    key_for: F,
+) -> Result<Vec<u8>, SimpleCipherError>
+where
+    F: FnOnce(Option<&Header>) -> Result<Zeroizing<Key>, SimpleCipherError>,
+{
+    let (header, ciphertext) = Header::parse(contents)?;
+    if let Some(header) = &header {
+        header.verify(ciphertext)?;
+    }
+    // Files from before the header existed are decrypted with the given algorithm.
+    let recorded = header
+        .as_ref()
+        .map_or(algorithm.unwrap_or_default(), |header| header.algorithm);
+    check_algorithm(algorithm, recorded)?;
+    let key = key_for(header.as_ref())?;
+    match header.as_ref().and_then(|header| header.key_commitment) {
+        // The comparison of blake3 hashes takes constant time.
+        Some(commitment) if kdf::key_commitment(&key) != commitment => {
+            return Err(SimpleCipherError::KeyCommitmentMismatch);
+        }
+        None if require_commitment => return Err(SimpleCipherError::KeyCommitmentMissing),
+        _ => {}
+    }
+    let nonce = match header.as_ref().and_then(|header| header.nonce.as_ref()) {
+        Some(embedded) => XNonce::from_slice(embedded),
+        None => nonce.ok_or(SimpleCipherError::NonceChoiceUndeteremined)?,
+    };
+    let msg = tag_position.restore(ciphertext);
+    // The header is authenticated along with the associated data, so removing or changing a
+    // field such as the compression fails to decrypt.
+    let aad = match &header {
+        Some(header) => header.associated_data(aad),
+        None => aad.to_vec(),
+    };
+    let plaintext = recorded
+        .decrypt(
+            &key,
+            nonce,
+            Payload {
+                msg: &msg,
+                aad: &aad,
+            },
+        )
+        .map_err(authentication_failed)?;
+    // Files from before compression existed were never compressed.
+    header
+        .map_or(Compression::None, |header| header.compression)
+        .decompress(plaintext)
+}
+
+// The AEAD error says nothing more than that the ciphertext failed to authenticate, which is
+// all that is reported so a wrong key cannot be told apart from tampering.
+fn authentication_failed(_: ChachaError) -> SimpleCipherError {
+    SimpleCipherError::AuthenticationFailed
+}
+
+// This refuses an --algorithm other than the one the encrypted file records.
+fn check_algorithm(
+    given: Option<SimpleCipherAlgorithm>,
+    recorded: SimpleCipherAlgorithm,
+) -> Result<(), SimpleCipherError> {
+    match given {
+        Some(given) if given != recorded => {
+            Err(SimpleCipherError::AlgorithmMismatch { given, recorded })
+        }
+        _ => Ok(()),
+    }
+}
+
+// This truncates the file at `path`, or creates it, holding an exclusive lock so a
+// `decrypt_mapped` of the file does not see it change.
+fn create_locked(path: &Path) -> io::Result<fs::File> {
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    file.lock()?;
+    file.set_len(0)?;
+    Ok(file)
+}
+
+const BUSY_RETRIES: u32 = 5;
+const BUSY_BACKOFF: Duration = Duration::from_millis(50);
+
+// Whether `error` is the kind of failure another process briefly holding the file causes, such
+// as a virus scanner on Windows.
+fn is_busy(error: &io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+    let windows_busy = cfg!(windows) && matches!(error.raw_os_error(), Some(32 | 33));
+    error.kind() == io::ErrorKind::ResourceBusy || windows_busy
+}
+
+// This runs `op` again after a doubling backoff while it fails with a busy error, when `retry` is
+// set, and gives back the last error once the retries run out.
+fn retry_if_busy<T, F>(retry: bool, mut op: F) -> io::Result<T>
+where
+    F: FnMut() -> io::Result<T>,
+{
+    let mut backoff = BUSY_BACKOFF;
+    for _ in 0..BUSY_RETRIES {
+        match op() {
+            Err(e) if retry && is_busy(&e) => {
+                std::thread::sleep(backoff);
+                backoff *= 2;
+            }
+            result => return result,
+        }
+    }
+    op()
+}
+
+// This splits `encoded` into space separated groups of `width` characters.
+fn group(encoded: &str, width: usize) -> String {
+    encoded
+        .as_bytes()
+        .chunks(width)
+        .map(|chunk| std::str::from_utf8(chunk).expect("encoded nonces are ascii"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Writes `plaintext` to `writer` and, when `tee` is given, to that file as well. Both sinks
+/// receive exactly the same bytes.
+pub fn write_plaintext<W: Write>(
+    plaintext: &[u8],
+    writer: &mut W,
+    tee: Option<&Path>,
+) -> Result<(), SimpleCipherError> {
+    if let Some(tee) = tee {
+        fs::File::create(tee)?.write_all(plaintext)?;
+    }
+    writer.write_all(plaintext)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// The method used to generate a nonce with `--generate-nonce`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum NonceGenerator {
+    /// Lower case letters sampled from a corpus of the alphabet. These are easy to type but
+    /// cover a small part of the nonce space.
+    Legacy,
+    /// Every byte drawn from the operating system's random number generator.
+    #[default]
+    Random,
+}
+
+/// How `--key` and `--previous-key` are read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum KeyFormat {
+    /// A string of up to 32 bytes, or any length with a --kdf other than none.
+    #[default]
+    Text,
+    /// 64 hex digits used as the 32 byte key as they are, such as from `openssl rand -hex 32`.
+    Hex,
+    /// Standard base64 of up to 32 bytes, padded with zero bytes like a text key.
+    Base64,
+    /// URL-safe base64 of up to 32 bytes, using `-` and `_` and no padding, padded with zero
+    /// bytes like a text key.
+    Base64Url,
+}
+
+/// How `--nonce` is read and a generated nonce is printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum NonceEncoding {
+    /// Up to 24 ascii characters, or 48 hex digits. Generated nonces which are not printable
+    /// ascii are printed as hex.
+    #[default]
+    Text,
+    /// Hex of up to 24 bytes.
+    Hex,
+    /// Standard base64 of up to 24 bytes.
+    Base64,
+    /// URL-safe base64 of up to 24 bytes, using `-` and `_` and no padding so the nonce can be
+    /// put in a URL or file name as it is.
+    Base64Url,
+}
+
+/// Where the nonce of a set of options comes from. The nonce flags are converted to this once,
+/// when they are parsed, so a conflicting combination of them never reaches the cipher.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum NonceSource {
+    /// A fresh nonce is generated with `--generate-nonce`.
+    Generated,
+    /// A fresh nonce is generated with `--generate-nonce` and written to `--nonce-file`.
+    GeneratedToFile(PathBuf),
+    /// The all zero nonce of `--no-nonce`.
+    Zero,
+    /// The nonce is parsed from `--nonce`.
+    Provided(String),
+    /// The raw nonce is read from `--nonce-file`.
+    File(PathBuf),
+    /// No nonce option is set, so decryption reads the nonce from the header of the encrypted
+    /// file and encryption fails.
+    #[default]
+    Undetermined,
+}
+
+impl NonceSource {
+    /// Whether a fresh nonce is generated, which only encryption can do.
+    pub fn is_generated(&self) -> bool {
+        matches!(self, Self::Generated | Self::GeneratedToFile(_))
+    }
+}
+
+impl clap::FromArgMatches for NonceSource {
+    fn from_arg_matches(matches: &clap::ArgMatches) -> Result<Self, clap::Error> {
+        let nonce_file = matches.get_one::<PathBuf>("nonce_file").cloned();
+        Ok(if matches.get_flag("no_nonce") {
+            Self::Zero
+        } else if matches.get_flag("generate_nonce") {
+            nonce_file.map_or(Self::Generated, Self::GeneratedToFile)
+        } else if let Some(nonce) = matches.get_one::<String>("nonce") {
+            Self::Provided(nonce.clone())
+        } else {
+            nonce_file.map_or(Self::Undetermined, Self::File)
+        })
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &clap::ArgMatches) -> Result<(), clap::Error> {
+        *self = Self::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+// These are the nonce flags, which clap lets only one of --no-nonce, --generate-nonce and
+// --nonce be given of.
+impl clap::Args for NonceSource {
+    fn augment_args(command: clap::Command) -> clap::Command {
+        command
+            .arg(
+                clap::Arg::new("no_nonce")
+                    .long("no-nonce")
+                    .action(clap::ArgAction::SetTrue)
+                    .group("nonce-choice")
+                    .required(true)
+                    .help(
+                        "**NOT RECOMMENDED:** This is a helper option to enable the nonce be all \
+                         zeros. This results in the encrypted message be the same on every \
+                         encryption and subject to a replay attacks",
+                    ),
+            )
+            .arg(
+                clap::Arg::new("generate_nonce")
+                    .short('g')
+                    .long("generate-nonce")
+                    .action(clap::ArgAction::SetTrue)
+                    .group("nonce-choice")
+                    .required(true)
+                    .help(
+                        "This is a flag to enable a newly generated nonce on encryption. This \
+                         will error when used on decryption",
+                    ),
+            )
+            .arg(
+                clap::Arg::new("nonce")
+                    .short('n')
+                    .long("nonce")
+                    .value_name("NONCE")
+                    .value_parser(clap::value_parser!(String))
+                    .group("nonce-choice")
+                    .required(true)
+                    .help(
+                        "This is the string representation of a nonce as ascii characters up to \
+                         24 characters in length. This is required for decryption unless using \
+                         the unrecommended --no-nonce feature or the nonce was embedded in the \
+                         encrypted file",
+                    ),
+            )
+            .arg(
+                clap::Arg::new("nonce_file")
+                    .long("nonce-file")
+                    .value_name("NONCE_FILE")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .conflicts_with_all(["no_nonce", "nonce"])
+                    .help(
+                        "This is a file holding the raw 24 byte nonce. With --generate-nonce the \
+                         generated nonce is written to it rather than printed or embedded, \
+                         otherwise the nonce is read from it",
+                    ),
+            )
+    }
+
+    fn augment_args_for_update(command: clap::Command) -> clap::Command {
+        Self::augment_args(command)
+    }
+}
+
+/// Where the 16 byte authentication tag is placed in a one shot ciphertext.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum TagPosition {
+    /// After the ciphertext, as the chacha20poly1305 crate writes it.
+    #[default]
+    Suffix,
+    /// Before the ciphertext, as some other AEAD implementations expect.
+    Prefix,
+}
+
+impl TagPosition {
+    // This moves the tag from the end of `ciphertext`, where the AEAD appends it, to this
+    // position.
+    fn place(self, ciphertext: &mut [u8]) {
+        if self == Self::Prefix {
+            ciphertext.rotate_right(TAG_LENGTH.min(ciphertext.len()));
+        }
+    }
+
+    // This moves the tag of a ciphertext in this position back to the end for the AEAD. A
+    // ciphertext too short to hold a tag is left for the AEAD to reject.
+    fn restore(self, ciphertext: &[u8]) -> Cow<'_, [u8]> {
+        match self {
+            Self::Prefix if ciphertext.len() >= TAG_LENGTH => {
+                let mut restored = ciphertext.to_vec();
+                restored.rotate_left(TAG_LENGTH);
+                Cow::Owned(restored)
+            }
+            _ => Cow::Borrowed(ciphertext),
+        }
+    }
+}
+
+impl NonceGenerator {
+    /// This is the base 2 logarithm of the number of distinct nonces this generator can produce.
+    pub fn nonce_space_log2(&self) -> f64 {
+        match self {
+            // Every byte of a legacy nonce is one of the 25 distinct letters in the corpus. The
+            // 624 choose 24 figure counts choices of corpus positions, many of which produce
+            // the same nonce, so 25^24 is the actual upper bound.
+            Self::Legacy => NONCE_LENGTH as f64 * 25_f64.log2(),
+            Self::Random => (NONCE_LENGTH * 8) as f64,
+        }
+    }
+}
+
+/// A message encrypted in memory by [`CommonEncryptionOpts::encrypt_message`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Encrypted {
+    /// The contents the encrypted file would have, header included and in the chosen format.
+    pub ciphertext: Vec<u8>,
+    /// The nonce the message was encrypted under, whether it was generated or given.
+    pub nonce: XNonce,
+    /// The AEAD the message was encrypted with.
+    pub algorithm: SimpleCipherAlgorithm,
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Clone, Debug, Default)]
 pub struct CommonEncryptionOpts {
-    #[arg(short, long)]
+    #[arg(short, long, group = "key-source", required = true)]
     /// This is an encryption key. It must be less than 32 characters long.
-    key: String,
+    key: Option<Zeroizing<String>>,
+
+    #[arg(long, group = "key-source", required = true)]
+    /// This is a file holding the encryption key, so it is kept out of the shell history. A
+    /// trailing newline is not part of the key.
+    key_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        group = "key-source",
+        required = true,
+        value_name = "VAR",
+        num_args = 0..=1,
+        default_missing_value = "SKE_KEY"
+    )]
+    /// This is an environment variable holding the encryption key, SKE_KEY if no name is given,
+    /// for CI pipelines which provide secrets that way.
+    key_env: Option<String>,
+
+    #[arg(long, group = "key-source", required = true)]
+    /// This is a flag to type the key at a prompt on the terminal, without echo, so it is kept
+    /// out of process listings and the shell history. Encryption asks for it twice.
+    prompt: bool,
+
+    #[arg(long = "key-share", group = "key-source", required = true)]
+    /// This is one of the shares of a key split with `split-key`. Pass it once per share, at
+    /// least as many times as the threshold the key was split with.
+    key_shares: Vec<String>,
+
+    #[arg(long, value_enum, default_value_t, conflicts_with = "kdf")]
+    /// This is how --key and --previous-key are read. A hex key is already a full 32 byte key so
+    /// it is used without a KDF.
+    key_format: KeyFormat,
+
+    #[arg(long)]
+    /// This is a flag to refuse a --key-format base64 key shorter than 32 bytes rather than pad
+    /// it with zero bytes.
+    strict_key_length: bool,
+
+    #[arg(long, conflicts_with = "kdf")]
+    /// This is a flag to use the key, once decoded with --key-format, as the 32 byte key exactly
+    /// as it is, refusing a key of any other length rather than padding it or using a KDF.
+    raw_key: bool,
+
+    #[arg(long, default_value_t = 0, value_name = "BYTES")]
+    /// This is the shortest key accepted, in bytes before padding or a KDF, to enforce a key
+    /// policy. Shorter keys are refused rather than padded.
+    min_key_length: usize,
+
+    #[arg(long = "previous-key")]
+    /// This is an earlier key to try when decryption with the key fails, after rotating keys.
+    /// Pass it once per key, and they are tried in order.
+    previous_keys: Vec<Zeroizing<String>>,
+
+    #[arg(long)]
+    /// This is the number of keys which may fail to decrypt before giving up on any remaining
+    /// --previous-key, as a safety valve for a very long list. Unlimited by default.
+    max_decrypt_attempts: Option<NonZeroUsize>,
 
     #[arg(short, long, default_value = "data.dat")]
     /// This is the file which an message is encrypted/decrypted to/from.
     encrypted_file: PathBuf,
 
-    #[arg(long, group = "nonce-choice", required = true)]
-    /// **NOT RECOMMENDED:** This is a helper option to enable the nonce be all zeros. This results
-    /// in the encrypted message be the same on every encryption and subject to a replay attacks.
-    no_nonce: bool,
-
-    #[arg(short, long, group = "nonce-choice", required = true)]
-    /// This is a flag to enable a newly generated nonce on encryption. This will error when used
-    /// on decryption.
-    generate_nonce: bool,
-
-    #[arg(short, long, group = "nonce-choice", required = true)]
-    /// This is the string representation of a nonce as ascii characters up to
-    /// 24 characters in length. This is required for decryption unless using
-    /// the unrecommended --no-nonce feature.
-    nonce: Option<String>,
+    #[command(flatten)]
+    nonce: NonceSource,
+
+    #[arg(long, requires = "generate_nonce")]
+    /// This is a flag to print a generated nonce for decryption to be given with --nonce,
+    /// rather than embedding it in the header of the encrypted file. Streamed files always
+    /// have their nonce printed.
+    detached_nonce: bool,
+
+    #[arg(long, value_enum, default_value_t)]
+    /// This is how --nonce is read and how a generated nonce is printed. Nonces shorter than 24
+    /// bytes are padded with zero bytes.
+    nonce_encoding: NonceEncoding,
+
+    #[arg(long, requires = "nonce")]
+    /// This is a flag to reject a hex or base64 --nonce which is not exactly how the nonce would
+    /// be printed, such as base64 with unused bits set, so every nonce has a single spelling.
+    canonical_nonce: bool,
+
+    #[arg(long, requires = "nonce")]
+    /// This is a flag to reject a --nonce shorter than 24 bytes rather than padding it with zero
+    /// bytes, so a mistyped short nonce is caught instead of failing to decrypt.
+    strict_nonce: bool,
+
+    #[arg(long, value_enum, default_value_t, requires = "generate_nonce")]
+    /// This is the method used to generate a nonce with --generate-nonce. Random nonces cover
+    /// the whole nonce space and are printed as hex; legacy nonces are lower case letters.
+    nonce_generator: NonceGenerator,
+
+    #[arg(long, requires = "generate_nonce")]
+    /// This prints a generated hex nonce in space separated groups of this many characters so it
+    /// is easier to copy by hand. Whitespace is ignored when a hex nonce is given to --nonce.
+    nonce_display_width: Option<NonZeroUsize>,
+
+    #[arg(long, value_enum, default_value_t)]
+    /// This is how the key is turned into the 32 byte encryption key. The KDF is recorded in the
+    /// encrypted file so decryption uses the one it was encrypted with.
+    kdf: Kdf,
+
+    #[arg(long, value_enum, default_value_t)]
+    /// This is the hash used to reduce the key to 32 bytes with --kdf hash.
+    key_hash_algorithm: KeyHashAlgorithm,
+
+    #[arg(long)]
+    /// This is the memory in KiB used by --kdf argon2. Defaults to 19 MiB.
+    argon2_memory: Option<u32>,
+
+    #[arg(long)]
+    /// This is the number of passes over its memory made by --kdf argon2. Defaults to 2.
+    argon2_iterations: Option<u32>,
+
+    #[arg(long)]
+    /// This is the base 2 logarithm of the cost N used by --kdf scrypt. Defaults to 15.
+    scrypt_log_n: Option<u8>,
+
+    #[arg(long)]
+    /// This is the block size r used by --kdf scrypt. Defaults to 8.
+    scrypt_r: Option<u32>,
+
+    #[arg(long)]
+    /// This is the parallelism p used by --kdf scrypt. Defaults to 1.
+    scrypt_p: Option<u32>,
+
+    #[arg(long, visible_alias = "iterations")]
+    /// This is the number of iterations of --kdf pbkdf2. Defaults to 600000.
+    pbkdf2_iterations: Option<NonZeroU32>,
+
+    #[arg(long, value_name = "MB")]
+    /// This caps the address space of the process before the key is derived so a KDF asking for
+    /// too much memory fails with an error rather than exhausting the machine. Unix only.
+    limit_memory: Option<u64>,
+
+    #[arg(long, value_enum, visible_alias = "input-format")]
+    /// This is the encoding of the encrypted file. Encryption writes raw bytes unless a format is
+    /// given. Decryption detects the format from the file contents unless a format is given.
+    format: Option<CiphertextFormat>,
+
+    #[arg(long, conflicts_with_all = ["format", "stream"])]
+    /// This is a flag to write the encrypted file as base64 between SKE MESSAGE markers, the same
+    /// as --format pem, for pasting into email or chat. Decryption detects the armor without it.
+    armor: bool,
+
+    #[arg(long, value_enum)]
+    /// This is the AEAD the message is encrypted with, xchacha20poly1305 unless given.
+    /// Decryption reads it from the header, and refuses an algorithm given that differs.
+    algorithm: Option<SimpleCipherAlgorithm>,
+
+    #[arg(long, value_enum, default_value_t, conflicts_with = "stream")]
+    /// This is where the authentication tag is placed in the ciphertext, for interoperating with
+    /// implementations which expect it first. It is not recorded in the encrypted file so
+    /// decryption needs the same option.
+    tag_position: TagPosition,
+
+    #[arg(long, value_enum, default_value_t, conflicts_with = "stream")]
+    /// This is how the message is compressed before it is encrypted. The method is recorded in
+    /// the header so decryption decompresses without it. The length of the encrypted file then
+    /// shows how compressible the message was, which can leak its contents to an attacker who
+    /// influences part of it.
+    compress: Compression,
+
+    #[arg(long)]
+    /// This is associated data authenticated along with the message but not encrypted or
+    /// stored, such as the context a file belongs to. Decryption fails unless it is given the
+    /// same associated data. Streamed files authenticate it with every chunk.
+    aad: Option<String>,
+
+    #[arg(long, conflicts_with = "format")]
+    /// This is a flag to encrypt the message in fixed size chunks rather than in one shot so
+    /// large inputs never need to be held in memory. Decryption detects streamed files itself.
+    stream: bool,
+
+    #[arg(
+        long,
+        requires = "stream",
+        value_parser = clap::value_parser!(u32).range(1..=i64::from(stream::MAX_CHUNK_SIZE))
+    )]
+    /// This is the number of plaintext bytes in each streamed chunk. Defaults to 64 KiB, and may
+    /// be at most 16 MiB.
+    chunk_size: Option<u32>,
+
+    #[arg(long, requires = "stream")]
+    /// This is the number of streamed chunks encrypted before a fresh sub-key is derived from the
+    /// key. This bounds the amount of data encrypted under any single key and nonce.
+    rekey_interval: Option<u32>,
+
+    #[arg(long, requires = "stream")]
+    /// This is the number of worker threads encrypting streamed chunks concurrently. Chunks are
+    /// still written in order so the encrypted file is the same as without this option.
+    max_concurrency: Option<NonZeroUsize>,
+
+    #[arg(long, requires = "stream", conflicts_with = "max_concurrency")]
+    /// This is a flag to encrypt streamed chunks on one worker thread per available core, like
+    /// --max-concurrency with the number of cores.
+    parallel: bool,
+
+    #[arg(long, value_name = "BYTES")]
+    /// This writes the encrypted file as numbered parts of at most this many bytes, such as
+    /// data.dat.001 and data.dat.002, for channels which limit the size of a file. Decryption
+    /// reads the parts back in order when the whole file is missing.
+    split_output_size: Option<NonZeroU64>,
+
+    #[arg(long)]
+    /// This is a flag to create the directory of the encrypted file, including any missing
+    /// parent directories, before encrypting.
+    mkdir: bool,
+
+    #[arg(long)]
+    /// This is a flag to overwrite an encrypted file which already exists, which is refused
+    /// otherwise so running the tool twice does not lose the earlier file.
+    force: bool,
+
+    #[arg(long)]
+    /// This is a flag to read the encrypted file back and decrypt it after encrypting, failing if
+    /// it does not give back the message. This doubles the work of encrypting.
+    verify_after_encrypt: bool,
+
+    #[arg(long)]
+    /// This is a flag to encrypt a message even though it is itself the contents of an encrypted
+    /// file. Without it encryption refuses, as this is usually a mistake.
+    allow_double_encrypt: bool,
+
+    #[arg(long)]
+    /// This is a flag to print the fingerprint of the key actually used, after padding or the
+    /// KDF, to standard error so two parties can check they share a key without revealing it.
+    show_fingerprint: bool,
+
+    #[arg(long)]
+    /// This is the key fingerprint decryption must derive, as printed by
+    /// --prompt-confirm-key-fingerprint, failing before anything is decrypted otherwise.
+    expect_key_fingerprint: Option<String>,
+
+    #[arg(long)]
+    /// This is a flag to show the fingerprint of the key and the key id the encrypted file
+    /// records, and ask for confirmation before decrypting. Without a terminal to ask it fails
+    /// unless --expect-key-fingerprint confirms the key instead.
+    prompt_confirm_key_fingerprint: bool,
+
+    #[arg(long, value_name = "SECONDS")]
+    /// This gives up with an error if an interactive prompt, such as the one of
+    /// --prompt-confirm-key-fingerprint or either passphrase prompt of --prompt, is not answered
+    /// within this many seconds, so an unattended run fails fast rather than waiting.
+    key_prompt_confirm_timeout: Option<u64>,
+
+    #[arg(short = 'y', long)]
+    /// This is a flag to answer yes to every yes or no prompt, such as the one of
+    /// --prompt-confirm-key-fingerprint, without reading standard input. It never supplies a
+    /// key or passphrase.
+    assume_yes: bool,
+
+    #[arg(long)]
+    /// This is a flag to display only the category of an error, omitting paths, lengths and any
+    /// other details, for scripts whose logs are shipped elsewhere.
+    redact: bool,
+
+    #[arg(long)]
+    /// This is a flag to print a rough estimate of the entropy of --key before encrypting, with
+    /// a warning when it is low. The key itself is never printed.
+    show_entropy_estimate: bool,
+
+    #[arg(long, requires = "show_entropy_estimate")]
+    /// This is a flag to refuse to encrypt when the entropy estimate is low instead of warning.
+    strict: bool,
+
+    #[arg(long, conflicts_with = "stream")]
+    /// This is a flag to allocate the whole encrypted file once and encrypt the message in place
+    /// within it, rather than copying the ciphertext into the file, for large messages.
+    prealloc: bool,
+
+    #[arg(long, conflicts_with = "stream")]
+    /// This is a flag to record a commitment to the key in the header, which decryption checks
+    /// before the AEAD, as XChaCha20Poly1305 alone does not rule out a ciphertext which
+    /// authenticates under a second key. Streamed files have no header to record it in. On
+    /// decryption it refuses a file without a commitment.
+    key_committing: bool,
+
+    #[arg(long)]
+    /// This is a flag to decrypt a file which does not start with the file format magic as bare
+    /// ciphertext from before the header existed, rather than refusing it.
+    legacy: bool,
+
+    #[arg(long)]
+    /// This is a flag to retry reading or writing the encrypted file a few times, backing off in
+    /// between, while another process such as a virus scanner holds it.
+    retry_on_busy: bool,
+
+    #[arg(long)]
+    /// This is a flag to never color errors and warnings. Color is also off when standard error
+    /// is not a terminal or the NO_COLOR environment variable is set.
+    no_color: bool,
+
+    #[arg(long, hide = true)]
+    /// **TESTS ONLY, NEVER FOR REAL DATA:** This seeds the generator of nonces and salts with
+    /// this number so runs can be reproduced exactly. Every run with the same seed reuses the
+    /// same nonce.
+    test_seed: Option<u64>,
+
+    // This is set by `with_rng`, otherwise randomness comes from the operating system.
+    #[arg(skip)]
+    rng: Option<rng::InjectedRng>,
 }
 impl CommonEncryptionOpts {
     pub fn encrypt(&self, message: String) -> Result<Option<String>, SimpleCipherError> {
-        let key = self.get_key_from_string()?;
-        let nonce = self.nonce()?;
+        self.write_encrypted(message.as_bytes())
+    }
+
+    /// Encrypts `message` like [`CommonEncryptionOpts::encrypt`] for a message which need not
+    /// be text.
+    pub fn write_encrypted(&self, message: &[u8]) -> Result<Option<String>, SimpleCipherError> {
+        self.check_not_overwriting()?;
+        self.prepare_output_dir()?;
+        let mut file = self.create_encrypted_file()?;
+        let nonce = self.encrypt_with_nonce_to(message, &mut file)?;
+        file.commit()?;
+        if self.verify_after_encrypt {
+            self.verify_written(&nonce, message)?;
+        }
+        self.generated_nonce(&nonce)
+    }
+
+    /// Encrypts `message` like [`CommonEncryptionOpts::write_encrypted`] to `writer`, such as a
+    /// pipe, a socket or a buffer, rather than to the encrypted file.
+    pub fn encrypt_to_writer<W: Write>(
+        &self,
+        message: &[u8],
+        writer: &mut W,
+    ) -> Result<Option<String>, SimpleCipherError> {
+        let nonce = self.encrypt_with_nonce_to(message, writer)?;
+        self.generated_nonce(&nonce)
+    }
 
-        let cipher = XChaCha20Poly1305::new(&key);
-        let ciphertext = cipher.encrypt(&nonce, message.into_bytes().as_ref())?;
-        fs::write(&self.encrypted_file, ciphertext)?;
-        if self.generate_nonce {
-            Ok(Some(Self::stringify_nonce(&nonce)))
+    // This is `encrypt_to_writer` returning the nonce itself, for checking what was written.
+    fn encrypt_with_nonce_to<W: Write>(
+        &self,
+        message: &[u8],
+        writer: &mut W,
+    ) -> Result<XNonce, SimpleCipherError> {
+        let Encrypted {
+            ciphertext, nonce, ..
+        } = self.encrypt_message(message)?;
+        writer.write_all(&ciphertext)?;
+        Ok(nonce)
+    }
+
+    /// Encrypts `plaintext` without touching the encrypted file, returning the contents the file
+    /// would have along with the nonce, for [`CommonEncryptionOpts::decrypt_bytes`].
+    pub fn encrypt_bytes(&self, plaintext: &[u8]) -> Result<(Vec<u8>, XNonce), SimpleCipherError> {
+        let encrypted = self.encrypt_message(plaintext)?;
+        Ok((encrypted.ciphertext, encrypted.nonce))
+    }
+
+    /// Encrypts `plaintext` like [`CommonEncryptionOpts::encrypt_bytes`], returning the nonce
+    /// and algorithm along with the contents whether or not the nonce was generated.
+    pub fn encrypt_message(&self, plaintext: &[u8]) -> Result<Encrypted, SimpleCipherError> {
+        self.check_not_encrypted(plaintext)?;
+        let format = self.format().unwrap_or(CiphertextFormat::Raw);
+        let (contents, nonce) = if self.stream {
+            let mut contents = Vec::new();
+            let nonce = self.encrypt_stream_with_nonce(&mut &plaintext[..], &mut contents)?;
+            (contents, nonce)
+        } else if self.prealloc {
+            self.seal_framed(plaintext)?
         } else {
-            Ok(None)
+            let (ciphertext, nonce, header) = self.seal(plaintext)?;
+            (frame(&ciphertext, &header), nonce)
+        };
+        let ciphertext = match format {
+            CiphertextFormat::Raw => contents,
+            format => format.encode(&contents),
+        };
+        Ok(Encrypted {
+            ciphertext,
+            nonce,
+            algorithm: self.algorithm(),
+        })
+    }
+
+    // This reads the encrypted file back and checks it decrypts to `plaintext`.
+    fn verify_written(&self, nonce: &XNonce, plaintext: &[u8]) -> Result<(), SimpleCipherError> {
+        let contents = self.read_encrypted_file()?;
+        if self.open(Some(nonce), &contents)? != plaintext {
+            return Err(SimpleCipherError::VerifyMismatch);
+        }
+        Ok(())
+    }
+
+    /// Decrypts the encrypted file, returning the plaintext exactly as it was encrypted, which
+    /// need not be text.
+    pub fn decrypt(&self) -> Result<Vec<u8>, SimpleCipherError> {
+        // The file is read rather than opened so split parts and --retry-on-busy apply.
+        let contents = self.read_encrypted_file()?;
+        self.decrypt_from_reader(&mut contents.as_slice())
+    }
+
+    /// Decrypts everything read from `reader`, such as a pipe, a socket or a buffer, like
+    /// [`CommonEncryptionOpts::decrypt`] decrypts the encrypted file.
+    pub fn decrypt_from_reader<R: Read>(
+        &self,
+        reader: &mut R,
+    ) -> Result<Vec<u8>, SimpleCipherError> {
+        if self.nonce.is_generated() {
+            return Err(SimpleCipherError::NonceGenerate);
+        }
+        let nonce = self.decryption_nonce()?;
+
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+        match nonce {
+            Some(nonce) => self.decrypt_bytes(&contents, &nonce),
+            // The nonce is embedded in the header, or missing, which is reported for the file.
+            None => self.open_with_any_key(None, &contents),
+        }
+    }
+
+    /// Decrypts `contents` returned by [`CommonEncryptionOpts::encrypt_bytes`] with its nonce,
+    /// which is only used if the contents do not embed one, without reading the encrypted file.
+    pub fn decrypt_bytes(
+        &self,
+        contents: &[u8],
+        nonce: &XNonce,
+    ) -> Result<Vec<u8>, SimpleCipherError> {
+        self.open_with_any_key(Some(nonce), contents)
+    }
+
+    /// Decrypts the encrypted file like [`CommonEncryptionOpts::decrypt`] for a message which
+    /// was text, failing if the plaintext is not UTF-8.
+    pub fn decrypt_string(&self) -> Result<String, SimpleCipherError> {
+        Ok(String::from_utf8(self.decrypt()?)?)
+    }
+
+    // This encrypts the plaintext in one shot and returns the ciphertext along with the nonce
+    // and the header recording the KDF parameters and key used.
+    fn seal(&self, plaintext: &[u8]) -> Result<(Vec<u8>, XNonce, Header), SimpleCipherError> {
+        self.seal_with(plaintext, false, |nonce| *nonce)
+    }
+
+    // This is `seal` with the ciphertext encrypted under `nonce_for` of the nonce, such as the
+    // nonce of a segment of a log, which is embedded in the header if `embed_nonce` or the
+    // options embed it. The nonce returned is the nonce before `nonce_for`.
+    fn seal_with<F>(
+        &self,
+        plaintext: &[u8],
+        embed_nonce: bool,
+        nonce_for: F,
+    ) -> Result<(Vec<u8>, XNonce, Header), SimpleCipherError>
+    where
+        F: FnOnce(&XNonce) -> XNonce,
+    {
+        let (cipher, nonce, header) = self.sealer()?;
+        let sealed_nonce = nonce_for(&nonce);
+        let embedded = embed_nonce || header.nonce.is_some();
+        let header = Header {
+            nonce: embedded.then(|| sealed_nonce.into()),
+            ..header
+        };
+        let ciphertext = cipher.seal(&sealed_nonce, &header, plaintext)?;
+        let header = Header {
+            checksum: Some(Checksum::of(&ciphertext)),
+            ..header
+        };
+        Ok((ciphertext, nonce, header))
+    }
+
+    // This is `frame` of `seal` for --prealloc, see `Cipher::seal_framed`.
+    fn seal_framed(&self, plaintext: &[u8]) -> Result<(Vec<u8>, XNonce), SimpleCipherError> {
+        let (cipher, nonce, header) = self.sealer()?;
+        Ok((cipher.seal_framed(&nonce, header, plaintext)?, nonce))
+    }
+
+    /// The [`Cipher`] these options encrypt with, deriving the key from the key string with
+    /// fresh KDF parameters.
+    pub fn cipher(&self) -> Result<Cipher, SimpleCipherError> {
+        let kdf = self.kdf_params()?;
+        let key = self.get_key_from_string(&kdf)?;
+        self.print_fingerprint(&key);
+        Ok(Cipher::new(key)
+            .with_algorithm(self.algorithm())
+            .with_kdf(kdf)
+            .with_key_commitment(self.key_committing)
+            .with_tag_position(self.tag_position)
+            .with_compression(self.compress)
+            .with_aad(self.aad()))
+    }
+
+    // This builds the cipher and picks the nonce for a one shot encryption, and fills in the
+    // header but for the checksum of the ciphertext.
+    fn sealer(&self) -> Result<(Cipher, XNonce, Header), SimpleCipherError> {
+        let cipher = self.cipher()?;
+        let nonce = self.nonce()?;
+        let header = cipher.header(self.embeds_nonce().then_some(&nonce));
+        Ok((cipher, nonce, header))
+    }
+
+    // This decrypts the contents of an encrypted file, whether it was streamed or written in
+    // one shot in any of the ciphertext formats.
+    fn open(&self, nonce: Option<&XNonce>, contents: &[u8]) -> Result<Vec<u8>, SimpleCipherError> {
+        if contents.starts_with(stream::STREAM_MAGIC) {
+            // Streams have no header to embed a nonce in.
+            let nonce = nonce.ok_or(SimpleCipherError::NonceChoiceUndeteremined)?;
+            check_algorithm(self.algorithm, SimpleCipherAlgorithm::XChaCha20Poly1305)?;
+            self.check_stream_commitment()?;
+            let key = self.key_for(None)?;
+            let mut plaintext = Vec::new();
+            stream::decrypt_stream(&key, nonce, self.aad(), &mut &contents[..], &mut plaintext)?;
+            return Ok(plaintext);
+        }
+        let decoded = match self.format() {
+            Some(format) => format.decode(contents)?,
+            None => CiphertextFormat::decode_detected(contents),
+        };
+        if decoded.starts_with(header::MAGIC) {
+            return self.open_segments(nonce, &decoded);
+        }
+        if !self.legacy {
+            return Err(SimpleCipherError::BadMagic);
+        }
+        unframe_and_decrypt(
+            &decoded,
+            nonce,
+            self.algorithm,
+            self.tag_position,
+            self.aad(),
+            self.key_committing,
+            |kdf| self.key_for(kdf),
+        )
+    }
+
+    // Streams have no header to record a key commitment in, so --key-committing refuses them.
+    fn check_stream_commitment(&self) -> Result<(), SimpleCipherError> {
+        if self.key_committing {
+            return Err(SimpleCipherError::KeyCommitmentMissing);
+        }
+        Ok(())
+    }
+
+    // This is `open` with the key, then each --previous-key in turn until one decrypts the
+    // contents. Only a wrong key moves on to the next one, and at most --max-decrypt-attempts
+    // keys are tried.
+    fn open_with_any_key(
+        &self,
+        nonce: Option<&XNonce>,
+        contents: &[u8],
+    ) -> Result<Vec<u8>, SimpleCipherError> {
+        let mut failures = 0;
+        let mut last_error = match self.open(nonce, contents) {
+            Err(e) if e.is_wrong_key() => e,
+            result => return result,
+        };
+        for key in &self.previous_keys {
+            failures += 1;
+            if self
+                .max_decrypt_attempts
+                .is_some_and(|max| failures >= max.get())
+            {
+                return Err(SimpleCipherError::TooManyKeyAttempts(failures));
+            }
+            let previous = CommonEncryptionOpts {
+                key: Some(key.clone()),
+                key_file: None,
+                key_env: None,
+                key_shares: Vec::new(),
+                ..self.clone()
+            };
+            last_error = match previous.open(nonce, contents) {
+                Err(e) if e.is_wrong_key() => e,
+                result => return result,
+            };
+        }
+        Err(last_error)
+    }
+
+    // This derives the key with the KDF parameters recorded in a header, or those given on the
+    // command line for a file without a header, and checks its fingerprint if asked to.
+    fn key_for(&self, header: Option<&Header>) -> Result<Zeroizing<Key>, SimpleCipherError> {
+        let key = self.recorded_key(header)?;
+        self.confirm_key(&key, header.and_then(|header| header.key_id))?;
+        Ok(key)
+    }
+
+    // This is `key_for` without the fingerprint check, for the segments of a log after the
+    // first.
+    fn recorded_key(&self, header: Option<&Header>) -> Result<Zeroizing<Key>, SimpleCipherError> {
+        match header {
+            Some(header) => self.get_key_from_string(&header.kdf),
+            None => self.get_key_from_string(&self.kdf_params()?),
+        }
+    }
+
+    // This prints the fingerprint of a key for --show-fingerprint.
+    fn print_fingerprint(&self, key: &Key) {
+        if self.show_fingerprint {
+            eprintln!("Key fingerprint {}", key_fingerprint(key));
+        }
+    }
+
+    // This is the gate of --expect-key-fingerprint and --prompt-confirm-key-fingerprint, which
+    // runs before any decryption.
+    fn confirm_key(
+        &self,
+        key: &Key,
+        recorded: Option<kdf::KeyId>,
+    ) -> Result<(), SimpleCipherError> {
+        self.print_fingerprint(key);
+        let fingerprint = key_fingerprint(key);
+        if let Some(expected) = &self.expect_key_fingerprint {
+            let expected: String = expected.split_whitespace().collect();
+            // A matching expected fingerprint confirms the key without asking.
+            if !expected.eq_ignore_ascii_case(&fingerprint) {
+                return Err(SimpleCipherError::KeyFingerprintMismatch {
+                    expected,
+                    actual: fingerprint,
+                });
+            }
+            return Ok(());
+        }
+        if !self.prompt_confirm_key_fingerprint {
+            return Ok(());
+        }
+        if !self.assume_yes && !io::stdin().is_terminal() {
+            return Err(SimpleCipherError::KeyNotConfirmed);
+        }
+        let recorded = recorded.map_or("not recorded".to_string(), hex::encode);
+        eprint!("Key fingerprint {fingerprint}\nFile key id     {recorded}\nDecrypt? [y/N] ");
+        if self.assume_yes {
+            eprintln!("y");
+            return Ok(());
+        }
+        let timeout = self.key_prompt_confirm_timeout.map(Duration::from_secs);
+        let answer = input::read_line(io::BufReader::new(io::stdin()), timeout)?;
+        match answer.trim() {
+            "y" | "Y" | "yes" => Ok(()),
+            _ => Err(SimpleCipherError::KeyNotConfirmed),
+        }
+    }
+
+    /// Refuses a message which is already the contents of an encrypted file unless
+    /// `--allow-double-encrypt` was given.
+    pub fn check_not_encrypted(&self, message: &[u8]) -> Result<(), SimpleCipherError> {
+        if !self.allow_double_encrypt && header::is_encrypted(message) {
+            return Err(SimpleCipherError::AlreadyEncrypted);
+        }
+        Ok(())
+    }
+
+    // The nonce is only handed back to the user when it was generated and not embedded in the
+    // header, as otherwise they already know it or decryption reads it from the file.
+    fn generated_nonce(&self, nonce: &XNonce) -> Result<Option<String>, SimpleCipherError> {
+        if self.embeds_nonce() {
+            return Ok(None);
+        }
+        self.detached_nonce(nonce)
+    }
+
+    // This is `generated_nonce` for a stream, which always has its nonce detached. A nonce
+    // written to --nonce-file is not printed as well.
+    fn detached_nonce(&self, nonce: &XNonce) -> Result<Option<String>, SimpleCipherError> {
+        if !self.nonce.is_generated() {
+            return Ok(None);
+        }
+        let nonce = &nonce[..self.algorithm().nonce_length()];
+        if let NonceSource::GeneratedToFile(nonce_file) = &self.nonce {
+            fs::write(nonce_file, nonce)?;
+            return Ok(None);
+        }
+        let encoded = self.encode_nonce(nonce);
+        // An ascii nonce is printed as it is, since its whitespace would be part of it.
+        let ascii = self.nonce_encoding == NonceEncoding::Text && encoded.len() < 2 * NONCE_LENGTH;
+        Ok(match self.nonce_display_width {
+            Some(width) if !ascii => Some(group(&encoded, width.get())),
+            _ => Some(encoded),
+        })
+    }
+
+    // This is the nonce as it is printed with --nonce-encoding.
+    fn encode_nonce(&self, nonce: &[u8]) -> String {
+        match self.nonce_encoding {
+            NonceEncoding::Text => Self::stringify_nonce(nonce),
+            NonceEncoding::Hex => hex::encode(nonce),
+            NonceEncoding::Base64 => BASE64.encode(nonce),
+            NonceEncoding::Base64Url => URL_SAFE_NO_PAD.encode(nonce),
+        }
+    }
+
+    // This reads --nonce with --nonce-encoding.
+    fn parse_nonce(&self, nonce: &str) -> Result<XNonce, SimpleCipherError> {
+        let compact: String = nonce.split_whitespace().collect();
+        let decoded = match self.nonce_encoding {
+            NonceEncoding::Text => {
+                let parsed = Self::nonce_from_string(nonce.to_string())?;
+                self.check_nonce_length(self.text_nonce_length(nonce))?;
+                return Ok(parsed);
+            }
+            NonceEncoding::Hex => hex::decode(&compact)?,
+            // Either base64 alphabet is accepted, whichever --nonce-encoding printed the nonce.
+            NonceEncoding::Base64 | NonceEncoding::Base64Url => {
+                encoding::decode_base64(&LENIENT_BASE64, compact.as_bytes())?
+            }
+        };
+        self.check_nonce_length(decoded.len())?;
+        let canonical = match self.nonce_encoding {
+            NonceEncoding::Base64 => BASE64.encode(&decoded),
+            NonceEncoding::Base64Url => URL_SAFE_NO_PAD.encode(&decoded),
+            _ => hex::encode(&decoded),
+        };
+        // Whitespace is left out of the comparison, as --nonce-display-width prints a nonce in
+        // groups.
+        if self.canonical_nonce && canonical != compact {
+            return Err(SimpleCipherError::NonCanonicalNonce);
+        }
+        let mut padded = [0_u8; NONCE_LENGTH];
+        padded[..decoded.len()].copy_from_slice(&decoded);
+        Ok(padded.into())
+    }
+
+    // Nonces longer than the algorithm's are refused, and short nonces are padded unless
+    // --strict-nonce was given.
+    fn check_nonce_length(&self, length: usize) -> Result<(), SimpleCipherError> {
+        let nonce_length = self.algorithm().nonce_length();
+        if length > nonce_length {
+            return Err(SimpleCipherError::NonceTooLong(length));
+        }
+        if self.strict_nonce && length < nonce_length {
+            return Err(SimpleCipherError::NonceTooShort(length));
+        }
+        Ok(())
+    }
+
+    // This is the number of bytes a text --nonce decodes to, a byte for each character. Hex of
+    // a whole held nonce is as long as the algorithm's nonce if the bytes past it are zero.
+    fn text_nonce_length(&self, nonce: &str) -> usize {
+        let compact: String = nonce.split_whitespace().collect();
+        match hex::decode(&compact) {
+            Ok(decoded) if decoded.len() == NONCE_LENGTH => {
+                let nonce_length = self.algorithm().nonce_length();
+                match decoded[nonce_length..].iter().all(|byte| *byte == 0) {
+                    true => nonce_length,
+                    false => NONCE_LENGTH,
+                }
+            }
+            _ => nonce.chars().count(),
+        }
+    }
+
+    /// Encrypts everything read from `reader` to `writer` using the chunked stream format.
+    pub fn encrypt_stream<R: Read, W: Write>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<Option<String>, SimpleCipherError> {
+        let nonce = self.encrypt_stream_with_nonce(reader, writer)?;
+        self.detached_nonce(&nonce)
+    }
+
+    /// Streams everything from `reader`, such as standard input, to the encrypted file without
+    /// knowing its length in advance. Each chunk is written as soon as it has been read and the
+    /// last chunk is marked when `reader` reaches its end.
+    pub fn encrypt_reader<R: Read>(
+        &self,
+        reader: &mut R,
+    ) -> Result<Option<String>, SimpleCipherError> {
+        self.check_not_overwriting()?;
+        self.prepare_output_dir()?;
+        let mut file = self.create_encrypted_file()?;
+        let nonce = self.encrypt_stream(reader, &mut file)?;
+        file.commit()?;
+        Ok(nonce)
+    }
+
+    /// Encrypts the message file at `path`. With `--stream` it is read a chunk at a time, so a
+    /// file of any size is encrypted without being held in memory whole. A file which is
+    /// already encrypted is refused as a message would be.
+    pub fn encrypt_file(&self, path: &Path) -> Result<Option<String>, SimpleCipherError> {
+        let mut reader = io::BufReader::new(fs::File::open(path)?);
+        self.check_not_encrypted(reader.fill_buf()?)?;
+        if self.stream {
+            return self.encrypt_reader(&mut reader);
+        }
+        let mut message = Vec::new();
+        reader.read_to_end(&mut message)?;
+        self.write_encrypted(&message)
+    }
+
+    /// Whether the encrypted file is streamed, going by its first bytes. A missing file is not.
+    pub fn is_streamed(&self) -> Result<bool, SimpleCipherError> {
+        let mut magic = [0_u8; stream::STREAM_MAGIC.len()];
+        match fs::File::open(&self.encrypted_file) {
+            Ok(file) => Ok(
+                file.take(magic.len() as u64).read(&mut magic)? == magic.len()
+                    && &magic == stream::STREAM_MAGIC,
+            ),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Creates `path` for the decrypted message. Nothing replaces it until the file is
+    /// committed, so a failed decryption leaves it as it was, and the encrypted file itself is
+    /// refused as its output.
+    pub fn create_output(&self, path: &Path) -> Result<AtomicFile, SimpleCipherError> {
+        let same = match (
+            fs::canonicalize(path),
+            fs::canonicalize(&self.encrypted_file),
+        ) {
+            (Ok(output), Ok(encrypted_file)) => output == encrypted_file,
+            _ => path == self.encrypted_file,
+        };
+        if same {
+            return Err(SimpleCipherError::OutputIsEncryptedFile(path.to_path_buf()));
+        }
+        Ok(AtomicFile::create(path, self.retry_on_busy)?)
+    }
+
+    /// Decrypts the encrypted file to `writer`. A streamed file is decrypted a chunk at a time
+    /// so it is never held in memory whole. Every chunk is authenticated before it is written,
+    /// but a stream which fails part way leaves the chunks before the failure written. Other
+    /// files, and streams with --previous-key to try, are decrypted in memory.
+    pub fn decrypt_file_to<W: Write>(&self, writer: &mut W) -> Result<(), SimpleCipherError> {
+        if self.nonce.is_generated() {
+            return Err(SimpleCipherError::NonceGenerate);
         }
+        let nonce = self.decryption_nonce()?;
+        if !self.is_streamed()? || !self.previous_keys.is_empty() {
+            let contents = self.read_encrypted_file()?;
+            writer.write_all(&self.open_with_any_key(nonce.as_ref(), &contents)?)?;
+            return Ok(writer.flush()?);
+        }
+        let file = retry_if_busy(self.retry_on_busy, || fs::File::open(&self.encrypted_file))?;
+        self.decrypt_stream(&mut io::BufReader::new(file), writer)
+    }
+
+    fn encrypt_stream_with_nonce<R: Read, W: Write>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<XNonce, SimpleCipherError> {
+        let kdf = self.kdf_params()?;
+        if kdf.needs_header() {
+            return Err(SimpleCipherError::StreamKdf);
+        }
+        if self.algorithm() != SimpleCipherAlgorithm::XChaCha20Poly1305 {
+            return Err(SimpleCipherError::StreamAlgorithm(self.algorithm()));
+        }
+        if self.compress != Compression::None {
+            return Err(SimpleCipherError::StreamCompression);
+        }
+        let key = self.get_key_from_string(&kdf)?;
+        self.print_fingerprint(&key);
+        let nonce = self.nonce()?;
+        let params = self.stream_params();
+        match self.workers() {
+            Some(workers) => stream::encrypt_stream_parallel(
+                &key,
+                &nonce,
+                self.aad(),
+                &params,
+                workers,
+                reader,
+                writer,
+            )?,
+            None => stream::encrypt_stream(&key, &nonce, self.aad(), &params, reader, writer)?,
+        }
+        Ok(nonce)
+    }
+
+    /// Decrypts a stream written by `encrypt_stream` from `reader` to `writer`, with the same
+    /// checks of the key as any other decryption.
+    pub fn decrypt_stream<R: Read, W: Write>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<(), SimpleCipherError> {
+        if self.nonce.is_generated() {
+            return Err(SimpleCipherError::NonceGenerate);
+        }
+        let nonce = self
+            .decryption_nonce()?
+            .ok_or(SimpleCipherError::NonceChoiceUndeteremined)?;
+        check_algorithm(self.algorithm, SimpleCipherAlgorithm::XChaCha20Poly1305)?;
+        self.check_stream_commitment()?;
+        let key = self.key_for(None)?;
+        stream::decrypt_stream(&key, &nonce, self.aad(), reader, writer)
+    }
+
+    // This refuses to replace an encrypted file, whole or in parts, unless --force was given.
+    pub(crate) fn check_not_overwriting(&self) -> Result<(), SimpleCipherError> {
+        let written =
+            self.encrypted_file.exists() || parts::part_path(&self.encrypted_file, 1).exists();
+        if written && !self.force {
+            return Err(SimpleCipherError::OutputExists(self.encrypted_file.clone()));
+        }
+        Ok(())
+    }
+
+    // This makes sure the directory the encrypted file is written to exists, creating it if
+    // `--mkdir` was given.
+    pub(crate) fn prepare_output_dir(&self) -> Result<(), SimpleCipherError> {
+        let parent = match self.encrypted_file.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => return Ok(()),
+        };
+        if parent.is_dir() {
+            return Ok(());
+        }
+        if !self.mkdir {
+            return Err(SimpleCipherError::OutputDirMissing(parent.to_path_buf()));
+        }
+        fs::create_dir_all(parent)?;
+        Ok(())
+    }
+
+    // Nothing written replaces the encrypted file until the writer is committed.
+    fn create_encrypted_file(&self) -> Result<atomic::EncryptedFileWriter, SimpleCipherError> {
+        if let Some(part_size) = self.split_output_size {
+            return Ok(atomic::EncryptedFileWriter::Parts(
+                parts::PartWriter::create(
+                    &self.encrypted_file,
+                    part_size.get(),
+                    self.retry_on_busy,
+                )?,
+            ));
+        }
+        Ok(atomic::EncryptedFileWriter::create(
+            &self.encrypted_file,
+            self.retry_on_busy,
+        )?)
+    }
+
+    // A file written with --split-output-size is read back from its parts.
+    fn read_encrypted_file(&self) -> io::Result<Vec<u8>> {
+        if !self.encrypted_file.exists() {
+            if let Some(contents) = parts::read_parts(&self.encrypted_file, self.retry_on_busy)? {
+                return Ok(contents);
+            }
+        }
+        retry_if_busy(self.retry_on_busy, || fs::read(&self.encrypted_file))
+    }
+
+    // This is the number of threads encrypting streamed chunks, or `None` to encrypt them on
+    // this thread. A machine which cannot tell its core count encrypts them here.
+    fn workers(&self) -> Option<NonZeroUsize> {
+        if self.parallel {
+            return thread::available_parallelism().ok();
+        }
+        self.max_concurrency
+    }
+
+    fn stream_params(&self) -> StreamParams {
+        let defaults = StreamParams::default();
+        StreamParams {
+            chunk_size: self.chunk_size.unwrap_or(defaults.chunk_size),
+            rekey_interval: self.rekey_interval.unwrap_or(defaults.rekey_interval),
+        }
+    }
+
+    /// Asks for the key at the terminal if --prompt was given, twice if `confirm`, and uses it as
+    /// --key from then on. This is done once up front so every later use of the key, such as
+    /// the entropy report and then the encryption, sees the same passphrase.
+    pub fn prompt_for_key(&mut self, confirm: bool) -> Result<(), SimpleCipherError> {
+        if self.prompt && self.key.is_none() {
+            let ask = |prompt: &str| rpassword::prompt_password(prompt);
+            let timeout = self.key_prompt_confirm_timeout.map(Duration::from_secs);
+            self.key = Some(input::read_passphrase(ask, confirm, timeout)?);
+        }
+        Ok(())
+    }
+
+    // This is the key string from --key, borrowed rather than copied, or read from --key-file or
+    // --key-env.
+    fn key_string(&self) -> Result<Option<Cow<'_, Zeroizing<String>>>, SimpleCipherError> {
+        self.key_string_with(|name| std::env::var(name))
+    }
+
+    // The environment is shared by the whole process, so `var` stands in for it in tests.
+    fn key_string_with(
+        &self,
+        var: impl FnOnce(&str) -> Result<String, std::env::VarError>,
+    ) -> Result<Option<Cow<'_, Zeroizing<String>>>, SimpleCipherError> {
+        if let Some(key) = &self.key {
+            return Ok(Some(Cow::Borrowed(key)));
+        }
+        if let Some(name) = &self.key_env {
+            return match var(name) {
+                Ok(key) => Ok(Some(Cow::Owned(Zeroizing::new(key)))),
+                Err(source) => Err(SimpleCipherError::KeyEnv {
+                    name: name.clone(),
+                    source,
+                }),
+            };
+        }
+        let Some(path) = &self.key_file else {
+            return Ok(None);
+        };
+        let key = Zeroizing::new(fs::read_to_string(path)?);
+        let trimmed = key.strip_suffix('\n').unwrap_or(&key);
+        let trimmed = trimmed.strip_suffix('\r').unwrap_or(trimmed);
+        Ok(Some(Cow::Owned(Zeroizing::new(trimmed.to_string()))))
+    }
+
+    // This function either reconstructs the key from its shares or derives it from the key
+    // string with `kdf`.
+    fn get_key_from_string(&self, kdf: &KdfParams) -> Result<Zeroizing<Key>, SimpleCipherError> {
+        if !self.key_shares.is_empty() {
+            return combine_key(&self.key_shares);
+        }
+        let Some(key) = self.key_string()? else {
+            return Err(SimpleCipherError::KeyMissing);
+        };
+        // A text key is used as it is. Only a decoded key is copied, into a buffer wiped on drop.
+        let decoded: Zeroizing<Vec<u8>>;
+        let key: &[u8] = match self.key_format {
+            KeyFormat::Text => key.as_bytes(),
+            KeyFormat::Hex => {
+                decoded = match hex::decode(key.as_str()) {
+                    Ok(key) if key.len() == MAX_KEY_LENGTH || self.raw_key => Zeroizing::new(key),
+                    _ => return Err(SimpleCipherError::KeyHexInvalid),
+                };
+                &decoded
+            }
+            // Either base64 alphabet is accepted, whichever one the key was printed in.
+            KeyFormat::Base64 | KeyFormat::Base64Url => {
+                decoded = encoding::decode_base64(&LENIENT_BASE64, key.as_bytes())
+                    .map(Zeroizing::new)
+                    .map_err(SimpleCipherError::KeyBase64Invalid)?;
+                &decoded
+            }
+        };
+        // A raw key, like a hex one, is the full 32 byte key as it is.
+        if self.raw_key || self.key_format == KeyFormat::Hex {
+            if key.len() > MAX_KEY_LENGTH {
+                return Err(SimpleCipherError::KeyTooLong(key.len()));
+            }
+            check_key_length(key.len(), MAX_KEY_LENGTH)?;
+            return Ok(Zeroizing::new(*Key::from_slice(key)));
+        }
+        let base64 = matches!(self.key_format, KeyFormat::Base64 | KeyFormat::Base64Url);
+        let min = if self.strict_key_length && base64 {
+            MAX_KEY_LENGTH
+        } else {
+            self.min_key_length
+        };
+        check_key_length(key.len(), min)?;
+        if base64 {
+            return pad_key(key);
+        }
+        if let Some(megabytes) = self.limit_memory {
+            kdf::limit_memory(megabytes)?;
+        }
+        kdf::derive_key(key, kdf)
+    }
+
+    // These are the KDF parameters selected on the command line, with a fresh salt if the KDF
+    // takes one. Stream files do not record their KDF so these are also used to decrypt them.
+    fn kdf_params(&self) -> Result<KdfParams, SimpleCipherError> {
+        let params = match self.kdf {
+            Kdf::None => KdfParams::None,
+            Kdf::Hash => KdfParams::Hash(self.key_hash_algorithm),
+            Kdf::Argon2 => KdfParams::argon2(
+                self.argon2_memory.unwrap_or(kdf::DEFAULT_ARGON2_MEMORY),
+                self.argon2_iterations
+                    .unwrap_or(kdf::DEFAULT_ARGON2_ITERATIONS),
+                &mut self.seeded_rng()?,
+            ),
+            Kdf::Scrypt => KdfParams::scrypt(
+                self.scrypt_log_n.unwrap_or(kdf::DEFAULT_SCRYPT_LOG_N),
+                self.scrypt_r.unwrap_or(kdf::DEFAULT_SCRYPT_R),
+                self.scrypt_p.unwrap_or(kdf::DEFAULT_SCRYPT_P),
+                &mut self.seeded_rng()?,
+            ),
+            Kdf::Pbkdf2 => KdfParams::pbkdf2(
+                self.pbkdf2_iterations
+                    .unwrap_or(kdf::DEFAULT_PBKDF2_ITERATIONS),
+                &mut self.seeded_rng()?,
+            ),
+        };
+        params.check_limits()?;
+        Ok(params)
+    }
+
+    /// The --show-entropy-estimate report on the key, or `None` if it was not asked for or the
+    /// key comes from shares, which are random. With --strict a low estimate is an error.
+    pub fn entropy_report(&self) -> Result<Option<String>, SimpleCipherError> {
+        if !self.show_entropy_estimate {
+            return Ok(None);
+        }
+        let Some(key) = self.key_string()? else {
+            return Ok(None);
+        };
+        let bits = estimate_entropy_bits(&key);
+        if bits >= LOW_ENTROPY_BITS {
+            return Ok(Some(format!("Key entropy estimate: about {bits:.0} bits")));
+        }
+        if self.strict {
+            return Err(SimpleCipherError::LowEntropyKey(bits));
+        }
+        Ok(Some(self.error_style().warning(&format!(
+            "Key entropy estimate: about {bits:.0} bits, which is weak. Use a longer key mixing \
+             cases, digits and symbols, or a stretching --kdf such as argon2 for passphrases."
+        ))))
+    }
+
+    /// How errors are displayed, with [`redacted`] rather than in full for `--redact` and in
+    /// color unless turned off.
+    pub fn error_style(&self) -> ErrorStyle {
+        ErrorStyle::new(self.redact, self.no_color)
+    }
+
+    /// This is the estimated nonce space of the generator `--generate-nonce` would use.
+    pub fn nonce_space_log2(&self) -> f64 {
+        self.nonce_generator.nonce_space_log2()
+    }
+
+    // This is a helper function to make a nonce a string. This is for converting a generated nonce
+    // into a string for decryption
+    fn stringify_nonce(nonce: &[u8]) -> String {
+        // Nonces that are not printable ascii, such as random ones, could not be typed back in
+        // and bytes above 127 would become multi-byte characters, so they are printed as hex of
+        // the whole held nonce instead, so it is never as short as an ascii nonce.
+        if !nonce.iter().all(u8::is_ascii_graphic) {
+            let mut held = [0_u8; NONCE_LENGTH];
+            held[..nonce.len()].copy_from_slice(nonce);
+            return hex::encode(held);
+        }
+        nonce.iter().map(|val| char::from(*val)).collect()
+    }
+
+    // This is a helper function to turn a string into a nonce. This is used when the user wants to
+    // specify a given nonce via the CLI.
+    fn nonce_from_string(nonce: String) -> Result<XNonce, SimpleCipherError> {
+        // A hex nonce is twice as long as the longest ascii nonce so the two never overlap. It
+        // may have been printed in groups so whitespace in it is ignored.
+        let compact: String = nonce.split_whitespace().collect();
+        if compact.len() == 2 * NONCE_LENGTH {
+            if let Ok(nonce) = hex::decode(&compact) {
+                return Ok(*XNonce::from_slice(&nonce));
+            }
+        }
+        // Each character is one byte, so a character past U+00FF is refused rather than
+        // truncated into a byte shared with other characters.
+        let mut nonce = nonce
+            .chars()
+            .map(|v| u8::try_from(v).map_err(|_| SimpleCipherError::NonceCharacter(v)))
+            .collect::<Result<Vec<u8>, _>>()?;
+        if nonce.len() > NONCE_LENGTH {
+            return Err(SimpleCipherError::NonceTooLong(nonce.len()));
+        }
+        let mut padding_bytes = vec![0_u8; NONCE_LENGTH - nonce.len()];
+        nonce.append(&mut padding_bytes);
+        Ok(*XNonce::from_slice(nonce.as_slice()))
+    }
+
+    // This is the --aad authenticated with every ciphertext and stream chunk, empty if none was
+    // given.
+    fn aad(&self) -> &[u8] {
+        self.aad.as_deref().unwrap_or_default().as_bytes()
+    }
+
+    // This is the --format of the encrypted file, which --armor sets to PEM.
+    fn format(&self) -> Option<CiphertextFormat> {
+        match self.armor {
+            true => Some(CiphertextFormat::Pem),
+            false => self.format,
+        }
+    }
+
+    // This is the --algorithm to encrypt with.
+    fn algorithm(&self) -> SimpleCipherAlgorithm {
+        self.algorithm.unwrap_or_default()
+    }
+
+    /// Returns exactly what the AEAD authenticates when encrypting `plaintext` in one shot with
+    /// these options, without encrypting anything: the header, but for its checksum and key id,
+    /// followed by the `--aad` as the associated data, and the message after any compression.
+    /// A generated nonce or KDF salt is drawn afresh, so the header only matches a later
+    /// encryption when they are given. Streamed chunks are authenticated differently.
+    pub fn authenticated_bytes(
+        &self,
+        plaintext: &[u8],
+    ) -> Result<AuthenticatedBytes, SimpleCipherError> {
+        let (cipher, _, header) = self.sealer()?;
+        cipher.authenticated(&header, plaintext)
+    }
+
+    /// Returns the encrypted file these options read and write.
+    pub fn encrypted_file(&self) -> &Path {
+        &self.encrypted_file
+    }
+
+    /// Returns where the nonce comes from, without producing it.
+    pub fn nonce_source(&self) -> &NonceSource {
+        &self.nonce
+    }
+
+    // This is the nonce given for decryption, or `None` when no nonce option is set and the
+    // nonce is read from the header of the encrypted file instead.
+    fn decryption_nonce(&self) -> Result<Option<XNonce>, SimpleCipherError> {
+        match self.nonce {
+            NonceSource::Undetermined => Ok(None),
+            _ => self.nonce().map(Some),
+        }
+    }
+
+    // A generated nonce is embedded in the header unless --detached-nonce is given. Streams
+    // have no header, so their nonce is always detached.
+    fn embeds_nonce(&self) -> bool {
+        self.nonce == NonceSource::Generated && !self.detached_nonce && !self.stream
+    }
+
+    // This zeroes the bytes of a generated nonce past the nonce length of the algorithm, which
+    // it does not use.
+    fn fit_nonce(&self, mut nonce: XNonce) -> XNonce {
+        nonce[self.algorithm().nonce_length()..].fill(0);
+        nonce
+    }
+
+    // This function either:
+    // * generates a nonce
+    // * returns a nonce of all zeros (**NOT RECOMMENDED**)
+    // * converts a nonce-string to an XNonce.
+    fn nonce(&self) -> Result<XNonce, SimpleCipherError> {
+        match &self.nonce {
+            NonceSource::Undetermined => Err(SimpleCipherError::NonceChoiceUndeteremined),
+            NonceSource::Zero => Ok(XNonce::default()),
+            NonceSource::Generated | NonceSource::GeneratedToFile(_) => self.fresh_nonce(),
+            NonceSource::Provided(nonce) => self.parse_nonce(nonce),
+            NonceSource::File(nonce_file) => {
+                let nonce = fs::read(nonce_file)?;
+                let length = self.algorithm().nonce_length();
+                if nonce.len() != length {
+                    return Err(SimpleCipherError::NonceFileLength(nonce.len(), length));
+                }
+                let mut padded = XNonce::default();
+                padded[..length].copy_from_slice(&nonce);
+                Ok(padded)
+            }
+        }
+    }
+
+    // This draws a fresh nonce with --nonce-generator.
+    fn fresh_nonce(&self) -> Result<XNonce, SimpleCipherError> {
+        if self.nonce_generator == NonceGenerator::Random {
+            let nonce = XChaCha20Poly1305::generate_nonce(&mut self.seeded_rng()?);
+            return Ok(self.fit_nonce(nonce));
+        }
+        // Only `--nonce-generator legacy` reaches this, for nonces which are easy to type.
+        let mut rng = self.seeded_rng()?;
+
+        // There is almost certainly a better way to do this.
+        // The choos_multiple function in rand does not reuse existing values from my short
+        // tests.
+        // https://docs.rs/rand/latest/rand/seq/trait.IteratorRandom.html#method.choose_multiple
+        // Given that the goal of this is to make a nonce easy to enter, copy and paste
+        // usage of a corpus of each lower case letter of the alphabet repeated NONCE_LENGTH
+        // times, there is probably enough entropy.
+        //
+        // The ChaCha23Poly1305 documentation has actual math behind theuir random nonces.
+        // https://docs.rs/aead/latest/src/aead/lib.rs.html#114-148
+        //
+        // In this case, 26*24 input characters with selecting  24 characters and as
+        // `choose_multiple` selects some without repetitions, I think the number of
+        // combinations is 624 choose 24. Which has ~1.25e43 combinations, this *feels* like a
+        // sufficiently large set but the author of this nonce-subset hack is not a
+        // cyrptographer and would require a proper audit.
+        let potential_nonce_chars: String = vec![
+            vec!["a"; NONCE_LENGTH].join(""),
+            vec!["b"; NONCE_LENGTH].join(""),
+            vec!["c"; NONCE_LENGTH].join(""),
+            vec!["d"; NONCE_LENGTH].join(""),
+            vec!["e"; NONCE_LENGTH].join(""),
+            vec!["f"; NONCE_LENGTH].join(""),
+            vec!["g"; NONCE_LENGTH].join(""),
+            vec!["h"; NONCE_LENGTH].join(""),
+            vec!["i"; NONCE_LENGTH].join(""),
+            vec!["j"; NONCE_LENGTH].join(""),
+            vec!["k"; NONCE_LENGTH].join(""),
+            vec!["l"; NONCE_LENGTH].join(""),
+            vec!["m"; NONCE_LENGTH].join(""),
+            vec!["o"; NONCE_LENGTH].join(""),
+            vec!["o"; NONCE_LENGTH].join(""),
+            vec!["p"; NONCE_LENGTH].join(""),
+            vec!["q"; NONCE_LENGTH].join(""),
+            vec!["r"; NONCE_LENGTH].join(""),
+            vec!["s"; NONCE_LENGTH].join(""),
+            vec!["t"; NONCE_LENGTH].join(""),
+            vec!["u"; NONCE_LENGTH].join(""),
+            vec!["v"; NONCE_LENGTH].join(""),
+            vec!["w"; NONCE_LENGTH].join(""),
+            vec!["x"; NONCE_LENGTH].join(""),
+            vec!["y"; NONCE_LENGTH].join(""),
+            vec!["z"; NONCE_LENGTH].join(""),
+        ]
+        .join("");
+        let nonce: String = potential_nonce_chars
+            .chars()
+            .choose_multiple(&mut rng, NONCE_LENGTH)
+            .into_iter()
+            .collect();
+
+        Ok(self.fit_nonce(*XNonce::from_slice(nonce.as_bytes())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    #[test]
+    fn encrypt_and_decrypt_with_nonce() {
+        let key = "baz".to_string();
+        let input = "foobar".to_string();
+        let nonce = vec!["a"; NONCE_LENGTH].join("");
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("encyrpted.dat");
+        let encrypt_opts = CommonEncryptionOpts {
+            key: Some(key.clone().into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Provided(nonce.clone()),
+            ..Default::default()
+        };
+        let decrypt_opts = CommonEncryptionOpts {
+            key: Some(key.into()),
+            encrypted_file,
+            nonce: NonceSource::Provided(nonce),
+            ..Default::default()
+        };
+
+        let _ = encrypt_opts
+            .encrypt(input.clone())
+            .expect("Failed to encrypt data");
+        let output = decrypt_opts.decrypt().expect("Failed to decrypt data");
+        assert_eq!(input.as_bytes(), output);
+    }
+
+    #[test]
+    fn fail_to_decrypt() {
+        let encrypt_key = "this is the encryption key".to_string();
+        let decrypt_key = "this is not the encryption key".to_string();
+        let input = "foobar".to_string();
+        let nonce = vec!["b"; NONCE_LENGTH].join("");
+
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("encyrpted.dat");
+        let encrypt_opts = CommonEncryptionOpts {
+            key: Some(encrypt_key.into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Provided(nonce.clone()),
+            ..Default::default()
+        };
+        let decrypt_opts = CommonEncryptionOpts {
+            key: Some(decrypt_key.into()),
+            encrypted_file,
+            nonce: NonceSource::Provided(nonce),
+            ..Default::default()
+        };
+
+        let _ = encrypt_opts.encrypt(input).expect("Failed to encrypt data");
+        let output = decrypt_opts.decrypt();
+        assert!(output.is_err());
+    }
+
+    // Verify that if our input key string exceeds 32 bytes, it throws an error.
+    #[test]
+    fn encryption_key_too_long() {
+        const BAD_KEY_LENGTH: usize = MAX_KEY_LENGTH + 1;
+
+        let encrypt_key = vec!["a"; BAD_KEY_LENGTH].join("");
+        let input = "foobar".to_string();
+        let nonce = vec!["c"; NONCE_LENGTH].join("");
+
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("encyrpted.dat");
+        let encrypt_opts = CommonEncryptionOpts {
+            key: Some(encrypt_key.into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Provided(nonce.clone()),
+            ..Default::default()
+        };
+
+        let encrypt_out = encrypt_opts.encrypt(input.clone());
+        assert!(encrypt_out.is_err());
+        let encrypt_out = encrypt_out.err().unwrap();
+        assert_eq!(
+            format!("{encrypt_out:?}"),
+            format!("{:?}", SimpleCipherError::KeyTooLong(BAD_KEY_LENGTH))
+        );
+
+        let decrypt_key = vec!["a"; BAD_KEY_LENGTH].join("");
+        let encrypt_key = vec!["a"; MAX_KEY_LENGTH].join("");
+        let encrypt_opts = CommonEncryptionOpts {
+            key: Some(encrypt_key.into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Provided(nonce.clone()),
+            ..Default::default()
+        };
+        let decrypt_opts = CommonEncryptionOpts {
+            key: Some(decrypt_key.into()),
+            encrypted_file,
+            nonce: NonceSource::Provided(nonce),
+            ..Default::default()
+        };
+
+        let _ = encrypt_opts.encrypt(input).expect("Failed to encrypt data");
+        let decrypt_out = decrypt_opts.decrypt();
+
+        assert!(decrypt_out.is_err());
+        let decrypt_out = decrypt_out.unwrap_err();
+
+        // To quote the docs, this error is intentionally opaque to prevent side channel attacks.
+        assert_eq!(
+            format!("{decrypt_out:?}"),
+            format!("{:?}", SimpleCipherError::KeyTooLong(BAD_KEY_LENGTH))
+        );
+    }
+
+    #[test]
+    fn encrypt_and_decrypt_zeros_as_a_nonce() {
+        let key = "baz".to_string();
+        let input = "foobar".to_string();
+
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("encyrpted.dat");
+        let encrypt_opts = CommonEncryptionOpts {
+            key: Some(key.clone().into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Zero,
+            ..Default::default()
+        };
+        let _ = encrypt_opts
+            .encrypt(input.clone())
+            .expect("Failed to encrypt data");
+        let decrypt_opts = CommonEncryptionOpts {
+            key: Some(key.into()),
+            encrypted_file,
+            nonce: NonceSource::Zero,
+            ..Default::default()
+        };
+
+        let output = decrypt_opts.decrypt().expect("Failed to decrypt data");
+        assert_eq!(input.as_bytes(), output);
+    }
+
+    #[test]
+    fn encrypt_and_decrypt_zeros_with_generated_nonce() {
+        let key = "baz".to_string();
+        let input = "foobar".to_string();
+
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("encyrpted.dat");
+        let encrypt_opts = CommonEncryptionOpts {
+            nonce: NonceSource::Generated,
+            key: Some(key.clone().into()),
+            encrypted_file: encrypted_file.clone(),
+            ..Default::default()
+        };
+        let generated_nonce = encrypt_opts
+            .encrypt(input.clone())
+            .expect("Failed to encrypt data");
+
+        let decrypt_opts = CommonEncryptionOpts {
+            key: Some(key.into()),
+            encrypted_file,
+            nonce: generated_nonce.map_or(NonceSource::Undetermined, NonceSource::Provided),
+            ..Default::default()
+        };
+
+        let output = decrypt_opts.decrypt().expect("Failed to decrypt data");
+        assert_eq!(input.as_bytes(), output);
+    }
+
+    #[test]
+    fn nonce_error() {
+        let key = "baz".to_string();
+        let input = "foobar".to_string();
+
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("encyrpted.dat");
+        let encrypt_opts = CommonEncryptionOpts {
+            key: Some(key.into()),
+            encrypted_file,
+            ..Default::default()
+        };
+        let out = encrypt_opts.encrypt(input);
+        assert!(out.is_err());
+        let out = out.unwrap_err();
+        assert_eq!(
+            format!("{out:?}"),
+            format!("{:?}", SimpleCipherError::NonceChoiceUndeteremined)
+        );
+    }
+
+    #[test]
+    fn nonce_too_long() {
+        let key = "baz".to_string();
+        let input = "foobar".to_string();
+        let nonce = vec!["a"; NONCE_LENGTH + 1].join("");
+
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("encyrpted.dat");
+        let encrypt_opts = CommonEncryptionOpts {
+            key: Some(key.into()),
+            encrypted_file,
+            nonce: NonceSource::Provided(nonce),
+            ..Default::default()
+        };
+        let out = encrypt_opts.encrypt(input);
+        assert!(out.is_err());
+        let out = out.unwrap_err();
+        assert_eq!(
+            format!("{out:?}"),
+            format!("{:?}", SimpleCipherError::NonceTooLong(NONCE_LENGTH + 1))
+        );
+    }
+
+    #[test]
+    fn decrypt_detects_format() {
+        let key = "baz".to_string();
+        let input = "foobar".to_string();
+        let nonce = vec!["d"; NONCE_LENGTH].join("");
+
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        for format in CiphertextFormat::ALL {
+            let encrypted_file = tmpdir.path().join(format!("encyrpted.{}", format.name()));
+            let encrypt_opts = CommonEncryptionOpts {
+                key: Some(key.clone().into()),
+                encrypted_file: encrypted_file.clone(),
+                nonce: NonceSource::Provided(nonce.clone()),
+                format: Some(format),
+                ..Default::default()
+            };
+            let decrypt_opts = CommonEncryptionOpts {
+                key: Some(key.clone().into()),
+                encrypted_file,
+                nonce: NonceSource::Provided(nonce.clone()),
+                ..Default::default()
+            };
+
+            let _ = encrypt_opts
+                .encrypt(input.clone())
+                .expect("Failed to encrypt data");
+            let output = decrypt_opts.decrypt().expect("Failed to decrypt data");
+            assert_eq!(input.as_bytes(), output);
+        }
+    }
+
+    #[test]
+    fn encrypt_and_decrypt_stream_with_rekey() {
+        let key = "baz".to_string();
+        let nonce = vec!["e"; NONCE_LENGTH].join("");
+        // This is large enough for the stream to rekey several times.
+        let input = "foobar".repeat(4096);
+
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("encyrpted.dat");
+        let encrypt_opts = CommonEncryptionOpts {
+            key: Some(key.clone().into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Provided(nonce.clone()),
+            stream: true,
+            chunk_size: Some(1024),
+            rekey_interval: Some(4),
+            ..Default::default()
+        };
+        let decrypt_opts = CommonEncryptionOpts {
+            key: Some(key.into()),
+            encrypted_file,
+            nonce: NonceSource::Provided(nonce),
+            ..Default::default()
+        };
+
+        let _ = encrypt_opts
+            .encrypt(input.clone())
+            .expect("Failed to encrypt data");
+        let output = decrypt_opts.decrypt().expect("Failed to decrypt data");
+        assert_eq!(input.as_bytes(), output);
+    }
+
+    #[test]
+    fn auth_failure_predicate() {
+        let nonce = vec!["f"; NONCE_LENGTH].join("");
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("encyrpted.dat");
+        let encrypt_opts = CommonEncryptionOpts {
+            key: Some("right key".to_string().into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Provided(nonce.clone()),
+            ..Default::default()
+        };
+        let _ = encrypt_opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+
+        let wrong_key = CommonEncryptionOpts {
+            key: Some("wrong key".to_string().into()),
+            encrypted_file,
+            nonce: NonceSource::Provided(nonce.clone()),
+            ..Default::default()
+        };
+        assert!(wrong_key.decrypt().unwrap_err().is_auth_failure());
+
+        let missing_file = CommonEncryptionOpts {
+            key: Some("right key".to_string().into()),
+            encrypted_file: tmpdir.path().join("missing.dat"),
+            nonce: NonceSource::Provided(nonce),
+            ..Default::default()
+        };
+        assert!(!missing_file.decrypt().unwrap_err().is_auth_failure());
+    }
+
+    #[test]
+    fn encrypt_with_mkdir() {
+        let key = "baz".to_string();
+        let input = "foobar".to_string();
+        let nonce = vec!["g"; NONCE_LENGTH].join("");
+
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir
+            .path()
+            .join("first")
+            .join("second")
+            .join("encyrpted.dat");
+        let mut encrypt_opts = CommonEncryptionOpts {
+            key: Some(key.clone().into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Provided(nonce.clone()),
+            ..Default::default()
+        };
+        let out = encrypt_opts.encrypt(input.clone());
+        assert_eq!(
+            format!("{:?}", out.unwrap_err()),
+            format!(
+                "{:?}",
+                SimpleCipherError::OutputDirMissing(encrypted_file.parent().unwrap().to_path_buf())
+            )
+        );
+
+        encrypt_opts.mkdir = true;
+        let _ = encrypt_opts
+            .encrypt(input.clone())
+            .expect("Failed to encrypt data");
+        let decrypt_opts = CommonEncryptionOpts {
+            key: Some(key.into()),
+            encrypted_file,
+            nonce: NonceSource::Provided(nonce),
+            ..Default::default()
+        };
+        let output = decrypt_opts.decrypt().expect("Failed to decrypt data");
+        assert_eq!(input.as_bytes(), output);
+    }
+
+    #[test]
+    fn nonce_space_estimates() {
+        assert_ne!(
+            NonceGenerator::Legacy.nonce_space_log2(),
+            NonceGenerator::Random.nonce_space_log2()
+        );
+        assert!(NonceGenerator::Legacy.nonce_space_log2() < 112.0);
+        assert_eq!(NonceGenerator::Random.nonce_space_log2(), 192.0);
+    }
+
+    #[test]
+    fn generated_nonces_differ() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Generated,
+            ..Default::default()
+        };
+        let first = opts.nonce().expect("Failed to generate nonce");
+        let second = opts.nonce().expect("Failed to generate nonce");
+        // Two equal nonces out of 2^192 would mean the generator is broken.
+        assert_ne!(first, second);
+        assert!(!first.iter().all(u8::is_ascii_lowercase));
+    }
+
+    #[test]
+    fn encrypt_and_decrypt_with_random_nonce() {
+        let key = "baz".to_string();
+        let input = "foobar".to_string();
+
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("encyrpted.dat");
+        let encrypt_opts = CommonEncryptionOpts {
+            key: Some(key.clone().into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Generated,
+            detached_nonce: true,
+            nonce_generator: NonceGenerator::Random,
+            ..Default::default()
+        };
+        let generated_nonce = encrypt_opts
+            .encrypt(input.clone())
+            .expect("Failed to encrypt data");
+        assert_eq!(
+            generated_nonce.as_ref().map(String::len),
+            Some(2 * NONCE_LENGTH)
+        );
+
+        let decrypt_opts = CommonEncryptionOpts {
+            key: Some(key.into()),
+            encrypted_file,
+            nonce: generated_nonce.map_or(NonceSource::Undetermined, NonceSource::Provided),
+            ..Default::default()
+        };
+        let output = decrypt_opts.decrypt().expect("Failed to decrypt data");
+        assert_eq!(input.as_bytes(), output);
+    }
+
+    #[test]
+    fn tee_plaintext() {
+        let key = "baz".to_string();
+        let input = "foobar\nwith a second line".to_string();
+        let nonce = vec!["h"; NONCE_LENGTH].join("");
+
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("encyrpted.dat");
+        let tee_file = tmpdir.path().join("plaintext.txt");
+        let encrypt_opts = CommonEncryptionOpts {
+            key: Some(key.clone().into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Provided(nonce.clone()),
+            ..Default::default()
+        };
+        let decrypt_opts = CommonEncryptionOpts {
+            key: Some(key.into()),
+            encrypted_file,
+            nonce: NonceSource::Provided(nonce),
+            ..Default::default()
+        };
+        let _ = encrypt_opts
+            .encrypt(input.clone())
+            .expect("Failed to encrypt data");
+        let output = decrypt_opts.decrypt().expect("Failed to decrypt data");
+
+        let mut printed = Vec::new();
+        write_plaintext(&output, &mut printed, Some(&tee_file)).expect("Failed to write plaintext");
+        let teed = fs::read(&tee_file).expect("Failed to read tee file");
+        assert_eq!(printed, teed);
+        assert_eq!(printed, input.as_bytes());
+    }
+
+    #[test]
+    fn encrypt_and_decrypt_with_hex_key() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let hex_key = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+        let opts = CommonEncryptionOpts {
+            key: Some(hex_key.to_string().into()),
+            key_format: KeyFormat::Hex,
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Provided("hex key".to_string()),
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        assert_eq!(opts.decrypt().expect("Failed to decrypt data"), b"foobar");
+        assert_eq!(
+            opts.get_key_from_string(&KdfParams::None).unwrap().to_vec(),
+            hex::decode(hex_key).unwrap()
+        );
+
+        // The same digits as a text key are a different key.
+        let text = CommonEncryptionOpts {
+            key_format: KeyFormat::Text,
+            kdf: Kdf::Hash,
+            ..opts.clone()
+        };
+        assert!(text.decrypt().is_err());
+
+        for invalid in [&hex_key[1..], &hex_key[2..], &hex_key.replace('f', "g")] {
+            let invalid = CommonEncryptionOpts {
+                key: Some(invalid.to_string().into()),
+                ..opts.clone()
+            };
+            assert!(matches!(
+                invalid.decrypt(),
+                Err(SimpleCipherError::KeyHexInvalid)
+            ));
+        }
+    }
+
+    #[test]
+    fn encrypt_and_decrypt_with_base64_key() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let raw_key: Vec<u8> = (0..32).map(|i| i * 7).collect();
+        let opts = CommonEncryptionOpts {
+            key: Some(BASE64.encode(&raw_key).into()),
+            key_format: KeyFormat::Base64,
+            strict_key_length: true,
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Provided("base64 key".to_string()),
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        assert_eq!(opts.decrypt().expect("Failed to decrypt data"), b"foobar");
+        assert_eq!(
+            opts.get_key_from_string(&KdfParams::None).unwrap().to_vec(),
+            raw_key
+        );
+
+        let with_key = |key: String, strict_key_length| CommonEncryptionOpts {
+            key: Some(key.into()),
+            strict_key_length,
+            ..opts.clone()
+        };
+        let short = with_key(BASE64.encode(b"short"), false);
+        assert_eq!(
+            &short.get_key_from_string(&KdfParams::None).unwrap()[..6],
+            b"short\0"
+        );
+        assert!(matches!(
+            with_key(BASE64.encode(b"short"), true).get_key_from_string(&KdfParams::None),
+            Err(SimpleCipherError::KeyTooShort(5, 32))
+        ));
+        assert!(matches!(
+            with_key(BASE64.encode([1; 33]), false).get_key_from_string(&KdfParams::None),
+            Err(SimpleCipherError::KeyTooLong(33))
+        ));
+        let error = with_key("not base64!".to_string(), false)
+            .get_key_from_string(&KdfParams::None)
+            .unwrap_err();
+        assert!(
+            matches!(error, SimpleCipherError::KeyBase64Invalid(_)),
+            "{error:?}"
+        );
+        assert_eq!(error.to_string(), "The key is not valid base64");
+    }
+
+    #[test]
+    fn encrypt_and_decrypt_with_base64_url_key() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let raw_key = [0xfb_u8; 32];
+        let url_key = URL_SAFE_NO_PAD.encode(raw_key);
+        assert!(url_key.contains(['-', '_']));
+        let opts = CommonEncryptionOpts {
+            key: Some(url_key.into()),
+            key_format: KeyFormat::Base64Url,
+            strict_key_length: true,
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Provided("base64url key".to_string()),
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        assert_eq!(opts.decrypt().expect("Failed to decrypt data"), b"foobar");
+
+        // Either base64 format reads a key printed in either alphabet.
+        for key_format in [KeyFormat::Base64, KeyFormat::Base64Url] {
+            for key in [BASE64.encode(raw_key), URL_SAFE_NO_PAD.encode(raw_key)] {
+                let opts = CommonEncryptionOpts {
+                    key: Some(key.into()),
+                    key_format,
+                    ..opts.clone()
+                };
+                assert_eq!(
+                    *opts.get_key_from_string(&KdfParams::None).unwrap(),
+                    raw_key.into()
+                );
+                assert_eq!(opts.decrypt().expect("Failed to decrypt data"), b"foobar");
+            }
+        }
+    }
+
+    #[test]
+    fn encrypt_and_decrypt_with_key_file() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let key_file = tmpdir.path().join("key.txt");
+        fs::write(&key_file, "baz\n").unwrap();
+        let opts = CommonEncryptionOpts {
+            key_file: Some(key_file.clone()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Provided("key file".to_string()),
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        // The newline is not part of the key.
+        let with_key = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            key_file: None,
+            ..opts.clone()
+        };
+        assert_eq!(
+            with_key.decrypt().expect("Failed to decrypt data"),
+            b"foobar"
+        );
+
+        let missing = CommonEncryptionOpts {
+            key_file: Some(tmpdir.path().join("missing.txt")),
+            ..opts
+        };
+        assert!(matches!(
+            missing.decrypt(),
+            Err(SimpleCipherError::IO(e)) if e.kind() == io::ErrorKind::NotFound
+        ));
+
+        // Exactly one key source is accepted on the command line.
+        let key_file = key_file.to_str().unwrap();
+        let parse = |key_args: &[&str]| {
+            let args = ["encrypt", "--no-nonce"].iter().chain(key_args);
+            CommonEncryptionOpts::try_parse_from(args).map(|_| ())
+        };
+        assert!(parse(&["--key-file", key_file]).is_ok());
+        assert!(parse(&["--key", "baz", "--key-file", key_file]).is_err());
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["--prompt"]).is_ok());
+        assert!(parse(&["--prompt", "--key", "baz"]).is_err());
+    }
+
+    #[test]
+    fn key_is_read_from_key_env() {
+        // The environment is shared by every test thread, so a lookup stands in for it.
+        fn var(
+            value: Option<&'static str>,
+        ) -> impl FnOnce(&str) -> Result<String, std::env::VarError> {
+            move |name| {
+                assert_eq!(name, "SKE_KEY_ENV_TEST");
+                value
+                    .map(str::to_string)
+                    .ok_or(std::env::VarError::NotPresent)
+            }
+        }
+        let opts = CommonEncryptionOpts {
+            key_env: Some("SKE_KEY_ENV_TEST".to_string()),
+            ..Default::default()
+        };
+        let key = opts
+            .key_string_with(var(Some("baz")))
+            .expect("Failed to read key")
+            .expect("Key was set");
+        assert_eq!(key.as_str(), "baz");
+
+        // --key is used ahead of the environment.
+        let with_key = CommonEncryptionOpts {
+            key: Some("from flag".to_string().into()),
+            ..opts.clone()
+        };
+        let key = with_key.key_string_with(var(None)).unwrap().unwrap();
+        assert_eq!(key.as_str(), "from flag");
+
+        let error = opts.key_string_with(var(None)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Could not read the key from the environment variable SKE_KEY_ENV_TEST"
+        );
+
+        let parsed = CommonEncryptionOpts::try_parse_from(["decrypt", "--no-nonce", "--key-env"])
+            .expect("Failed to parse");
+        assert_eq!(parsed.key_env.as_deref(), Some("SKE_KEY"));
+    }
+
+    #[test]
+    fn min_key_length_refuses_short_keys() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let with_key = |key: &str| CommonEncryptionOpts {
+            key: Some(key.to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Provided("min key length".to_string()),
+            min_key_length: 12,
+            ..Default::default()
+        };
+        let error = with_key("short key")
+            .encrypt("foobar".to_string())
+            .unwrap_err();
+        assert!(
+            matches!(error, SimpleCipherError::KeyTooShort(9, 12)),
+            "{error:?}"
+        );
+        assert!(!tmpdir.path().join("encyrpted.dat").exists());
+
+        let opts = with_key("a long enough key");
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        assert_eq!(opts.decrypt().expect("Failed to decrypt data"), b"foobar");
+    }
+
+    #[test]
+    fn raw_key_must_be_32_bytes() {
+        let raw_key = |key: String, key_format| {
+            CommonEncryptionOpts {
+                key: Some(key.into()),
+                key_format,
+                raw_key: true,
+                ..Default::default()
+            }
+            .get_key_from_string(&KdfParams::None)
+        };
+        let full = "0123456789abcdef0123456789abcdef";
+        assert_eq!(
+            raw_key(full.to_string(), KeyFormat::Text)
+                .unwrap()
+                .as_slice(),
+            full.as_bytes()
+        );
+        let short = &full[1..];
+        let long = format!("{full}!");
+        for (key, key_format) in [
+            (short.to_string(), KeyFormat::Text),
+            (hex::encode(short), KeyFormat::Hex),
+            (BASE64.encode(short), KeyFormat::Base64),
+        ] {
+            assert!(matches!(
+                raw_key(key, key_format),
+                Err(SimpleCipherError::KeyTooShort(31, 32))
+            ));
+        }
+        for (key, key_format) in [
+            (long.clone(), KeyFormat::Text),
+            (hex::encode(&long), KeyFormat::Hex),
+            (BASE64.encode(&long), KeyFormat::Base64),
+        ] {
+            assert!(matches!(
+                raw_key(key, key_format),
+                Err(SimpleCipherError::KeyTooLong(33))
+            ));
+        }
+    }
+
+    // The key strings are held in types which wipe them on drop, from the command line as
+    // well as from a key file.
+    #[test]
+    fn key_strings_are_zeroizing() {
+        fn zeroizing(_: &Option<Zeroizing<String>>) {}
+        fn zeroizing_cow(_: &Option<Cow<'_, Zeroizing<String>>>) {}
+        let opts = CommonEncryptionOpts::try_parse_from(["encrypt", "--key", "baz", "--no-nonce"])
+            .expect("Failed to parse");
+        zeroizing(&opts.key);
+        let key = opts.key_string().expect("Failed to read key");
+        zeroizing_cow(&key);
+        assert!(matches!(key, Some(Cow::Borrowed(_))));
+        assert_eq!(key.as_deref().map(|key| key.as_str()), Some("baz"));
+    }
+
+    // This is how `--key` was padded before the key string was borrowed, kept to check the
+    // keys are unchanged.
+    fn padded_by_copying(key: &str) -> Key {
+        let mut key = Zeroizing::new(key.to_string().into_bytes());
+        let mut padding_bytes = vec![0_u8; MAX_KEY_LENGTH - key.len()];
+        key.append(&mut padding_bytes);
+        *Key::from_slice(&key)
+    }
+
+    #[test]
+    fn padded_keys_are_unchanged() {
+        for length in [0, 1, 3, 31, 32] {
+            let key_string = "k".repeat(length);
+            let opts = CommonEncryptionOpts {
+                key: Some(key_string.clone().into()),
+                min_key_length: 0,
+                ..Default::default()
+            };
+            let key = opts
+                .get_key_from_string(&KdfParams::None)
+                .expect("Failed to derive key");
+            assert_eq!(*key, padded_by_copying(&key_string), "{length}");
+            assert_eq!(key_from_string(&key_string).unwrap(), key);
+        }
+        assert!(matches!(
+            pad_key(&[0; 33]),
+            Err(SimpleCipherError::KeyTooLong(33))
+        ));
+    }
+
+    #[test]
+    fn decrypt_with_key_shares() {
+        let key = "baz".to_string();
+        let input = "foobar".to_string();
+        let nonce = vec!["i"; NONCE_LENGTH].join("");
+        let shares = split_key(&key_from_string(&key).unwrap(), 2, 3).expect("Failed to split");
+
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("encyrpted.dat");
+        let encrypt_opts = CommonEncryptionOpts {
+            key: Some(key.into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Provided(nonce.clone()),
+            ..Default::default()
+        };
+        let _ = encrypt_opts
+            .encrypt(input.clone())
+            .expect("Failed to encrypt data");
+
+        let mut decrypt_opts = CommonEncryptionOpts {
+            key_shares: vec![shares[2].clone(), shares[0].clone()],
+            encrypted_file,
+            nonce: NonceSource::Provided(nonce),
+            ..Default::default()
+        };
+        let output = decrypt_opts.decrypt().expect("Failed to decrypt data");
+        assert_eq!(input.as_bytes(), output);
+
+        decrypt_opts.key_shares.pop();
+        assert!(decrypt_opts.decrypt().is_err());
+    }
+
+    #[test]
+    fn truncated_file_is_reported_as_corrupt() {
+        let nonce = vec!["j"; NONCE_LENGTH].join("");
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("encyrpted.dat");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Provided(nonce),
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        let contents = fs::read(&encrypted_file).expect("Failed to read encrypted file");
+        assert!(contents.starts_with(header::MAGIC));
+        fs::write(&encrypted_file, &contents[..contents.len() - 3]).expect("Failed to truncate");
+
+        let out = opts.decrypt().unwrap_err();
+        assert!(!out.is_auth_failure());
+        assert_eq!(
+            format!("{out:?}"),
+            format!(
+                "{:?}",
+                SimpleCipherError::Corrupted {
+                    expected: 6 + 16,
+                    actual: 6 + 16 - 3
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn redacted_errors_omit_details() {
+        let error = anyhow::Error::from(SimpleCipherError::KeyTooLong(40));
+        assert!(format!("{error}").contains("40"));
+        assert_eq!(redacted(error.as_ref()), "Key is too long");
+
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("missing").join("encyrpted.dat");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Zero,
+            redact: true,
+            ..Default::default()
+        };
+        assert!(opts.error_style().redact);
+        let path = encrypted_file.to_string_lossy().into_owned();
+        for (error, category) in [
+            (opts.decrypt().unwrap_err(), "I/O error"),
+            (
+                opts.encrypt("foobar".to_string()).unwrap_err(),
+                "Output directory does not exist",
+            ),
+        ] {
+            let error = anyhow::Error::from(error);
+            assert_eq!(redacted(error.as_ref()), category);
+            assert!(!redacted(error.as_ref()).contains(&path));
+        }
+        assert_eq!(redacted(anyhow::anyhow!("{path}").as_ref()), "Error");
+    }
+
+    #[test]
+    fn refuses_to_encrypt_an_encrypted_file() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("encyrpted.dat");
+        let mut opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Provided("double".to_string()),
+            format: Some(CiphertextFormat::Base64),
+            force: true,
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        let encrypted = fs::read_to_string(&encrypted_file).expect("Failed to read encrypted file");
+
+        let out = opts.encrypt(encrypted.clone()).unwrap_err();
+        assert_eq!(
+            format!("{out:?}"),
+            format!("{:?}", SimpleCipherError::AlreadyEncrypted)
+        );
+        assert!(opts
+            .check_not_encrypted(&frame(b"raw ciphertext", &Header::default()))
+            .is_err());
+
+        opts.allow_double_encrypt = true;
+        let _ = opts
+            .encrypt(encrypted.clone())
+            .expect("Failed to encrypt data");
+        assert_eq!(
+            opts.decrypt().expect("Failed to decrypt data"),
+            encrypted.as_bytes()
+        );
+    }
+
+    #[test]
+    fn encrypt_and_decrypt_with_hashed_key() {
+        let key = "a passphrase far longer than the thirty two bytes of a padded key";
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let mut contents = Vec::new();
+        for key_hash_algorithm in [KeyHashAlgorithm::Sha256, KeyHashAlgorithm::Blake3] {
+            let encrypted_file = tmpdir.path().join(format!("{key_hash_algorithm:?}.dat"));
+            let opts = CommonEncryptionOpts {
+                key: Some(key.to_string().into()),
+                encrypted_file: encrypted_file.clone(),
+                nonce: NonceSource::Provided("hashed".to_string()),
+                kdf: Kdf::Hash,
+                key_hash_algorithm,
+                ..Default::default()
+            };
+            let _ = opts
+                .encrypt("foobar".to_string())
+                .expect("Failed to encrypt data");
+            contents.push(fs::read(&encrypted_file).expect("Failed to read encrypted file"));
+
+            // The KDF recorded in the file is used whatever is given on decryption.
+            let decrypt_opts = CommonEncryptionOpts {
+                kdf: Kdf::None,
+                key_hash_algorithm: KeyHashAlgorithm::Sha512,
+                ..opts
+            };
+            let output = decrypt_opts.decrypt().expect("Failed to decrypt data");
+            assert_eq!(output, b"foobar");
+        }
+        assert_ne!(contents[0], contents[1]);
+
+        let opts = CommonEncryptionOpts {
+            key: Some(key.to_string().into()),
+            encrypted_file: tmpdir.path().join("padded.dat"),
+            nonce: NonceSource::Provided("hashed".to_string()),
+            ..Default::default()
+        };
+        let out = opts.encrypt("foobar".to_string()).unwrap_err();
+        assert_eq!(
+            format!("{out:?}"),
+            format!("{:?}", SimpleCipherError::KeyTooLong(key.len()))
+        );
+    }
+
+    #[test]
+    fn encrypt_and_decrypt_with_argon2() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = CommonEncryptionOpts {
+            key: Some("correct horse battery staple".to_string().into()),
+            encrypted_file: tmpdir.path().join("argon2.dat"),
+            nonce: NonceSource::Provided("argon2".to_string()),
+            kdf: Kdf::Argon2,
+            argon2_memory: Some(64),
+            argon2_iterations: Some(1),
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        // The salt and parameters come from the header.
+        let decrypt_opts = CommonEncryptionOpts {
+            kdf: Kdf::None,
+            argon2_memory: None,
+            ..opts
+        };
+        let output = decrypt_opts.decrypt().expect("Failed to decrypt data");
+        assert_eq!(output, b"foobar");
+    }
+
+    #[test]
+    fn encrypt_and_decrypt_with_scrypt() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = CommonEncryptionOpts {
+            key: Some("correct horse battery staple".to_string().into()),
+            encrypted_file: tmpdir.path().join("scrypt.dat"),
+            nonce: NonceSource::Provided("scrypt".to_string()),
+            kdf: Kdf::Scrypt,
+            scrypt_log_n: Some(4),
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        let decrypt_opts = CommonEncryptionOpts {
+            kdf: Kdf::None,
+            scrypt_log_n: None,
+            ..opts
+        };
+        let output = decrypt_opts.decrypt().expect("Failed to decrypt data");
+        assert_eq!(output, b"foobar");
+
+        // Decryption follows the parameters in the header, so changing log_n there derives a
+        // different key.
+        let contents = fs::read(&decrypt_opts.encrypted_file).unwrap();
+        let (header, ciphertext) = Header::parse(&contents).unwrap();
+        let mut header = header.expect("A header was written");
+        let KdfParams::Scrypt { log_n, .. } = &mut header.kdf else {
+            panic!("The header records {:?}", header.kdf);
+        };
+        *log_n += 1;
+        let mut changed = header.to_bytes();
+        changed.extend_from_slice(ciphertext);
+        fs::write(&decrypt_opts.encrypted_file, changed).unwrap();
+        let error = decrypt_opts.decrypt().unwrap_err();
+        assert!(
+            matches!(error, SimpleCipherError::AuthenticationFailed),
+            "{error:?}"
+        );
+    }
+
+    #[test]
+    fn pbkdf2_iterations_must_match() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = CommonEncryptionOpts {
+            key: Some("correct horse battery staple".to_string().into()),
+            encrypted_file: tmpdir.path().join("pbkdf2.dat"),
+            nonce: NonceSource::Provided("pbkdf2".to_string()),
+            kdf: Kdf::Pbkdf2,
+            pbkdf2_iterations: NonZeroU32::new(1000),
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        assert_eq!(opts.decrypt().expect("Failed to decrypt data"), b"foobar");
+
+        // The recorded count is the one decryption uses, so a changed count fails.
+        let contents = fs::read(&opts.encrypted_file).unwrap();
+        let (header, ciphertext) = Header::parse(&contents).unwrap();
+        let mut header = header.expect("A header was written");
+        let KdfParams::Pbkdf2 { iterations, .. } = &mut header.kdf else {
+            panic!("The header records {:?}", header.kdf);
+        };
+        *iterations = NonZeroU32::new(1001).unwrap();
+        let mut changed = header.to_bytes();
+        changed.extend_from_slice(ciphertext);
+        fs::write(&opts.encrypted_file, changed).unwrap();
+        let error = opts.decrypt().unwrap_err();
+        assert!(
+            matches!(error, SimpleCipherError::AuthenticationFailed),
+            "{error:?}"
+        );
+    }
+
+    #[test]
+    fn verify_after_encrypt() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("encyrpted.dat");
+        for stream in [false, true] {
+            let opts = CommonEncryptionOpts {
+                key: Some("baz".to_string().into()),
+                encrypted_file: encrypted_file.clone(),
+                nonce: NonceSource::Generated,
+                stream,
+                verify_after_encrypt: true,
+                force: true,
+                ..Default::default()
+            };
+            let _ = opts
+                .encrypt("foobar".to_string())
+                .expect("Failed to encrypt data");
+        }
+
+        // A write which did not reach the disk intact fails verification.
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Provided("verify".to_string()),
+            force: true,
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        let nonce = opts.nonce().expect("Failed to get nonce");
+        let mut contents = fs::read(&encrypted_file).expect("Failed to read encrypted file");
+        *contents.last_mut().unwrap() ^= 1;
+        fs::write(&encrypted_file, &contents).expect("Failed to corrupt encrypted file");
+        assert!(opts.verify_written(&nonce, b"foobar").is_err());
+
+        let (ciphertext, _, header) = opts.seal(b"something else").expect("Failed to seal");
+        fs::write(&encrypted_file, frame(&ciphertext, &header)).expect("Failed to write");
+        let out = opts.verify_written(&nonce, b"foobar").unwrap_err();
+        assert_eq!(
+            format!("{out:?}"),
+            format!("{:?}", SimpleCipherError::VerifyMismatch)
+        );
+    }
+
+    #[test]
+    fn grouped_nonce_round_trip() {
+        let opts = CommonEncryptionOpts {
+            nonce: NonceSource::Generated,
+            detached_nonce: true,
+            nonce_generator: NonceGenerator::Random,
+            nonce_display_width: NonZeroUsize::new(4),
+            ..Default::default()
+        };
+        let nonce = opts.nonce().expect("Failed to generate nonce");
+        let printed = opts
+            .generated_nonce(&nonce)
+            .unwrap()
+            .expect("Nonce was generated");
+        assert_eq!(printed.len(), 2 * NONCE_LENGTH + 2 * NONCE_LENGTH / 4 - 1);
+        assert!(printed.split(' ').all(|group| group.len() == 4));
+        let parsed = CommonEncryptionOpts::nonce_from_string(printed).expect("Failed to parse");
+        assert_eq!(parsed, nonce);
+
+        // Spaces in an ascii nonce are still part of it.
+        let parsed = CommonEncryptionOpts::nonce_from_string("a b".to_string()).unwrap();
+        assert_eq!(&parsed[..3], b"a b");
+    }
+
+    #[test]
+    fn grouped_nonce_is_canonical() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        for nonce_encoding in [
+            NonceEncoding::Hex,
+            NonceEncoding::Base64,
+            NonceEncoding::Base64Url,
+        ] {
+            let opts = CommonEncryptionOpts {
+                key: Some("baz".to_string().into()),
+                encrypted_file: tmpdir.path().join("encyrpted.dat"),
+                nonce: NonceSource::Generated,
+                detached_nonce: true,
+                nonce_generator: NonceGenerator::Random,
+                nonce_encoding,
+                nonce_display_width: NonZeroUsize::new(4),
+                force: true,
+                ..Default::default()
+            };
+            let printed = opts
+                .encrypt("foobar".to_string())
+                .expect("Failed to encrypt data")
+                .expect("Nonce was generated");
+            assert!(printed.contains(' '), "{printed}");
+            let opts = CommonEncryptionOpts {
+                nonce: NonceSource::Provided(printed),
+                canonical_nonce: true,
+                ..opts
+            };
+            assert_eq!(opts.decrypt().expect("Failed to decrypt data"), b"foobar");
+        }
+    }
+
+    #[test]
+    fn stringified_nonce_round_trip() {
+        // Together the nonces hold every byte value, including those above 127, and some are
+        // entirely printable ascii.
+        let bytes: Vec<u8> = (0..=255).collect();
+        for chunk in bytes.chunks(NONCE_LENGTH) {
+            let mut nonce = XNonce::default();
+            nonce[..chunk.len()].copy_from_slice(chunk);
+            let printed = CommonEncryptionOpts::stringify_nonce(&nonce);
+            assert!(printed.is_ascii());
+            let parsed = CommonEncryptionOpts::nonce_from_string(printed).expect("Failed to parse");
+            assert_eq!(parsed, nonce);
+        }
+
+        let error = CommonEncryptionOpts::nonce_from_string("nonce \u{2603}".to_string());
+        assert!(matches!(
+            error,
+            Err(SimpleCipherError::NonceCharacter('\u{2603}'))
+        ));
+    }
+
+    #[test]
+    fn wrapped_errors_are_the_source() {
+        use std::error::Error;
+
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("missing.dat"),
+            nonce: NonceSource::Zero,
+            ..Default::default()
+        };
+        let out = opts.decrypt().unwrap_err();
+        let source = out
+            .source()
+            .and_then(|source| source.downcast_ref::<std::io::Error>())
+            .expect("IO errors have the io::Error as their source");
+        assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+        assert_eq!(out.to_string(), "I/O error");
+
+        assert!(SimpleCipherError::KeyMissing.source().is_none());
+    }
+
+    #[test]
+    fn strict_nonce_requires_24_bytes() {
+        for nonce_encoding in [NonceEncoding::Text, NonceEncoding::Hex] {
+            let mut opts = CommonEncryptionOpts {
+                nonce_encoding,
+                ..Default::default()
+            };
+            let nonce = |length: usize| match nonce_encoding {
+                NonceEncoding::Text => "n".repeat(length),
+                _ => "ab".repeat(length),
+            };
+            assert!(opts.parse_nonce(&nonce(23)).is_ok());
+            opts.strict_nonce = true;
+            assert!(matches!(
+                opts.parse_nonce(&nonce(23)),
+                Err(SimpleCipherError::NonceTooShort(23))
+            ));
+            assert!(opts.parse_nonce(&nonce(24)).is_ok());
+            assert!(matches!(
+                opts.parse_nonce(&nonce(25)),
+                Err(SimpleCipherError::NonceTooLong(25))
+            ));
+        }
+    }
+
+    #[test]
+    fn canonical_nonce_encodings() {
+        let mut opts = CommonEncryptionOpts {
+            nonce_encoding: NonceEncoding::Base64,
+            ..Default::default()
+        };
+        // Both decode to the bytes 0x00 0x01 as the low bits of the last character are unused.
+        for nonce in ["AAE=", "AAF=", "AAE"] {
+            let parsed = opts.parse_nonce(nonce).expect("Failed to parse nonce");
+            assert_eq!(&parsed[..3], &[0, 1, 0]);
+        }
+        opts.canonical_nonce = true;
+        assert!(opts.parse_nonce("AAE=").is_ok());
+        for nonce in ["AAF=", "AAE"] {
+            assert_eq!(
+                format!("{:?}", opts.parse_nonce(nonce).unwrap_err()),
+                format!("{:?}", SimpleCipherError::NonCanonicalNonce)
+            );
+        }
+
+        opts.nonce_encoding = NonceEncoding::Hex;
+        assert!(opts.parse_nonce("0a0b").is_ok());
+        assert!(opts.parse_nonce("0A0B").is_err());
+        opts.canonical_nonce = false;
+        assert_eq!(
+            opts.parse_nonce("0A0B").unwrap(),
+            opts.parse_nonce("0a 0b").unwrap()
+        );
+    }
+
+    #[test]
+    fn encrypt_and_decrypt_with_base64_nonce() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Generated,
+            detached_nonce: true,
+            nonce_generator: NonceGenerator::Random,
+            nonce_encoding: NonceEncoding::Base64,
+            ..Default::default()
+        };
+        let nonce = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data")
+            .expect("Nonce was generated");
+        assert_eq!(BASE64.decode(&nonce).unwrap().len(), NONCE_LENGTH);
+        let opts = CommonEncryptionOpts {
+            nonce: NonceSource::Provided(nonce),
+            canonical_nonce: true,
+            ..opts
+        };
+        assert_eq!(opts.decrypt().expect("Failed to decrypt data"), b"foobar");
+    }
+
+    #[test]
+    fn encrypt_and_decrypt_with_base64_url_nonce() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Generated,
+            detached_nonce: true,
+            nonce_generator: NonceGenerator::Random,
+            nonce_encoding: NonceEncoding::Base64Url,
+            format: Some(CiphertextFormat::Base64Url),
+            ..Default::default()
+        };
+        let nonce = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data")
+            .expect("Nonce was generated");
+        assert!(!nonce.contains(['+', '/', '=']), "{nonce}");
+        assert_eq!(URL_SAFE_NO_PAD.decode(&nonce).unwrap().len(), NONCE_LENGTH);
+
+        let url_safe = CommonEncryptionOpts {
+            nonce: NonceSource::Provided(nonce.clone()),
+            canonical_nonce: true,
+            format: None,
+            ..opts
+        };
+        assert_eq!(
+            url_safe.decrypt().expect("Failed to decrypt data"),
+            b"foobar"
+        );
+
+        // The standard encoding reads a URL-safe nonce too, though it is not its canonical form.
+        let standard = CommonEncryptionOpts {
+            nonce_encoding: NonceEncoding::Base64,
+            canonical_nonce: false,
+            ..url_safe
+        };
+        assert_eq!(
+            standard.decrypt().expect("Failed to decrypt data"),
+            b"foobar"
+        );
+        assert_eq!(
+            standard
+                .parse_nonce(&BASE64.encode(standard.nonce().unwrap()))
+                .unwrap(),
+            standard.nonce().unwrap()
+        );
+    }
+
+    #[test]
+    fn fingerprint_follows_the_derived_key() {
+        let fingerprint = |key: &str, kdf: &KdfParams| {
+            let opts = CommonEncryptionOpts {
+                key: Some(key.to_string().into()),
+                ..Default::default()
+            };
+            key_fingerprint(&opts.get_key_from_string(kdf).unwrap())
+        };
+        let none = KdfParams::None;
+        assert_eq!(fingerprint("baz", &none), fingerprint("baz", &none));
+        assert_ne!(fingerprint("baz", &none), fingerprint("qux", &none));
+        // This is the first 8 bytes of the SHA-256 of "baz" padded with 29 zero bytes.
+        assert_eq!(fingerprint("baz", &none), "fe25f03914c40280");
+        assert_eq!(key_fingerprint(&Key::default()), "66687aadf862bd77");
+
+        // The same key stretched with a different salt is a different key.
+        let salted = |salt| KdfParams::Pbkdf2 {
+            salt: [salt; kdf::SALT_LENGTH],
+            iterations: NonZeroU32::new(1).unwrap(),
+        };
+        assert_ne!(
+            fingerprint("baz", &salted(1)),
+            fingerprint("baz", &salted(2))
+        );
+    }
+
+    #[test]
+    fn mismatching_key_fingerprint_aborts_decryption() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Provided("fingerprint nonce".to_string()),
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        let contents = fs::read(&opts.encrypted_file).expect("Failed to read encrypted file");
+        let (header, _) = Header::parse(&contents).expect("Failed to parse header");
+        let key_id = hex::encode(header.unwrap().key_id.expect("Key id is recorded"));
+
+        let expecting = |fingerprint: &str| CommonEncryptionOpts {
+            key: opts.key.clone(),
+            encrypted_file: opts.encrypted_file.clone(),
+            nonce: opts.nonce.clone(),
+            expect_key_fingerprint: Some(fingerprint.to_string()),
+            prompt_confirm_key_fingerprint: true,
+            ..Default::default()
+        };
+        assert_eq!(expecting(&key_id).decrypt().unwrap(), b"foobar");
+        // The right key with the wrong expectation fails on the fingerprint, not the AEAD.
+        let out = expecting("0011223344556677").decrypt();
+        assert_eq!(
+            format!("{:?}", out.unwrap_err()),
+            format!(
+                "{:?}",
+                SimpleCipherError::KeyFingerprintMismatch {
+                    expected: "0011223344556677".to_string(),
+                    actual: key_id,
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn encrypt_bytes_round_trips_in_memory() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("never-written.dat");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Generated,
+            ..Default::default()
+        };
+        let detached = CommonEncryptionOpts {
+            detached_nonce: true,
+            format: Some(CiphertextFormat::Base64),
+            ..opts.clone()
+        };
+        for opts in [opts, detached] {
+            let (contents, nonce) = opts
+                .encrypt_bytes(b"foo\0bar")
+                .expect("Failed to encrypt data");
+            assert_eq!(
+                opts.decrypt_bytes(&contents, &nonce)
+                    .expect("Failed to decrypt data"),
+                b"foo\0bar"
+            );
+        }
+        assert!(!encrypted_file.exists());
+    }
+
+    #[test]
+    fn flipped_ciphertext_byte_fails_to_authenticate() {
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            nonce: NonceSource::Provided("flipped".to_string()),
+            ..Default::default()
+        };
+        let (contents, nonce) = opts
+            .encrypt_bytes(b"foobar")
+            .expect("Failed to encrypt data");
+        let (header, ciphertext) = Header::parse(&contents).unwrap();
+        let mut flipped = ciphertext.to_vec();
+        flipped[0] ^= 1;
+
+        // The checksum reports the damage before the AEAD is reached.
+        let damaged = [&contents[..contents.len() - flipped.len()], &flipped].concat();
+        assert!(matches!(
+            opts.decrypt_bytes(&damaged, &nonce),
+            Err(SimpleCipherError::Corrupted { .. })
+        ));
+        // With a checksum to match only authentication catches it.
+        let tampered = frame(&flipped, &header.unwrap());
+        let error = opts.decrypt_bytes(&tampered, &nonce).unwrap_err();
+        assert!(
+            matches!(error, SimpleCipherError::AuthenticationFailed),
+            "{error:?}"
+        );
+        assert_eq!(
+            error.to_string(),
+            "Decryption failed: wrong key or nonce, or corrupted data"
+        );
+    }
+
+    #[test]
+    fn writer_and_reader_round_trip_through_buffers() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("never-written.dat");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Provided("buffered".to_string()),
+            ..Default::default()
+        };
+        let streamed = CommonEncryptionOpts {
+            stream: true,
+            ..opts.clone()
+        };
+        for opts in [opts, streamed] {
+            let mut buffer = Vec::new();
+            let nonce = opts
+                .encrypt_to_writer(b"foo\0bar", &mut buffer)
+                .expect("Failed to encrypt data");
+            assert_eq!(nonce, None);
+            let mut cursor = io::Cursor::new(buffer);
+            assert_eq!(
+                opts.decrypt_from_reader(&mut cursor)
+                    .expect("Failed to decrypt data"),
+                b"foo\0bar"
+            );
+        }
+
+        // A generated nonce is embedded, so decryption from the reader needs no nonce.
+        let generated = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: encrypted_file.clone(),
+            nonce: NonceSource::Generated,
+            ..Default::default()
+        };
+        let mut buffer = Vec::new();
+        generated
+            .encrypt_to_writer(b"foobar", &mut buffer)
+            .expect("Failed to encrypt data");
+        let decrypting = CommonEncryptionOpts {
+            nonce: NonceSource::Undetermined,
+            ..generated
+        };
+        assert_eq!(
+            decrypting
+                .decrypt_from_reader(&mut buffer.as_slice())
+                .expect("Failed to decrypt data"),
+            b"foobar"
+        );
+        assert!(!encrypted_file.exists());
+    }
+
+    #[test]
+    fn encrypted_message_has_its_nonce_and_algorithm() {
+        let generated = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            nonce: NonceSource::Generated,
+            algorithm: Some(SimpleCipherAlgorithm::ChaCha20Poly1305),
+            ..Default::default()
+        };
+        let encrypted = generated
+            .encrypt_message(b"foobar")
+            .expect("Failed to encrypt data");
+        assert_eq!(encrypted.algorithm, SimpleCipherAlgorithm::ChaCha20Poly1305);
+        let (header, _) = Header::parse(&encrypted.ciphertext).unwrap();
+        assert_eq!(header.unwrap().nonce, Some(encrypted.nonce.into()));
+        assert_ne!(
+            generated.encrypt_message(b"foobar").unwrap().nonce,
+            encrypted.nonce
+        );
+
+        let explicit = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            nonce: NonceSource::Provided("explicit".to_string()),
+            ..Default::default()
+        };
+        let encrypted = explicit
+            .encrypt_message(b"foobar")
+            .expect("Failed to encrypt data");
+        assert_eq!(
+            encrypted.algorithm,
+            SimpleCipherAlgorithm::XChaCha20Poly1305
+        );
+        assert_eq!(encrypted.nonce, explicit.nonce().unwrap());
+        assert_eq!(
+            explicit
+                .decrypt_bytes(&encrypted.ciphertext, &encrypted.nonce)
+                .expect("Failed to decrypt data"),
+            b"foobar"
+        );
+    }
+
+    #[test]
+    fn armored_file_round_trips_with_its_header() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.asc"),
+            nonce: NonceSource::Generated,
+            armor: true,
+            ..Default::default()
+        };
+        opts.encrypt("foobar".repeat(20))
+            .expect("Failed to encrypt data");
+
+        let armored = fs::read_to_string(&opts.encrypted_file).unwrap();
+        let lines: Vec<&str> = armored.lines().collect();
+        assert_eq!(lines[0], "-----BEGIN SKE MESSAGE-----");
+        assert_eq!(lines[lines.len() - 1], "-----END SKE MESSAGE-----");
+        assert!(lines.iter().all(|line| line.len() <= 64));
+        let decoded = CiphertextFormat::Pem
+            .decode(armored.as_bytes())
+            .expect("Failed to strip the armor");
+        let (header, _) = Header::parse(&decoded).expect("Failed to parse header");
+        assert!(header.expect("The armor holds the header").nonce.is_some());
+
+        let decrypting = CommonEncryptionOpts {
+            nonce: NonceSource::Undetermined,
+            armor: false,
+            ..opts
+        };
+        assert_eq!(
+            decrypting.decrypt().expect("Failed to decrypt data"),
+            "foobar".repeat(20).as_bytes()
+        );
+    }
+
+    #[test]
+    fn decrypt_bytes_fails_to_authenticate() {
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            nonce: NonceSource::Provided("in memory".to_string()),
+            aad: Some("sent over a socket".to_string()),
+            ..Default::default()
+        };
+        let (contents, nonce) = opts
+            .encrypt_bytes(b"foobar")
+            .expect("Failed to encrypt data");
+        assert_eq!(
+            opts.decrypt_bytes(&contents, &nonce)
+                .expect("Failed to decrypt data"),
+            b"foobar"
+        );
+        let mut other_nonce = nonce;
+        other_nonce[0] ^= 1;
+        let other_aad = CommonEncryptionOpts {
+            aad: Some("sent over another socket".to_string()),
+            ..opts.clone()
+        };
+        for (opts, nonce) in [(&opts, &other_nonce), (&other_aad, &nonce)] {
+            assert!(matches!(
+                opts.decrypt_bytes(&contents, nonce),
+                Err(SimpleCipherError::AuthenticationFailed)
+            ));
+        }
+    }
+
+    #[test]
+    fn binary_plaintext_round_trips() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let message = b"\0binary\0\xff\xfe data\0\0 with NUL bytes\n\0";
+        for stream in [false, true] {
+            let opts = CommonEncryptionOpts {
+                key: Some("baz".to_string().into()),
+                encrypted_file: tmpdir.path().join("encyrpted.dat"),
+                nonce: NonceSource::Provided("binary".to_string()),
+                stream,
+                force: true,
+                ..Default::default()
+            };
+            let _ = opts
+                .write_encrypted(message)
+                .expect("Failed to encrypt data");
+            assert_eq!(opts.decrypt().expect("Failed to decrypt data"), message);
+
+            let mut output = Vec::new();
+            write_plaintext(&opts.decrypt().unwrap(), &mut output, None).unwrap();
+            assert_eq!(output, message);
+        }
+    }
+
+    #[test]
+    fn generated_nonce_is_embedded() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypting = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Generated,
+            force: true,
+            ..Default::default()
+        };
+        let decrypting = CommonEncryptionOpts {
+            nonce: NonceSource::Undetermined,
+            ..encrypting.clone()
+        };
+        assert_eq!(decrypting.nonce_source(), &NonceSource::Undetermined);
+        for format in [CiphertextFormat::Raw, CiphertextFormat::Pem] {
+            let opts = CommonEncryptionOpts {
+                format: Some(format),
+                ..encrypting.clone()
+            };
+            let nonce = opts
+                .encrypt("foobar".to_string())
+                .expect("Failed to encrypt data");
+            assert_eq!(nonce, None);
+            assert_eq!(
+                decrypting.decrypt().expect("Failed to decrypt data"),
+                b"foobar"
+            );
+        }
+
+        // A detached nonce has to be given to decrypt.
+        let detached = CommonEncryptionOpts {
+            detached_nonce: true,
+            ..encrypting
+        };
+        let nonce = detached
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        assert!(nonce.is_some());
+        assert!(matches!(
+            decrypting.decrypt(),
+            Err(SimpleCipherError::NonceChoiceUndeteremined)
+        ));
+        let given = CommonEncryptionOpts {
+            nonce: NonceSource::Provided(nonce.unwrap()),
+            ..decrypting
+        };
+        assert_eq!(given.decrypt().expect("Failed to decrypt data"), b"foobar");
     }
 
-    pub fn decrypt(&self) -> Result<String, SimpleCipherError> {
-        if self.generate_nonce {
-            return Err(SimpleCipherError::NonceGenerate);
-        }
-        let key = self.get_key_from_string()?;
-        let nonce = self.nonce()?;
+    #[test]
+    fn aes256gcm_round_trips_without_algorithm() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            algorithm: Some(SimpleCipherAlgorithm::Aes256Gcm),
+            nonce: NonceSource::Generated,
+            ..Default::default()
+        };
+        opts.encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        let decrypting = CommonEncryptionOpts {
+            algorithm: None,
+            nonce: NonceSource::Undetermined,
+            ..opts.clone()
+        };
+        assert_eq!(
+            decrypting.decrypt().expect("Failed to decrypt data"),
+            b"foobar"
+        );
 
-        let cipher = XChaCha20Poly1305::new(&key);
-        let ciphertext = &fs::read(&self.encrypted_file)?;
-        let plaintext = cipher.decrypt(&nonce, ciphertext.as_slice())?;
-        let plaintext = String::from_utf8(plaintext)?;
-        Ok(plaintext)
-    }
+        let contents = fs::read(&opts.encrypted_file).unwrap();
+        let json = header::header_json(&contents).expect("Failed to describe header");
+        assert_eq!(json["algorithm"], "aes256gcm");
+        assert_eq!(json["segments"][0]["nonce_length"], 12);
+
+        // An --algorithm other than the recorded one is refused before the key is derived.
+        let mismatched = CommonEncryptionOpts {
+            algorithm: Some(SimpleCipherAlgorithm::XChaCha20Poly1305),
+            key: None,
+            ..decrypting.clone()
+        };
+        assert!(matches!(
+            mismatched.decrypt(),
+            Err(SimpleCipherError::AlgorithmMismatch {
+                given: SimpleCipherAlgorithm::XChaCha20Poly1305,
+                recorded: SimpleCipherAlgorithm::Aes256Gcm,
+            })
+        ));
 
-    // This function simply takes a string, converts it to bytes, and pads the vec to be 32 bytes long
-    // as this key is 32 bytes long.
-    fn get_key_from_string(&self) -> Result<Key, SimpleCipherError> {
-        let mut key = self.key.clone().into_bytes();
-        if key.len() > MAX_KEY_LENGTH {
-            return Err(SimpleCipherError::KeyTooLong(key.len()));
+        // A header claiming XChaCha20Poly1305 fails to authenticate rather than panicking.
+        let contents = fs::read(&opts.encrypted_file).unwrap();
+        let (header, ciphertext) = Header::parse(&contents).expect("Failed to parse header");
+        let mut relabelled = Header {
+            algorithm: SimpleCipherAlgorithm::XChaCha20Poly1305,
+            ..header.unwrap()
         }
-        let mut padding_bytes = vec![0_u8; MAX_KEY_LENGTH - key.len()];
-        key.append(&mut padding_bytes);
-        Ok(*Key::from_slice(&key))
+        .to_bytes();
+        relabelled.extend_from_slice(ciphertext);
+        fs::write(&opts.encrypted_file, relabelled).unwrap();
+        assert!(matches!(
+            decrypting.decrypt(),
+            Err(SimpleCipherError::AuthenticationFailed)
+        ));
     }
 
-    // This is a helper function to make a nonce a string. This is for converting a generated nonce
-    // into a string for decryption
-    fn stringify_nonce(nonce: &XNonce) -> String {
-        let nonce: String = nonce
+    #[test]
+    fn aes256gcmsiv_survives_a_reused_nonce() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = |name: &str| CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join(name),
+            algorithm: Some(SimpleCipherAlgorithm::Aes256GcmSiv),
+            nonce: NonceSource::Provided("reused".to_string()),
+            ..Default::default()
+        };
+        let (first, second) = (opts("first.dat"), opts("second.dat"));
+        first
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        second
+            .encrypt("bazqux".to_string())
+            .expect("Failed to encrypt data");
+        assert_eq!(first.decrypt().expect("Failed to decrypt data"), b"foobar");
+        assert_eq!(second.decrypt().expect("Failed to decrypt data"), b"bazqux");
+
+        // Unlike a stream cipher, XORing the ciphertexts does not give the XOR of the messages.
+        let ciphertext = |opts: &CommonEncryptionOpts| {
+            let contents = fs::read(&opts.encrypted_file).unwrap();
+            Header::parse(&contents).unwrap().1[..6].to_vec()
+        };
+        let xored: Vec<u8> = ciphertext(&first)
             .iter()
-            .map(|val| *val as char)
-            .collect::<Vec<char>>()
-            .into_iter()
+            .zip(ciphertext(&second))
+            .map(|(a, b)| a ^ b)
             .collect();
-        nonce
+        let messages: Vec<u8> = b"foobar"
+            .iter()
+            .zip(b"bazqux")
+            .map(|(a, b)| a ^ b)
+            .collect();
+        assert_ne!(xored, messages);
     }
 
-    // This is a helper function to turn a string into a nonce. This is used when the user wants to
-    // specify a given nonce via the CLI.
-    fn nonce_from_string(nonce: String) -> Result<XNonce, SimpleCipherError> {
-        if nonce.len() > NONCE_LENGTH {
-            return Err(SimpleCipherError::NonceTooLong(nonce.len()));
+    #[test]
+    fn compressed_files_are_smaller_and_round_trip() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let message = "foobar ".repeat(1000);
+        let encrypted_length = |compress, prealloc| {
+            let encrypting = CommonEncryptionOpts {
+                key: Some("baz".to_string().into()),
+                encrypted_file: tmpdir.path().join("encyrpted.dat"),
+                nonce: NonceSource::Generated,
+                compress,
+                prealloc,
+                ..Default::default()
+            };
+            encrypting
+                .encrypt(message.clone())
+                .expect("Failed to encrypt data");
+            // The header records the compression so decryption needs no option.
+            let decrypting = CommonEncryptionOpts {
+                compress: Compression::None,
+                nonce: NonceSource::Undetermined,
+                ..encrypting
+            };
+            assert_eq!(
+                decrypting.decrypt_string().expect("Failed to decrypt data"),
+                message
+            );
+            let length = fs::metadata(&decrypting.encrypted_file).unwrap().len();
+            fs::remove_file(&decrypting.encrypted_file).unwrap();
+            length
+        };
+        let uncompressed = encrypted_length(Compression::None, false);
+        for compress in [Compression::Zstd, Compression::Gzip] {
+            for prealloc in [false, true] {
+                assert!(
+                    encrypted_length(compress, prealloc) < uncompressed / 4,
+                    "{compress:?}"
+                );
+            }
         }
-        let mut nonce: Vec<u8> = nonce.chars().map(|v| v as u8).collect();
-        let mut padding_bytes = vec![0_u8; NONCE_LENGTH - nonce.len()];
-        nonce.append(&mut padding_bytes);
-        Ok(*XNonce::from_slice(nonce.as_slice()))
-    }
-
-    // This function either:
-    // * generates a nonce
-    // * returns a nonce of all zeros (**NOT RECOMMENDED**)
-    // * converts a nonce-string to an XNonce.
-    fn nonce(&self) -> Result<XNonce, SimpleCipherError> {
-        if !self.no_nonce && self.nonce.is_none() && !self.generate_nonce {
-            return Err(SimpleCipherError::NonceChoiceUndeteremined);
-        }
-        if self.no_nonce {
-            let nonce = vec![0_u8; NONCE_LENGTH];
-            return Ok(*XNonce::from_slice(&nonce));
-        }
-        if self.generate_nonce {
-            let mut rng = rand::thread_rng();
-
-            // There is almost certainly a better way to do this.
-            // The choos_multiple function in rand does not reuse existing values from my short
-            // tests.
-            // https://docs.rs/rand/latest/rand/seq/trait.IteratorRandom.html#method.choose_multiple
-            // Given that the goal of this is to make a nonce easy to enter, copy and paste
-            // usage of a corpus of each lower case letter of the alphabet repeated NONCE_LENGTH
-            // times, there is probably enough entropy.
-            //
-            // The ChaCha23Poly1305 documentation has actual math behind theuir random nonces.
-            // https://docs.rs/aead/latest/src/aead/lib.rs.html#114-148
-            //
-            // In this case, 26*24 input characters with selecting  24 characters and as
-            // `choose_multiple` selects some without repetitions, I think the number of
-            // combinations is 624 choose 24. Which has ~1.25e43 combinations, this *feels* like a
-            // sufficiently large set but the author of this nonce-subset hack is not a
-            // cyrptographer and would require a proper audit.
-            let potential_nonce_chars: String = vec![
-                vec!["a"; NONCE_LENGTH].join(""),
-                vec!["b"; NONCE_LENGTH].join(""),
-                vec!["c"; NONCE_LENGTH].join(""),
-                vec!["d"; NONCE_LENGTH].join(""),
-                vec!["e"; NONCE_LENGTH].join(""),
-                vec!["f"; NONCE_LENGTH].join(""),
-                vec!["g"; NONCE_LENGTH].join(""),
-                vec!["h"; NONCE_LENGTH].join(""),
-                vec!["i"; NONCE_LENGTH].join(""),
-                vec!["j"; NONCE_LENGTH].join(""),
-                vec!["k"; NONCE_LENGTH].join(""),
-                vec!["l"; NONCE_LENGTH].join(""),
-                vec!["m"; NONCE_LENGTH].join(""),
-                vec!["o"; NONCE_LENGTH].join(""),
-                vec!["o"; NONCE_LENGTH].join(""),
-                vec!["p"; NONCE_LENGTH].join(""),
-                vec!["q"; NONCE_LENGTH].join(""),
-                vec!["r"; NONCE_LENGTH].join(""),
-                vec!["s"; NONCE_LENGTH].join(""),
-                vec!["t"; NONCE_LENGTH].join(""),
-                vec!["u"; NONCE_LENGTH].join(""),
-                vec!["v"; NONCE_LENGTH].join(""),
-                vec!["w"; NONCE_LENGTH].join(""),
-                vec!["x"; NONCE_LENGTH].join(""),
-                vec!["y"; NONCE_LENGTH].join(""),
-                vec!["z"; NONCE_LENGTH].join(""),
-            ]
-            .join("");
-            let nonce: String = potential_nonce_chars
-                .chars()
-                .choose_multiple(&mut rng, NONCE_LENGTH)
-                .into_iter()
-                .collect();
-
-            return Ok(*XNonce::from_slice(nonce.as_bytes()));
-        }
-        if let Some(nonce_string) = &self.nonce {
-            return Self::nonce_from_string(nonce_string.to_string());
-        }
-        Err(SimpleCipherError::NonceChoiceUndeteremined)
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
     #[test]
-    fn encrypt_and_decrypt_with_nonce() {
-        let key = "baz".to_string();
-        let input = "foobar".to_string();
-        let nonce = vec!["a"; NONCE_LENGTH].join("");
+    fn chacha20poly1305_round_trips() {
         let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
-        let encrypted_file = tmpdir.path().join("encyrpted.dat");
-        let encrypt_opts = CommonEncryptionOpts {
-            key: key.clone(),
-            generate_nonce: false,
-            encrypted_file: encrypted_file.clone(),
-            no_nonce: false,
-            nonce: Some(nonce.clone()),
+        let encrypting = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            algorithm: Some(SimpleCipherAlgorithm::ChaCha20Poly1305),
+            force: true,
+            ..Default::default()
         };
-        let decrypt_opts = CommonEncryptionOpts {
-            key,
-            encrypted_file,
-            generate_nonce: false,
-            no_nonce: false,
-            nonce: Some(nonce),
+        // The algorithm is read from the header, so it is not needed to decrypt.
+        let decrypting = |nonce: Option<String>| CommonEncryptionOpts {
+            algorithm: None,
+            nonce: nonce.map_or(NonceSource::Undetermined, NonceSource::Provided),
+            ..encrypting.clone()
         };
+        let given = CommonEncryptionOpts {
+            nonce: NonceSource::Provided("twelve bytes".to_string()),
+            ..encrypting.clone()
+        };
+        given
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        let contents = fs::read(&encrypting.encrypted_file).unwrap();
+        let (header, _) = Header::parse(&contents).expect("Failed to parse header");
+        assert_eq!(
+            header.unwrap().algorithm,
+            SimpleCipherAlgorithm::ChaCha20Poly1305
+        );
+        assert_eq!(
+            decrypting(Some("twelve bytes".to_string()))
+                .decrypt()
+                .expect("Failed to decrypt data"),
+            b"foobar"
+        );
+        assert!(matches!(
+            given.parse_nonce("thirteen byte"),
+            Err(SimpleCipherError::NonceTooLong(13))
+        ));
 
-        let _ = encrypt_opts
-            .encrypt(input.clone())
+        let generated = CommonEncryptionOpts {
+            nonce: NonceSource::Generated,
+            detached_nonce: true,
+            ..encrypting.clone()
+        };
+        let nonce = generated
+            .encrypt("foobar".to_string())
             .expect("Failed to encrypt data");
-        let output = decrypt_opts.decrypt().expect("Failed to decrypt data");
-        assert_eq!(input, output);
+        assert_eq!(
+            decrypting(nonce).decrypt().expect("Failed to decrypt data"),
+            b"foobar"
+        );
+        let embedded = CommonEncryptionOpts {
+            nonce: NonceSource::Generated,
+            ..encrypting.clone()
+        };
+        embedded
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        assert_eq!(
+            decrypting(None).decrypt().expect("Failed to decrypt data"),
+            b"foobar"
+        );
+
+        let streamed = CommonEncryptionOpts {
+            stream: true,
+            ..given
+        };
+        assert!(matches!(
+            streamed.encrypt("foobar".to_string()),
+            Err(SimpleCipherError::StreamAlgorithm(_))
+        ));
     }
 
     #[test]
-    fn fail_to_decrypt() {
-        let encrypt_key = "this is the encryption key".to_string();
-        let decrypt_key = "this is not the encryption key".to_string();
-        let input = "foobar".to_string();
-        let nonce = vec!["b"; NONCE_LENGTH].join("");
-
+    fn encrypt_and_decrypt_with_nonce_file() {
         let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
-        let encrypted_file = tmpdir.path().join("encyrpted.dat");
-        let encrypt_opts = CommonEncryptionOpts {
-            key: encrypt_key,
-            encrypted_file: encrypted_file.clone(),
-            generate_nonce: false,
-            no_nonce: false,
-            nonce: Some(nonce.clone()),
+        let nonce_file = tmpdir.path().join("nonce.bin");
+        let encrypting = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::GeneratedToFile(nonce_file.clone()),
+            ..Default::default()
         };
-        let decrypt_opts = CommonEncryptionOpts {
-            key: decrypt_key,
-            generate_nonce: false,
-            encrypted_file,
-            no_nonce: false,
-            nonce: Some(nonce),
+        let printed = encrypting
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        assert_eq!(printed, None);
+        assert_eq!(fs::read(&nonce_file).unwrap().len(), NONCE_LENGTH);
+
+        let decrypting = CommonEncryptionOpts {
+            nonce: NonceSource::File(nonce_file.clone()),
+            ..encrypting
         };
+        assert_eq!(
+            decrypting.decrypt().expect("Failed to decrypt data"),
+            b"foobar"
+        );
 
-        let _ = encrypt_opts.encrypt(input).expect("Failed to encrypt data");
-        let output = decrypt_opts.decrypt();
-        assert!(output.is_err());
+        fs::write(&nonce_file, b"too short").unwrap();
+        assert!(matches!(
+            decrypting.decrypt(),
+            Err(SimpleCipherError::NonceFileLength(9, 24))
+        ));
     }
 
-    // Verify that if our input key string exceeds 32 bytes, it throws an error.
     #[test]
-    fn encryption_key_too_long() {
-        const BAD_KEY_LENGTH: usize = MAX_KEY_LENGTH + 1;
+    fn refuses_to_overwrite_without_force() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let mut opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Provided("overwrite".to_string()),
+            ..Default::default()
+        };
+        assert!(!opts.encrypted_file.exists());
+        let _ = opts
+            .encrypt("first".to_string())
+            .expect("Failed to encrypt data");
 
-        let encrypt_key = vec!["a"; BAD_KEY_LENGTH].join("");
-        let input = "foobar".to_string();
-        let nonce = vec!["c"; NONCE_LENGTH].join("");
+        assert!(matches!(
+            opts.encrypt("second".to_string()),
+            Err(SimpleCipherError::OutputExists(path)) if path == opts.encrypted_file
+        ));
+        assert_eq!(opts.decrypt().expect("Failed to decrypt data"), b"first");
+
+        opts.force = true;
+        let _ = opts
+            .encrypt("second".to_string())
+            .expect("Failed to encrypt data");
+        assert_eq!(opts.decrypt().expect("Failed to decrypt data"), b"second");
+    }
 
+    #[test]
+    fn non_utf8_bytes_round_trip() {
         let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
-        let encrypted_file = tmpdir.path().join("encyrpted.dat");
-        let encrypt_opts = CommonEncryptionOpts {
-            key: encrypt_key,
-            encrypted_file: encrypted_file.clone(),
-            generate_nonce: false,
-            no_nonce: false,
-            nonce: Some(nonce.clone()),
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Provided("non utf8".to_string()),
+            ..Default::default()
         };
+        let message = [0xFF, 0x00, 0xFE];
+        let _ = opts
+            .write_encrypted(&message)
+            .expect("Failed to encrypt data");
+        assert_eq!(opts.decrypt().expect("Failed to decrypt data"), message);
+        assert!(matches!(
+            opts.decrypt_string(),
+            Err(SimpleCipherError::Utf8Conversion(_))
+        ));
+    }
 
-        let encrypt_out = encrypt_opts.encrypt(input.clone());
-        assert!(encrypt_out.is_err());
-        let encrypt_out = encrypt_out.err().unwrap();
+    #[test]
+    fn encrypt_file_reads_raw_bytes() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let input_file = tmpdir.path().join("input.bin");
+        let contents: Vec<u8> = (0..=255).rev().collect();
+        fs::write(&input_file, &contents).unwrap();
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Provided("input file".to_string()),
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt_file(&input_file)
+            .expect("Failed to encrypt file");
         assert_eq!(
-            format!("{encrypt_out:?}"),
-            format!("{:?}", SimpleCipherError::KeyTooLong(BAD_KEY_LENGTH))
+            opts.decrypt().expect("Failed to decrypt data"),
+            fs::read(&input_file).unwrap()
         );
+    }
 
-        let decrypt_key = vec!["a"; BAD_KEY_LENGTH].join("");
-        let encrypt_key = vec!["a"; MAX_KEY_LENGTH].join("");
-        let encrypt_opts = CommonEncryptionOpts {
-            key: encrypt_key,
-            encrypted_file: encrypted_file.clone(),
-            no_nonce: false,
-            nonce: Some(nonce.clone()),
-            generate_nonce: false,
-        };
-        let decrypt_opts = CommonEncryptionOpts {
-            key: decrypt_key,
-            encrypted_file,
-            no_nonce: false,
-            generate_nonce: false,
-            nonce: Some(nonce),
+    #[test]
+    fn large_file_streams_through() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let message_file = tmpdir.path().join("message.bin");
+        let message: Vec<u8> = (0..10_000_000_u32).map(|i| (i * 13 % 256) as u8).collect();
+        fs::write(&message_file, &message).unwrap();
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Provided("large file".to_string()),
+            stream: true,
+            ..Default::default()
         };
+        let _ = opts
+            .encrypt_file(&message_file)
+            .expect("Failed to encrypt file");
+        assert!(opts.is_streamed().unwrap());
+        let expected_chunks = message.len().div_ceil(stream::DEFAULT_CHUNK_SIZE as usize);
+        assert!(
+            fs::metadata(&opts.encrypted_file).unwrap().len()
+                >= (message.len() + expected_chunks * TAG_LENGTH) as u64
+        );
 
-        let _ = encrypt_opts.encrypt(input).expect("Failed to encrypt data");
-        let decrypt_out = decrypt_opts.decrypt();
+        let mut output = Vec::new();
+        opts.decrypt_file_to(&mut output)
+            .expect("Failed to decrypt file");
+        assert_eq!(output, message);
 
-        assert!(decrypt_out.is_err());
-        let decrypt_out = decrypt_out.unwrap_err();
+        // An encrypted file is not encrypted again.
+        let again = CommonEncryptionOpts {
+            encrypted_file: tmpdir.path().join("again.dat"),
+            ..opts.clone()
+        };
+        assert!(matches!(
+            again.encrypt_file(&opts.encrypted_file),
+            Err(SimpleCipherError::AlreadyEncrypted)
+        ));
+    }
 
-        // To quote the docs, this error is intentionally opaque to prevent side channel attacks.
+    #[test]
+    fn parallel_stream_matches_sequential() {
+        let message: Vec<u8> = (0..4_000_000_u32).map(|i| (i * 7 % 251) as u8).collect();
+        let sequential = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            nonce: NonceSource::Provided("parallel".to_string()),
+            stream: true,
+            chunk_size: Some(4096),
+            ..Default::default()
+        };
+        let parallel = CommonEncryptionOpts {
+            parallel: true,
+            ..sequential.clone()
+        };
+        let (expected, _) = sequential
+            .encrypt_bytes(&message)
+            .expect("Failed to encrypt data");
+        let (contents, nonce) = parallel
+            .encrypt_bytes(&message)
+            .expect("Failed to encrypt data");
+        assert_eq!(contents, expected);
         assert_eq!(
-            format!("{decrypt_out:?}"),
-            format!("{:?}", SimpleCipherError::KeyTooLong(BAD_KEY_LENGTH))
+            sequential
+                .decrypt_bytes(&contents, &nonce)
+                .expect("Failed to decrypt data"),
+            message
         );
+
+        let parse = |args: &[&str]| {
+            let args = ["encrypt", "--key", "baz", "--no-nonce"].iter().chain(args);
+            CommonEncryptionOpts::try_parse_from(args).map(|_| ())
+        };
+        assert!(parse(&["--stream", "--parallel"]).is_ok());
+        assert!(parse(&["--parallel"]).is_err());
+        assert!(parse(&["--stream", "--parallel", "--max-concurrency", "2"]).is_err());
     }
 
     #[test]
-    fn encrypt_and_decrypt_zeros_as_a_nonce() {
-        let key = "baz".to_string();
-        let input = "foobar".to_string();
+    fn encrypt_piped_stream() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Provided("piped nonce".to_string()),
+            stream: true,
+            chunk_size: Some(4096),
+            ..Default::default()
+        };
+        // The bytes are not UTF-8 and arrive in writes which do not line up with chunks.
+        let input: Vec<u8> = (0..1_000_003_u32).map(|i| (i * 7 % 251) as u8).collect();
+        let (mut reader, mut writer) = io::pipe().expect("Failed to create pipe");
+        let piped = input.clone();
+        let producer = std::thread::spawn(move || {
+            for piece in piped.chunks(1000) {
+                writer.write_all(piece).unwrap();
+            }
+        });
+        let _ = opts
+            .encrypt_reader(&mut reader)
+            .expect("Failed to encrypt stream");
+        producer.join().expect("Producer panicked");
+
+        let mut output = Vec::new();
+        opts.decrypt_stream(
+            &mut fs::File::open(&opts.encrypted_file).unwrap(),
+            &mut output,
+        )
+        .expect("Failed to decrypt stream");
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn version_one_stream_still_decrypts() {
+        // Written by the last version to use version 1 streams, which did not record the
+        // algorithm or authenticate the preamble.
+        let ciphertext = hex::decode(
+            "534b4553010000001000000000a7b6348a52e25c24a6780a4f85b9114b84c9bb7d735f569080a6b3e5\
+             6c789d9ba464f73668da87aeea227c71b01d1d593991474c04eab47a75",
+        )
+        .unwrap();
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            nonce: NonceSource::Provided("v1 stream nonce".to_string()),
+            stream: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        opts.decrypt_stream(&mut ciphertext.as_slice(), &mut output)
+            .expect("Failed to decrypt stream");
+        assert_eq!(output, b"a stream from version one");
+    }
 
+    #[test]
+    fn busy_file_is_retried() {
+        let busy = || io::Error::from(io::ErrorKind::ResourceBusy);
+        let mut attempts = 0;
+        let out = retry_if_busy(true, || {
+            attempts += 1;
+            match attempts {
+                1 | 2 => Err(busy()),
+                _ => Ok("contents"),
+            }
+        });
+        assert_eq!(out.unwrap(), "contents");
+        assert_eq!(attempts, 3);
+
+        attempts = 0;
+        let out = retry_if_busy(false, || {
+            attempts += 1;
+            Err::<(), _>(busy())
+        });
+        assert_eq!(out.unwrap_err().kind(), io::ErrorKind::ResourceBusy);
+        assert_eq!(attempts, 1);
+
+        // Other errors are not retried.
+        attempts = 0;
+        let out = retry_if_busy(true, || {
+            attempts += 1;
+            Err::<(), _>(io::Error::from(io::ErrorKind::NotFound))
+        });
+        assert_eq!(out.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn key_commitment_is_checked_before_the_aead() {
         let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
-        let encrypted_file = tmpdir.path().join("encyrpted.dat");
-        let encrypt_opts = CommonEncryptionOpts {
-            generate_nonce: false,
-            key: key.clone(),
-            encrypted_file: encrypted_file.clone(),
-            no_nonce: true,
-            nonce: None,
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Provided("committed nonce".to_string()),
+            key_committing: true,
+            ..Default::default()
         };
-        let _ = encrypt_opts
-            .encrypt(input.clone())
+        let _ = opts
+            .encrypt("foobar".to_string())
             .expect("Failed to encrypt data");
-        let decrypt_opts = CommonEncryptionOpts {
-            key,
-            encrypted_file,
-            generate_nonce: false,
-            no_nonce: true,
-            nonce: None,
+        assert_eq!(opts.decrypt().expect("Failed to decrypt data"), b"foobar");
+
+        // Had the ciphertext authenticated under the other key as well, the commitment would
+        // still turn it away.
+        let other = CommonEncryptionOpts {
+            key: Some("qux".to_string().into()),
+            ..opts.clone()
+        };
+        assert_eq!(
+            format!("{:?}", other.decrypt().unwrap_err()),
+            format!("{:?}", SimpleCipherError::KeyCommitmentMismatch)
+        );
+
+        // The header is authenticated, so stripping the commitment fails to decrypt even
+        // without --key-committing.
+        let contents = fs::read(&opts.encrypted_file).unwrap();
+        let (header, ciphertext) = Header::parse(&contents).unwrap();
+        let stripped = Header {
+            key_commitment: None,
+            ..header.unwrap()
         };
+        fs::write(&opts.encrypted_file, frame(ciphertext, &stripped)).unwrap();
+        let uncommitted = CommonEncryptionOpts {
+            key_committing: false,
+            ..opts.clone()
+        };
+        assert!(uncommitted.decrypt().unwrap_err().is_auth_failure());
 
-        let output = decrypt_opts.decrypt().expect("Failed to decrypt data");
-        assert_eq!(input, output);
+        // With --key-committing a file without a commitment is refused.
+        let _ = CommonEncryptionOpts {
+            force: true,
+            ..uncommitted.clone()
+        }
+        .encrypt("foobar".to_string())
+        .expect("Failed to encrypt data");
+        assert_eq!(uncommitted.decrypt().expect("Failed to decrypt"), b"foobar");
+        assert!(matches!(
+            opts.decrypt(),
+            Err(SimpleCipherError::KeyCommitmentMissing)
+        ));
+        let streamed = CommonEncryptionOpts {
+            stream: true,
+            force: true,
+            ..uncommitted
+        };
+        let _ = streamed
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        assert!(matches!(
+            opts.decrypt(),
+            Err(SimpleCipherError::KeyCommitmentMissing)
+        ));
     }
 
     #[test]
-    fn encrypt_and_decrypt_zeros_with_generated_nonce() {
-        let key = "baz".to_string();
-        let input = "foobar".to_string();
+    fn prealloc_matches_allocating_encrypt() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let message = "a large message ".repeat(4096);
+        let mut written = Vec::new();
+        for (prealloc, format) in [
+            (false, CiphertextFormat::Raw),
+            (true, CiphertextFormat::Raw),
+            (false, CiphertextFormat::Hex),
+            (true, CiphertextFormat::Hex),
+        ] {
+            let opts = CommonEncryptionOpts {
+                key: Some("baz".to_string().into()),
+                encrypted_file: tmpdir.path().join("encyrpted.dat"),
+                nonce: NonceSource::Provided("prealloc nonce".to_string()),
+                key_committing: true,
+                format: Some(format),
+                prealloc,
+                force: true,
+                ..Default::default()
+            };
+            let _ = opts
+                .encrypt(message.clone())
+                .expect("Failed to encrypt data");
+            assert_eq!(
+                opts.decrypt().expect("Failed to decrypt data"),
+                message.as_bytes()
+            );
+            written.push(fs::read(&opts.encrypted_file).unwrap());
+        }
+        assert_eq!(written[0], written[1]);
+        assert_eq!(written[2], written[3]);
+    }
 
+    #[test]
+    fn encrypt_and_decrypt_with_tag_positions() {
         let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
-        let encrypted_file = tmpdir.path().join("encyrpted.dat");
-        let encrypt_opts = CommonEncryptionOpts {
-            generate_nonce: true,
-            key: key.clone(),
-            encrypted_file: encrypted_file.clone(),
-            no_nonce: false,
-            nonce: None,
+        let opts_with = |tag_position, prealloc| CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Provided("tag nonce".to_string()),
+            tag_position,
+            prealloc,
+            force: true,
+            ..Default::default()
         };
-        let generated_nonce = encrypt_opts
-            .encrypt(input.clone())
-            .expect("Failed to encrypt data");
+        let mut ciphertexts = Vec::new();
+        for (tag_position, prealloc) in [
+            (TagPosition::Suffix, false),
+            (TagPosition::Prefix, false),
+            (TagPosition::Prefix, true),
+        ] {
+            let opts = opts_with(tag_position, prealloc);
+            let _ = opts
+                .encrypt("foobar".to_string())
+                .expect("Failed to encrypt data");
+            assert_eq!(opts.decrypt().expect("Failed to decrypt data"), b"foobar");
+            let contents = fs::read(&opts.encrypted_file).unwrap();
+            let (_, ciphertext) = Header::parse(&contents).unwrap();
+            ciphertexts.push(ciphertext.to_vec());
+        }
+        let (suffix, prefix) = (&ciphertexts[0], &ciphertexts[1]);
+        let split = suffix.len() - TAG_LENGTH;
+        assert_eq!(prefix[..TAG_LENGTH], suffix[split..]);
+        assert_eq!(prefix[TAG_LENGTH..], suffix[..split]);
+        assert_eq!(ciphertexts[1], ciphertexts[2]);
 
-        let decrypt_opts = CommonEncryptionOpts {
-            key,
-            encrypted_file,
-            generate_nonce: false,
-            no_nonce: false,
-            nonce: generated_nonce,
+        // The last file has its tag first, so decrypting it as a suffix fails.
+        let suffix_opts = opts_with(TagPosition::Suffix, false);
+        assert!(suffix_opts.decrypt().unwrap_err().is_auth_failure());
+    }
+
+    #[test]
+    fn each_nonce_flag_produces_its_nonce() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let nonce_file = tmpdir.path().join("nonce.bin");
+        let nonce_file_arg = nonce_file.to_str().unwrap();
+        let parse = |flags: &[&str], expected: NonceSource| {
+            let args = ["encrypt", "-k", "baz"].iter().chain(flags);
+            let opts = CommonEncryptionOpts::try_parse_from(args).expect("Failed to parse flags");
+            assert_eq!(opts.nonce_source(), &expected, "{flags:?}");
+            opts
         };
+        let zero = parse(&["--no-nonce"], NonceSource::Zero);
+        assert_eq!(zero.nonce().unwrap(), XNonce::default());
+        let generated = parse(&["-g"], NonceSource::Generated);
+        assert_ne!(generated.nonce().unwrap(), generated.nonce().unwrap());
+        let provided = parse(&["-n", "foo"], NonceSource::Provided("foo".to_string()));
+        assert_eq!(&provided.nonce().unwrap()[..4], b"foo\0");
+        let to_file = parse(
+            &["-g", "--nonce-file", nonce_file_arg],
+            NonceSource::GeneratedToFile(nonce_file.clone()),
+        );
+        assert!(!to_file.embeds_nonce());
+        to_file
+            .detached_nonce(&to_file.nonce().unwrap())
+            .expect("Failed to write nonce file");
+        assert_eq!(fs::read(&nonce_file).unwrap().len(), NONCE_LENGTH);
 
-        let output = decrypt_opts.decrypt().expect("Failed to decrypt data");
-        assert_eq!(input, output);
+        // Decryption relaxes the nonce flags, so --nonce-file alone reads the nonce.
+        let relaxed = ["no_nonce", "generate_nonce", "nonce"]
+            .into_iter()
+            .fold(CommonEncryptionOpts::command(), |command, id| {
+                command.mut_arg(id, |arg| arg.required(false))
+            });
+        let from_matches = |args: &[&str]| {
+            let matches = relaxed.clone().try_get_matches_from(args).unwrap();
+            CommonEncryptionOpts::from_arg_matches(&matches).unwrap()
+        };
+        fs::write(&nonce_file, [5; 24]).unwrap();
+        let file = from_matches(&["decrypt", "-k", "baz", "--nonce-file", nonce_file_arg]);
+        assert_eq!(file.nonce_source(), &NonceSource::File(nonce_file.clone()));
+        assert_eq!(file.nonce().unwrap(), XNonce::from([5; 24]));
+        let embedded = from_matches(&["decrypt", "-k", "baz"]);
+        assert_eq!(embedded.nonce_source(), &NonceSource::Undetermined);
+        assert!(matches!(
+            embedded.nonce(),
+            Err(SimpleCipherError::NonceChoiceUndeteremined)
+        ));
     }
 
     #[test]
-    fn nonce_error() {
-        let key = "baz".to_string();
-        let input = "foobar".to_string();
+    fn conflicting_nonce_flags_are_refused() {
+        let conflicts: [&[&str]; 4] = [
+            &["--no-nonce", "-n", "given"],
+            &["--no-nonce", "-g"],
+            &["-g", "-n", "given"],
+            &["-n", "given", "--nonce-file", "nonce.bin"],
+        ];
+        for flags in conflicts {
+            let args = ["encrypt", "-k", "baz"].iter().chain(flags);
+            assert!(
+                CommonEncryptionOpts::try_parse_from(args).is_err(),
+                "{flags:?} were not refused"
+            );
+        }
+    }
 
+    #[test]
+    fn previous_keys_are_tried_up_to_the_limit() {
         let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
-        let encrypted_file = tmpdir.path().join("encyrpted.dat");
-        let encrypt_opts = CommonEncryptionOpts {
-            key,
-            encrypted_file,
-            generate_nonce: false,
-            no_nonce: false,
-            nonce: None,
+        let opts = CommonEncryptionOpts {
+            key: Some("old key".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Provided("rotation nonce".to_string()),
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+
+        let wrong_keys = (0..5).map(|i| Zeroizing::new(format!("wrong key {i}")));
+        let rotated = CommonEncryptionOpts {
+            key: Some("new key".to_string().into()),
+            previous_keys: wrong_keys
+                .clone()
+                .chain([Zeroizing::new("old key".to_string())])
+                .collect(),
+            ..opts
         };
-        let out = encrypt_opts.encrypt(input);
-        assert!(out.is_err());
-        let out = out.unwrap_err();
         assert_eq!(
-            format!("{out:?}"),
-            format!("{:?}", SimpleCipherError::NonceChoiceUndeteremined)
+            rotated.decrypt().expect("Failed to decrypt data"),
+            b"foobar"
         );
+
+        let limited = CommonEncryptionOpts {
+            max_decrypt_attempts: NonZeroUsize::new(3),
+            ..rotated.clone()
+        };
+        assert_eq!(
+            format!("{:?}", limited.decrypt().unwrap_err()),
+            format!("{:?}", SimpleCipherError::TooManyKeyAttempts(3))
+        );
+
+        // Running out of keys before the limit reports the last wrong key.
+        let exhausted = CommonEncryptionOpts {
+            previous_keys: wrong_keys.take(1).collect(),
+            ..limited
+        };
+        assert!(exhausted.decrypt().unwrap_err().is_auth_failure());
     }
 
     #[test]
-    fn nonce_too_long() {
-        let key = "baz".to_string();
-        let input = "foobar".to_string();
-        let nonce = vec!["a"; NONCE_LENGTH + 1].join("");
+    fn assume_yes_answers_the_prompt() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Provided("prompt nonce".to_string()),
+            prompt_confirm_key_fingerprint: true,
+            ..Default::default()
+        };
+        let _ = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data");
+        // Without an answer the prompt would block on a terminal, or fail without one.
+        let opts = CommonEncryptionOpts {
+            assume_yes: true,
+            ..opts
+        };
+        assert_eq!(opts.decrypt().expect("Failed to decrypt data"), b"foobar");
+    }
 
+    #[test]
+    fn authenticated_bytes_are_what_seal_authenticates() {
         let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
-        let encrypted_file = tmpdir.path().join("encyrpted.dat");
-        let encrypt_opts = CommonEncryptionOpts {
-            key,
-            encrypted_file,
-            generate_nonce: false,
-            no_nonce: false,
-            nonce: Some(nonce),
+        let with = |aad: Option<&str>, compress| CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Provided("authenticated".to_string()),
+            aad: aad.map(String::from),
+            compress,
+            force: true,
+            ..Default::default()
         };
-        let out = encrypt_opts.encrypt(input);
-        assert!(out.is_err());
-        let out = out.unwrap_err();
-        assert_eq!(
-            format!("{out:?}"),
-            format!("{:?}", SimpleCipherError::NonceTooLong(NONCE_LENGTH + 1))
-        );
+        let message = "foobar ".repeat(100);
+        let mut seen = Vec::new();
+        for opts in [
+            with(Some("context"), Compression::None),
+            with(Some("other"), Compression::None),
+            with(None, Compression::None),
+            with(Some("context"), Compression::Zstd),
+        ] {
+            let authenticated = opts
+                .authenticated_bytes(message.as_bytes())
+                .expect("Failed to build authenticated bytes");
+            let _ = opts
+                .encrypt(message.clone())
+                .expect("Failed to encrypt data");
+            let sealed = cipher::LAST_SEALED.with(|sealed| sealed.take());
+            assert_eq!(sealed.as_ref(), Some(&authenticated));
+            assert!(authenticated.associated_data.starts_with(header::MAGIC));
+            assert!(!seen.contains(&authenticated.associated_data));
+            seen.push(authenticated.associated_data);
+        }
+    }
+
+    #[test]
+    fn aad_must_match_to_decrypt() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        for stream in [false, true] {
+            let opts = CommonEncryptionOpts {
+                key: Some("baz".to_string().into()),
+                encrypted_file: tmpdir.path().join(format!("encyrpted-{stream}.dat")),
+                nonce: NonceSource::Provided("aad nonce".to_string()),
+                aad: Some("context".to_string()),
+                stream,
+                ..Default::default()
+            };
+            let _ = opts
+                .encrypt("foobar".to_string())
+                .expect("Failed to encrypt data");
+            assert_eq!(opts.decrypt().expect("Failed to decrypt data"), b"foobar");
+            for aad in [Some("other"), None] {
+                let other = CommonEncryptionOpts {
+                    aad: aad.map(String::from),
+                    ..opts.clone()
+                };
+                assert!(other.decrypt().unwrap_err().is_auth_failure(), "{stream}");
+            }
+        }
     }
 }
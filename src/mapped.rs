@@ -0,0 +1,61 @@
+//! Decrypting a memory mapped encrypted file, which saves copying a large file into memory
+//! before decrypting it.
+
+use memmap2::Mmap;
+use std::fs;
+
+use crate::{retry_if_busy, CommonEncryptionOpts, SimpleCipherError};
+
+impl CommonEncryptionOpts {
+    /// Decrypts the encrypted file like [`CommonEncryptionOpts::decrypt`] but reads it through a
+    /// read only memory map instead of copying it into memory first.
+    pub fn decrypt_mapped(&self) -> Result<Vec<u8>, SimpleCipherError> {
+        if self.nonce.is_generated() {
+            return Err(SimpleCipherError::NonceGenerate);
+        }
+        let nonce = self.decryption_nonce()?;
+
+        let file = retry_if_busy(self.retry_on_busy, || fs::File::open(&self.encrypted_file))?;
+        // A mapped file which shrinks while it is read faults the process rather than returning
+        // an error, and writers of a mapped file change the slice under the AEAD. The shared lock
+        // keeps this tool's own writers, which take an exclusive lock, out for as long as the
+        // mapping lives. Other writers are not stopped by the lock, which is advisory.
+        file.lock_shared()?;
+        // SAFETY: the file is locked against writes by this tool and the mapping is dropped
+        // before the lock is released when `file` is closed.
+        let contents = unsafe { Mmap::map(&file)? };
+        let plaintext = self.open_with_any_key(nonce.as_ref(), &contents)?;
+        drop(contents);
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CiphertextFormat, NonceSource};
+
+    #[test]
+    fn mapped_decrypt_matches_read() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let message = "a message that spans many pages ".repeat(1024);
+        for format in [CiphertextFormat::Raw, CiphertextFormat::Base64] {
+            let opts = CommonEncryptionOpts {
+                key: Some("baz".to_string().into()),
+                encrypted_file: tmpdir.path().join("encrypted.dat"),
+                nonce: NonceSource::Provided("mapped nonce".to_string()),
+                format: Some(format),
+                force: true,
+                ..Default::default()
+            };
+            let _ = opts
+                .encrypt(message.clone())
+                .expect("Failed to encrypt data");
+            let mapped = opts
+                .decrypt_mapped()
+                .expect("Failed to decrypt mapped file");
+            assert_eq!(mapped, opts.decrypt().expect("Failed to decrypt data"));
+            assert_eq!(mapped, message.as_bytes());
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Writing the encrypted file across numbered parts of a limited size, for channels which cap
+//! the size of a file, and reading the parts back as one file.
+
+use std::{
+    fs,
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{create_locked, retry_if_busy};
+
+/// The path of part `index` of the encrypted file at `path`, counting from 1: `data.dat.001`,
+/// `data.dat.002` and so on.
+pub(crate) fn part_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{index:03}"));
+    PathBuf::from(name)
+}
+
+// This removes `path`, treating a file which is already gone as removed.
+fn remove_if_present(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// This writes to part after part of at most `part_size` bytes, creating each when the first
+// byte reaches it.
+pub(crate) struct PartWriter {
+    path: PathBuf,
+    part_size: u64,
+    retry_on_busy: bool,
+    index: usize,
+    // The part being written and the number of bytes written to it.
+    part: Option<(fs::File, u64)>,
+}
+
+impl PartWriter {
+    // Whatever was written to `path` before, whole or in parts, is removed first so no stale
+    // part is read back after the new ones.
+    pub(crate) fn create(path: &Path, part_size: u64, retry_on_busy: bool) -> io::Result<Self> {
+        remove_if_present(path)?;
+        for index in 1.. {
+            let part = part_path(path, index);
+            if !part.exists() {
+                break;
+            }
+            remove_if_present(&part)?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            part_size,
+            retry_on_busy,
+            index: 0,
+            part: None,
+        })
+    }
+}
+
+impl Write for PartWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let full = self
+            .part
+            .as_ref()
+            .is_none_or(|(_, written)| *written == self.part_size);
+        if full {
+            self.index += 1;
+            let path = part_path(&self.path, self.index);
+            let file = retry_if_busy(self.retry_on_busy, || create_locked(&path))?;
+            self.part = Some((file, 0));
+        }
+        let (file, written) = self.part.as_mut().expect("a part is open");
+        let room = usize::try_from(self.part_size - *written).unwrap_or(usize::MAX);
+        let length = file.write(&buf[..buf.len().min(room)])?;
+        *written += length as u64;
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.part {
+            Some((file, _)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reads the parts of the encrypted file at `path` back into one, or returns `None` if it was
+/// not written in parts.
+pub(crate) fn read_parts(path: &Path, retry_on_busy: bool) -> io::Result<Option<Vec<u8>>> {
+    if !part_path(path, 1).exists() {
+        return Ok(None);
+    }
+    let mut contents = Vec::new();
+    for index in 1.. {
+        let part = part_path(path, index);
+        if !part.exists() {
+            break;
+        }
+        contents.extend(retry_if_busy(retry_on_busy, || fs::read(&part))?);
+    }
+    Ok(Some(contents))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CommonEncryptionOpts, NonceSource};
+    use std::num::NonZeroU64;
+
+    #[test]
+    fn parts_reassemble_and_decrypt() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let whole = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("whole.dat"),
+            nonce: NonceSource::Provided("split nonce".to_string()),
+            force: true,
+            ..Default::default()
+        };
+        let message = "a message spread over several parts".to_string();
+        let _ = whole
+            .encrypt(message.clone())
+            .expect("Failed to encrypt data");
+        let ciphertext = fs::read(&whole.encrypted_file).unwrap();
+
+        let split = CommonEncryptionOpts {
+            encrypted_file: tmpdir.path().join("split.dat"),
+            split_output_size: NonZeroU64::new(16),
+            ..whole
+        };
+        let _ = split
+            .encrypt(message.clone())
+            .expect("Failed to encrypt data");
+        assert!(!split.encrypted_file.exists());
+        let parts: Vec<Vec<u8>> = (1..)
+            .map(|index| part_path(&split.encrypted_file, index))
+            .take_while(|part| part.exists())
+            .map(|part| fs::read(part).unwrap())
+            .collect();
+        assert_eq!(parts.len(), ciphertext.len().div_ceil(16));
+        assert!(parts.iter().all(|part| part.len() <= 16));
+        assert_eq!(parts.concat(), ciphertext);
+        assert_eq!(
+            split.decrypt().expect("Failed to decrypt data"),
+            message.as_bytes()
+        );
+
+        // Encrypting again in fewer parts leaves none of the earlier ones behind.
+        let fewer = CommonEncryptionOpts {
+            split_output_size: NonZeroU64::new(1024),
+            ..split
+        };
+        let _ = fewer
+            .encrypt(message.clone())
+            .expect("Failed to encrypt data");
+        assert!(!part_path(&fewer.encrypted_file, 2).exists());
+        assert_eq!(
+            fewer.decrypt().expect("Failed to decrypt data"),
+            message.as_bytes()
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Rendering generated nonces and keys as QR codes, which are easier to move to a phone than a
+//! string to transcribe.
+
+use image::{GrayImage, ImageFormat, Luma};
+use qrcode::{Color, QrCode};
+use std::{
+    io::{Cursor, Write},
+    path::Path,
+};
+
+use crate::{atomic::AtomicFile, SimpleCipherError};
+
+// Each module of the code is drawn as a square of this many pixels, inside the light border of
+// four modules scanners expect.
+const MODULE_PIXELS: u32 = 8;
+const QUIET_ZONE: u32 = 4;
+
+/// Renders `text` as a black on white QR code.
+pub fn qr_image(text: &str) -> Result<GrayImage, SimpleCipherError> {
+    let code = QrCode::new(text.as_bytes())?;
+    let width = code.width() as u32;
+    let colors = code.to_colors();
+    let size = (width + 2 * QUIET_ZONE) * MODULE_PIXELS;
+    Ok(GrayImage::from_fn(size, size, |x, y| {
+        let (x, y) = (x / MODULE_PIXELS, y / MODULE_PIXELS);
+        let inside = QUIET_ZONE..QUIET_ZONE + width;
+        let dark = inside.contains(&x)
+            && inside.contains(&y)
+            && colors[((y - QUIET_ZONE) * width + x - QUIET_ZONE) as usize] == Color::Dark;
+        Luma([if dark { 0 } else { 255 }])
+    }))
+}
+
+/// Writes `text` as a QR code PNG to `path`. The code may be of a key, so a new file is only
+/// readable by its owner.
+pub fn write_qr(path: &Path, text: &str) -> Result<(), SimpleCipherError> {
+    let mut png = Cursor::new(Vec::new());
+    qr_image(text)?.write_to(&mut png, ImageFormat::Png)?;
+    let mut file = AtomicFile::create(path, false)?;
+    file.write_all(png.get_ref())?;
+    file.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CommonEncryptionOpts, NonceSource};
+
+    // This reads the text back out of the QR code PNG at `path`.
+    fn decode_qr(path: &Path) -> String {
+        let image = image::open(path)
+            .expect("Failed to read QR code")
+            .to_luma8();
+        let mut prepared = rqrr::PreparedImage::prepare_from_greyscale(
+            image.width() as usize,
+            image.height() as usize,
+            |x, y| image.get_pixel(x as u32, y as u32)[0],
+        );
+        let grids = prepared.detect_grids();
+        assert_eq!(grids.len(), 1);
+        let (_, decoded) = grids[0].decode().expect("Failed to decode QR code");
+        decoded
+    }
+
+    #[test]
+    fn qr_decodes_to_the_nonce() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file: tmpdir.path().join("encyrpted.dat"),
+            nonce: NonceSource::Generated,
+            detached_nonce: true,
+            nonce_generator: crate::NonceGenerator::Random,
+            ..Default::default()
+        };
+        let nonce = opts
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data")
+            .expect("Nonce was generated");
+        let qr_file = tmpdir.path().join("nonce.png");
+        write_qr(&qr_file, &nonce).expect("Failed to write QR code");
+        assert_eq!(decode_qr(&qr_file), nonce);
+    }
+
+    #[test]
+    fn qr_decodes_to_the_key() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let key = crate::generate_key(32).expect("Failed to generate key");
+        let encoded = crate::key_qr_text(&key);
+        let qr_file = tmpdir.path().join("key.png");
+        write_qr(&qr_file, &encoded).expect("Failed to write QR code");
+        assert_eq!(decode_qr(&qr_file), *encoded);
+
+        // The decoded text is read back as the key by --key-format base64.
+        let opts = CommonEncryptionOpts {
+            key: Some(decode_qr(&qr_file).into()),
+            key_format: crate::KeyFormat::Base64,
+            strict_key_length: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            opts.get_key_from_string(&crate::KdfParams::None)
+                .unwrap()
+                .as_slice(),
+            key
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&qr_file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Formatting the errors and warnings the binaries print to standard error.
+
+use clap::{Command, FromArgMatches};
+use std::{
+    env,
+    error::Error,
+    ffi::OsString,
+    io::{self, IsTerminal},
+    process::ExitCode,
+};
+
+use crate::{redacted, ConfigDefaults};
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const RESET: &str = "\x1b[0m";
+
+/// How errors and warnings are written to standard error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ErrorStyle {
+    /// Only the category of an error is shown, as with `--redact`.
+    pub redact: bool,
+    /// Labels are colored with ANSI escape sequences.
+    pub color: bool,
+}
+
+impl ErrorStyle {
+    /// Color is only used when standard error is a terminal and neither `no_color` nor a
+    /// non-empty `NO_COLOR` variable turns it off.
+    pub fn new(redact: bool, no_color: bool) -> Self {
+        let no_color_env = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        Self {
+            redact,
+            color: !no_color && !no_color_env && io::stderr().is_terminal(),
+        }
+    }
+
+    /// The style `--redact` and `--no-color` ask for in `args`, for an error reported before
+    /// the command line is parsed. Arguments after `--` are not options.
+    pub fn from_args(args: impl IntoIterator<Item = OsString>) -> Self {
+        let options: Vec<OsString> = args.into_iter().take_while(|arg| arg != "--").collect();
+        Self::new(
+            options.iter().any(|arg| arg == "--redact"),
+            options.iter().any(|arg| arg == "--no-color"),
+        )
+    }
+
+    /// Formats `error` and the chain of errors that caused it. Without color the output is
+    /// exactly the same on every run so it can be matched by scripts and tests.
+    pub fn error(&self, error: &(dyn Error + 'static)) -> String {
+        let label = self.paint("Error:", RED);
+        if self.redact {
+            return format!("{label} {}", redacted(error));
+        }
+        let mut message = format!("{label} {error}");
+        let mut source = error.source();
+        if source.is_some() {
+            message.push_str(&format!("\n\n{}", self.paint("Caused by:", RED)));
+        }
+        while let Some(cause) = source {
+            message.push_str(&format!("\n    {cause}"));
+            source = cause.source();
+        }
+        message
+    }
+
+    /// Formats a warning which does not stop the binary.
+    pub fn warning(&self, warning: &str) -> String {
+        format!("{} {warning}", self.paint("Warning:", YELLOW))
+    }
+
+    fn paint(&self, label: &str, color: &str) -> String {
+        if self.color {
+            format!("{color}{label}{RESET}")
+        } else {
+            label.to_string()
+        }
+    }
+}
+
+/// Prints `error` to standard error in `style` and returns the exit code of a failed run.
+pub fn report_error(error: &(dyn Error + 'static), style: ErrorStyle) -> ExitCode {
+    eprintln!("{}", style.error(error));
+    ExitCode::FAILURE
+}
+
+/// The `main` of every binary. This loads the defaults file, parses the command line built by
+/// `command` with the defaults applied, and runs it, reporting an error in the style `style`
+/// picks from the options. An error loading the defaults file is reported in the style of the
+/// `--redact` and `--no-color` given, as the command line cannot be parsed without it.
+pub fn run_main<T, E>(
+    command: impl FnOnce(&ConfigDefaults) -> Command,
+    style: impl FnOnce(&T) -> ErrorStyle,
+    run: impl FnOnce(T) -> Result<(), E>,
+) -> ExitCode
+where
+    T: FromArgMatches,
+    E: AsRef<dyn Error + 'static>,
+{
+    let defaults = match ConfigDefaults::load() {
+        Ok(defaults) => defaults,
+        Err(e) => return report_error(&e, ErrorStyle::from_args(env::args_os())),
+    };
+    let matches = command(&defaults).get_matches();
+    let opt = T::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let style = style(&opt);
+    match run(opt) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => report_error(e.as_ref(), style),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SimpleCipherError;
+
+    #[test]
+    fn no_color_output_has_no_escapes() {
+        let error = SimpleCipherError::from(hex::FromHexError::OddLength);
+        let plain = ErrorStyle::new(false, true);
+        assert!(!plain.color);
+        let message = plain.error(&error);
+        assert!(!message.contains('\x1b'), "{message:?}");
+        assert_eq!(
+            message,
+            "Error: Invalid hex\n\nCaused by:\n    Odd number of digits"
+        );
+        assert!(!plain.warning("careful").contains('\x1b'));
+
+        let colored = ErrorStyle {
+            color: true,
+            ..plain
+        };
+        assert!(colored.error(&error).contains(RED));
+        assert!(colored.warning("careful").contains(YELLOW));
+    }
+
+    #[test]
+    fn style_is_read_from_unparsed_args() {
+        let style = |args: &[&str]| ErrorStyle::from_args(args.iter().map(OsString::from));
+        let error = SimpleCipherError::from(hex::FromHexError::OddLength);
+        let redacting = style(&["encrypt", "-k", "baz", "--redact", "--no-color"]);
+        assert!(redacting.redact);
+        assert!(!redacting.color);
+        assert_eq!(redacting.error(&error), "Error: Invalid hex");
+        assert!(!style(&["encrypt", "--no-color"]).redact);
+        assert!(!style(&["encrypt", "--no-color", "--", "--redact"]).redact);
+    }
+}
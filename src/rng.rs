@@ -0,0 +1,167 @@
+//! The random number generator nonces and salts are drawn from. Library users can replace the
+//! operating system's generator with their own, for sandboxes where it is unavailable.
+
+use rand::{
+    rngs::{OsRng, StdRng},
+    RngCore, SeedableRng,
+};
+use std::{
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use crate::{CommonEncryptionOpts, SimpleCipherError};
+
+// This is shared rather than owned so the options stay `Clone`.
+#[derive(Clone)]
+pub(crate) struct InjectedRng(Arc<Mutex<dyn RngCore + Send>>);
+
+impl fmt::Debug for InjectedRng {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("InjectedRng")
+    }
+}
+
+impl CommonEncryptionOpts {
+    /// Draws generated nonces and salts from `rng` instead of the operating system's random
+    /// number generator. `rng` must be cryptographically secure for the nonces to be safe.
+    pub fn with_rng<R: RngCore + Send + 'static>(self, rng: R) -> Self {
+        Self {
+            rng: Some(InjectedRng(Arc::new(Mutex::new(rng)))),
+            ..self
+        }
+    }
+
+    /// Draws generated nonces and salts from a generator seeded with `seed`, as `--test-seed`
+    /// does, so the output of a run can be asserted exactly. **This is for tests only:** every
+    /// nonce is the same for the same seed, so it must never be used to encrypt real data.
+    pub fn with_test_seed(self, seed: u64) -> Self {
+        Self {
+            test_seed: Some(seed),
+            ..self
+        }
+    }
+
+    // This is a generator seeded from the injected generator, or the operating system's, which
+    // fails with an error rather than panicking when the seed cannot be drawn. A test seed
+    // seeds every generator the same way.
+    pub(crate) fn seeded_rng(&self) -> Result<StdRng, SimpleCipherError> {
+        if let (None, Some(seed)) = (&self.rng, self.test_seed) {
+            return Ok(StdRng::seed_from_u64(seed));
+        }
+        let mut seed = <StdRng as SeedableRng>::Seed::default();
+        let drawn = match &self.rng {
+            Some(InjectedRng(rng)) => rng
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .try_fill_bytes(&mut seed),
+            None => OsRng.try_fill_bytes(&mut seed),
+        };
+        drawn.map_err(SimpleCipherError::RngUnavailable)?;
+        Ok(StdRng::from_seed(seed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Kdf, NonceGenerator, NonceSource};
+    use rand::rngs::mock::StepRng;
+
+    struct FailingRng;
+
+    impl RngCore for FailingRng {
+        fn next_u32(&mut self) -> u32 {
+            panic!("The failing generator was used infallibly")
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            panic!("The failing generator was used infallibly")
+        }
+
+        fn fill_bytes(&mut self, _: &mut [u8]) {
+            panic!("The failing generator was used infallibly")
+        }
+
+        fn try_fill_bytes(&mut self, _: &mut [u8]) -> Result<(), rand::Error> {
+            Err(rand::Error::new("no entropy in this sandbox"))
+        }
+    }
+
+    #[test]
+    fn failing_rng_is_an_error() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let nonce_options = [
+            (NonceSource::Generated, Kdf::None, NonceGenerator::Random),
+            (NonceSource::Generated, Kdf::None, NonceGenerator::Legacy),
+            (
+                NonceSource::Provided("salted nonce".to_string()),
+                Kdf::Argon2,
+                NonceGenerator::Legacy,
+            ),
+        ];
+        for (nonce, kdf, nonce_generator) in nonce_options {
+            let opts = CommonEncryptionOpts {
+                key: Some("baz".to_string().into()),
+                encrypted_file: tmpdir.path().join("encyrpted.dat"),
+                nonce,
+                nonce_generator,
+                kdf,
+                argon2_memory: Some(64),
+                ..Default::default()
+            }
+            .with_rng(FailingRng);
+            let error = opts.encrypt("foobar".to_string()).unwrap_err();
+            assert!(
+                matches!(error, SimpleCipherError::RngUnavailable(_)),
+                "{error:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn injected_rng_generates_the_nonce() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let nonces: Vec<_> = (0..2)
+            .map(|_| {
+                CommonEncryptionOpts {
+                    key: Some("baz".to_string().into()),
+                    encrypted_file: tmpdir.path().join("encyrpted.dat"),
+                    nonce: NonceSource::Generated,
+                    detached_nonce: true,
+                    nonce_generator: NonceGenerator::Random,
+                    force: true,
+                    ..Default::default()
+                }
+                .with_rng(StepRng::new(0, 1))
+                .encrypt("foobar".to_string())
+                .expect("Failed to encrypt data")
+            })
+            .collect();
+        assert_eq!(nonces[0], nonces[1]);
+    }
+
+    #[test]
+    fn test_seed_reproduces_the_nonce() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let nonce = |nonce_generator, seed| {
+            CommonEncryptionOpts {
+                key: Some("baz".to_string().into()),
+                encrypted_file: tmpdir.path().join("encyrpted.dat"),
+                nonce: NonceSource::Generated,
+                detached_nonce: true,
+                nonce_generator,
+                force: true,
+                ..Default::default()
+            }
+            .with_test_seed(seed)
+            .encrypt("foobar".to_string())
+            .expect("Failed to encrypt data")
+            .expect("Nonce was generated")
+        };
+        for nonce_generator in [NonceGenerator::Legacy, NonceGenerator::Random] {
+            assert_eq!(nonce(nonce_generator, 7), nonce(nonce_generator, 7));
+            assert_ne!(nonce(nonce_generator, 7), nonce(nonce_generator, 8));
+        }
+    }
+}
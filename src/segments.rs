@@ -0,0 +1,261 @@
+//! Append only encrypted logs. A log is a series of one shot encrypted segments, each with its
+//! own header, so a plain encrypted file is a log of a single segment.
+
+use chacha20poly1305::XNonce;
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Read, Write},
+    ops::ControlFlow,
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    frame, unframe_and_decrypt, CommonEncryptionOpts, Header, SimpleCipherAlgorithm,
+    SimpleCipherError,
+};
+
+/// The nonce of the segment at `index`, which is the log's nonce with the index XORed into the
+/// last 8 of its `nonce_length` bytes so every segment is encrypted under a different nonce.
+pub fn segment_nonce(nonce: &XNonce, nonce_length: usize, index: u64) -> XNonce {
+    let mut nonce = *nonce;
+    let last = &mut nonce[nonce_length - 8..nonce_length];
+    for (byte, index_byte) in last.iter_mut().zip(index.to_be_bytes()) {
+        *byte ^= index_byte;
+    }
+    nonce
+}
+
+// This splits `contents` into its complete segments and returns them with the number of bytes
+// they take up. A trailing partial segment is left for later.
+fn complete_segments(contents: &[u8]) -> Result<(Vec<&[u8]>, usize), SimpleCipherError> {
+    let mut segments = Vec::new();
+    let mut offset = 0;
+    while let Some(length) = Header::segment_length(&contents[offset..])? {
+        if contents.len() - offset < length {
+            break;
+        }
+        segments.push(&contents[offset..offset + length]);
+        offset += length;
+    }
+    Ok((segments, offset))
+}
+
+// This is the nonce of the segment at `index` for the algorithm its header records.
+fn nonce_for_segment(
+    segment: &[u8],
+    nonce: Option<&XNonce>,
+    index: u64,
+) -> Result<Option<XNonce>, SimpleCipherError> {
+    let algorithm = Header::parse(segment)?
+        .0
+        .map_or(SimpleCipherAlgorithm::default(), |header| header.algorithm);
+    Ok(nonce.map(|nonce| segment_nonce(nonce, algorithm.nonce_length(), index)))
+}
+
+impl CommonEncryptionOpts {
+    /// Encrypts `message` as a new segment at the end of the encrypted file, creating it if it
+    /// does not exist. Every segment of a log must use the same key and nonce.
+    pub fn append(&self, message: String) -> Result<Option<String>, SimpleCipherError> {
+        self.append_bytes(message.as_bytes())
+    }
+
+    /// Appends `message` like [`CommonEncryptionOpts::append`] for a message which need not be
+    /// text.
+    pub fn append_bytes(&self, message: &[u8]) -> Result<Option<String>, SimpleCipherError> {
+        self.check_not_encrypted(message)?;
+        self.prepare_output_dir()?;
+        let existing = match fs::read(&self.encrypted_file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let (segments, length) = complete_segments(&existing)?;
+        if length != existing.len() {
+            return Err(SimpleCipherError::HeaderMalformed);
+        }
+        // A generated nonce would not match the nonce of the segments already in the log.
+        if self.nonce.is_generated() && !segments.is_empty() {
+            return Err(SimpleCipherError::AppendNonce);
+        }
+
+        let nonce_length = self.algorithm().nonce_length();
+        let (ciphertext, nonce, header) = self.seal_with(message, false, |nonce| {
+            segment_nonce(nonce, nonce_length, segments.len() as u64)
+        })?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.encrypted_file)?;
+        file.lock()?;
+        file.write_all(&frame(&ciphertext, &header))?;
+        self.generated_nonce(&nonce)
+    }
+
+    // This decrypts every segment of a log, which is a single segment for most files.
+    pub(crate) fn open_segments(
+        &self,
+        nonce: Option<&XNonce>,
+        contents: &[u8],
+    ) -> Result<Vec<u8>, SimpleCipherError> {
+        let (segments, length) = complete_segments(contents)?;
+        if length != contents.len() {
+            // The last segment is incomplete, which is reported by decrypting it on its own.
+            let last = &contents[length..];
+            unframe_and_decrypt(
+                last,
+                nonce_for_segment(last, nonce, segments.len() as u64)?.as_ref(),
+                self.algorithm,
+                self.tag_position,
+                self.aad(),
+                self.key_committing,
+                |header| self.recorded_key(header),
+            )?;
+        }
+        let mut plaintext = Vec::new();
+        for (index, segment) in segments.iter().enumerate() {
+            let nonce = nonce_for_segment(segment, nonce, index as u64)?;
+            // The key is confirmed once, for the first segment, as every segment shares it.
+            plaintext.extend(unframe_and_decrypt(
+                segment,
+                nonce.as_ref(),
+                self.algorithm,
+                self.tag_position,
+                self.aad(),
+                self.key_committing,
+                |header| match index {
+                    0 => self.key_for(header),
+                    _ => self.recorded_key(header),
+                },
+            )?);
+        }
+        Ok(plaintext)
+    }
+
+    /// Decrypts the segments of the encrypted file as they are appended, like `tail -f`, passing
+    /// each to `on_segment` once it is complete. The file is checked for more every
+    /// `poll_interval` until `on_segment` breaks.
+    pub fn follow<F>(
+        &self,
+        poll_interval: Duration,
+        mut on_segment: F,
+    ) -> Result<(), SimpleCipherError>
+    where
+        F: FnMut(Vec<u8>) -> ControlFlow<()>,
+    {
+        if self.nonce.is_generated() {
+            return Err(SimpleCipherError::NonceGenerate);
+        }
+        let nonce = self.decryption_nonce()?;
+        let mut file = fs::File::open(&self.encrypted_file)?;
+        let mut pending = Vec::new();
+        let mut index: u64 = 0;
+        loop {
+            file.read_to_end(&mut pending)?;
+            let (segments, length) = complete_segments(&pending)?;
+            for segment in segments {
+                let plaintext = unframe_and_decrypt(
+                    segment,
+                    nonce_for_segment(segment, nonce.as_ref(), index)?.as_ref(),
+                    self.algorithm,
+                    self.tag_position,
+                    self.aad(),
+                    self.key_committing,
+                    |header| match index {
+                        0 => self.key_for(header),
+                        _ => self.recorded_key(header),
+                    },
+                )?;
+                index += 1;
+                if on_segment(plaintext).is_break() {
+                    return Ok(());
+                }
+            }
+            pending.drain(..length);
+            if length == 0 {
+                thread::sleep(poll_interval);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NonceSource;
+
+    fn log_opts(encrypted_file: std::path::PathBuf) -> CommonEncryptionOpts {
+        CommonEncryptionOpts {
+            key: Some("baz".to_string().into()),
+            encrypted_file,
+            nonce: NonceSource::Provided("log nonce".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn segment_nonces_differ() {
+        let nonce = XNonce::from_slice(&[3; 24]);
+        assert_eq!(&segment_nonce(nonce, 24, 0), nonce);
+        assert_ne!(segment_nonce(nonce, 24, 1), segment_nonce(nonce, 24, 2));
+        // A 12 byte nonce has the index XORed into its own last 8 bytes.
+        assert_eq!(segment_nonce(nonce, 12, 1)[11], 2);
+        assert_eq!(segment_nonce(nonce, 12, 1)[12..], nonce[12..]);
+    }
+
+    #[test]
+    fn append_and_decrypt_log() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let opts = log_opts(tmpdir.path().join("log.dat"));
+        for line in ["first\n", "second\n", "third\n"] {
+            let _ = opts
+                .append(line.to_string())
+                .expect("Failed to append segment");
+        }
+        let output = opts.decrypt().expect("Failed to decrypt log");
+        assert_eq!(output, b"first\nsecond\nthird\n");
+    }
+
+    #[test]
+    fn follow_emits_segments_as_they_complete() {
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let encrypted_file = tmpdir.path().join("log.dat");
+        let opts = log_opts(encrypted_file.clone());
+        opts.append("first".to_string())
+            .expect("Failed to append segment");
+
+        // The writer appends the next segments a byte at a time so the follower sees partial
+        // segments, which it has to wait out.
+        let more = tmpdir.path().join("more.dat");
+        let more_opts = log_opts(more.clone());
+        let writer = thread::spawn(move || {
+            for message in ["second", "third"] {
+                let before = fs::read(&encrypted_file).unwrap();
+                fs::copy(&encrypted_file, &more).unwrap();
+                more_opts.append(message.to_string()).unwrap();
+                let segment = fs::read(&more).unwrap()[before.len()..].to_vec();
+                let mut file = OpenOptions::new()
+                    .append(true)
+                    .open(&encrypted_file)
+                    .unwrap();
+                for byte in segment {
+                    file.write_all(&[byte]).unwrap();
+                    thread::sleep(Duration::from_micros(200));
+                }
+            }
+        });
+
+        let mut emitted = Vec::new();
+        opts.follow(Duration::from_millis(1), |segment| {
+            emitted.push(segment);
+            if emitted.len() == 3 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .expect("Failed to follow log");
+        writer.join().expect("Writer panicked");
+        assert_eq!(emitted, [b"first".as_slice(), b"second", b"third"]);
+    }
+}
@@ -0,0 +1,99 @@
+use chacha20poly1305::Key;
+use rand::{
+    rngs::{OsRng, StdRng},
+    SeedableRng,
+};
+use sharks::{Share, Sharks};
+use zeroize::Zeroizing;
+
+use crate::SimpleCipherError;
+
+// An encoded share is the hex of the threshold, the share index and one byte per key byte.
+const SHARE_LENGTH: usize = 1 + 1 + 32;
+
+/// Splits `key` into `shares` Shamir shares, any `threshold` of which reconstruct it. Every share
+/// is hex encoded and records the threshold so `combine_key` can tell when too few are given.
+pub fn split_key(key: &Key, threshold: u8, shares: u8) -> Result<Vec<String>, SimpleCipherError> {
+    if threshold == 0 || threshold > shares {
+        return Err(SimpleCipherError::KeyShareThreshold { threshold, shares });
+    }
+    let mut rng = StdRng::from_rng(OsRng).map_err(SimpleCipherError::RngUnavailable)?;
+    let dealer = Sharks(threshold).dealer_rng(key.as_slice(), &mut rng);
+    Ok(dealer
+        .take(shares.into())
+        .map(|share| {
+            let mut encoded = vec![threshold];
+            encoded.extend(Vec::from(&share));
+            hex::encode(encoded)
+        })
+        .collect())
+}
+
+/// Reconstructs a key from shares produced by `split_key`.
+pub fn combine_key<S: AsRef<str>>(shares: &[S]) -> Result<Zeroizing<Key>, SimpleCipherError> {
+    let mut threshold = None;
+    let mut decoded = Vec::with_capacity(shares.len());
+    for share in shares {
+        let bytes = hex::decode(share.as_ref().trim())?;
+        if bytes.len() != SHARE_LENGTH || *threshold.get_or_insert(bytes[0]) != bytes[0] {
+            return Err(SimpleCipherError::KeyShareInvalid);
+        }
+        let share = Share::try_from(&bytes[1..]).map_err(|_| SimpleCipherError::KeyShareInvalid)?;
+        // The same share given twice does not count towards the threshold.
+        if decoded.iter().all(|other: &Share| other.x != share.x) {
+            decoded.push(share);
+        }
+    }
+    let threshold = threshold.ok_or(SimpleCipherError::KeyShareInvalid)?;
+    if decoded.len() < threshold.into() {
+        return Err(SimpleCipherError::NotEnoughKeyShares {
+            threshold,
+            given: decoded.len(),
+        });
+    }
+    let key = Sharks(threshold)
+        .recover(&decoded)
+        .map(Zeroizing::new)
+        .map_err(|_| SimpleCipherError::KeyShareInvalid)?;
+    Ok(Zeroizing::new(*Key::from_slice(&key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threshold_shares_reconstruct_the_key() {
+        let key = Key::from_slice(&[0x42; 32]);
+        let shares = split_key(key, 3, 5).expect("Failed to split key");
+        assert_eq!(shares.len(), 5);
+
+        let combined = combine_key(&shares[1..4]).expect("Failed to combine shares");
+        assert_eq!(&*combined, key);
+        let combined =
+            combine_key(&[&shares[4], &shares[0], &shares[2]]).expect("Failed to combine shares");
+        assert_eq!(&*combined, key);
+
+        let out = combine_key(&shares[..2]);
+        assert_eq!(
+            format!("{:?}", out.unwrap_err()),
+            format!(
+                "{:?}",
+                SimpleCipherError::NotEnoughKeyShares {
+                    threshold: 3,
+                    given: 2
+                }
+            )
+        );
+
+        // A repeated share does not make up for a missing one.
+        assert!(combine_key(&[&shares[0], &shares[1], &shares[1]]).is_err());
+    }
+
+    #[test]
+    fn invalid_threshold() {
+        let key = Key::from_slice(&[0x42; 32]);
+        assert!(split_key(key, 0, 3).is_err());
+        assert!(split_key(key, 4, 3).is_err());
+    }
+}
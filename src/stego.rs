@@ -0,0 +1,158 @@
+//! Hiding ciphertext in the low bits of PNG images.
+
+use image::{ImageFormat, RgbaImage};
+use std::path::Path;
+
+use crate::{CommonEncryptionOpts, SimpleCipherError};
+
+// The payload is prefixed with its length so extraction knows where it ends.
+const LENGTH_PREFIX: usize = 4;
+// Only the red, green and blue channels carry payload bits. Alpha is left alone as fully
+// transparent pixels are often normalised by image tools.
+const CHANNELS_PER_PIXEL: usize = 3;
+const BYTES_PER_PIXEL: usize = 4;
+
+/// The number of payload bytes that fit in the low bits of `image`.
+pub fn capacity(image: &RgbaImage) -> usize {
+    let bits = image.width() as usize * image.height() as usize * CHANNELS_PER_PIXEL;
+    (bits / 8).saturating_sub(LENGTH_PREFIX)
+}
+
+/// Hides `payload` in the least significant bit of every colour channel of `cover`.
+pub fn embed(cover: &RgbaImage, payload: &[u8]) -> Result<RgbaImage, SimpleCipherError> {
+    let available = capacity(cover);
+    if payload.len() > available {
+        return Err(SimpleCipherError::StegoCapacity {
+            needed: payload.len(),
+            available,
+        });
+    }
+    let length = u32::try_from(payload.len()).expect("payload fits in the image");
+    let bits = length
+        .to_be_bytes()
+        .into_iter()
+        .chain(payload.iter().copied())
+        .flat_map(|byte| (0..8).rev().map(move |bit| (byte >> bit) & 1));
+
+    let mut image = cover.clone();
+    let carriers = image
+        .chunks_exact_mut(BYTES_PER_PIXEL)
+        .flat_map(|pixel| pixel[..CHANNELS_PER_PIXEL].iter_mut());
+    for (carrier, bit) in carriers.zip(bits) {
+        *carrier = (*carrier & !1) | bit;
+    }
+    Ok(image)
+}
+
+/// Recovers a payload hidden by [`embed`].
+pub fn extract(image: &RgbaImage) -> Result<Vec<u8>, SimpleCipherError> {
+    let mut bits = image
+        .chunks_exact(BYTES_PER_PIXEL)
+        .flat_map(|pixel| pixel[..CHANNELS_PER_PIXEL].iter())
+        .map(|carrier| carrier & 1);
+    let mut next_byte =
+        || -> Option<u8> { (0..8).try_fold(0_u8, |byte, _| Some((byte << 1) | bits.next()?)) };
+
+    let mut length = [0_u8; LENGTH_PREFIX];
+    for byte in length.iter_mut() {
+        *byte = next_byte().ok_or(SimpleCipherError::StegoPayload)?;
+    }
+    let length = u32::from_be_bytes(length) as usize;
+    if length > capacity(image) {
+        return Err(SimpleCipherError::StegoPayload);
+    }
+    (0..length)
+        .map(|_| next_byte().ok_or(SimpleCipherError::StegoPayload))
+        .collect()
+}
+
+impl CommonEncryptionOpts {
+    /// Encrypts `message` and hides the ciphertext in the `cover` image, writing the resulting PNG
+    /// to the encrypted file.
+    pub fn stego_encode(
+        &self,
+        message: String,
+        cover: &Path,
+    ) -> Result<Option<String>, SimpleCipherError> {
+        self.check_not_encrypted(message.as_bytes())?;
+        let cover = image::open(cover)?.to_rgba8();
+        self.check_not_overwriting()?;
+        self.prepare_output_dir()?;
+        let (ciphertext, nonce, header) = self.seal(message.as_bytes())?;
+        let image = embed(&cover, &crate::frame(&ciphertext, &header))?;
+        image.save_with_format(&self.encrypted_file, ImageFormat::Png)?;
+        self.generated_nonce(&nonce)
+    }
+
+    /// Extracts the ciphertext hidden in the encrypted file's PNG and decrypts it.
+    pub fn stego_decode(&self) -> Result<Vec<u8>, SimpleCipherError> {
+        if self.nonce.is_generated() {
+            return Err(SimpleCipherError::NonceGenerate);
+        }
+        let nonce = self.decryption_nonce()?;
+
+        let image = image::open(&self.encrypted_file)?.to_rgba8();
+        let ciphertext = extract(&image)?;
+        self.open(nonce.as_ref(), &ciphertext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NonceSource;
+    use image::Rgba;
+
+    fn cover(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([(x * 13) as u8, (y * 7) as u8, (x ^ y) as u8, 255])
+        })
+    }
+
+    #[test]
+    fn embed_and_extract() {
+        let payload = b"a small payload \x00\xff";
+        let image = embed(&cover(16, 16), payload).expect("Failed to embed payload");
+        assert_eq!(extract(&image).expect("Failed to extract payload"), payload);
+    }
+
+    #[test]
+    fn cover_too_small() {
+        let cover = cover(4, 4);
+        let out = embed(&cover, &[0_u8; 64]);
+        assert_eq!(
+            format!("{:?}", out.unwrap_err()),
+            format!(
+                "{:?}",
+                SimpleCipherError::StegoCapacity {
+                    needed: 64,
+                    available: capacity(&cover)
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn stego_encode_and_decode() {
+        let key = "baz".to_string();
+        let input = "foobar".to_string();
+        let nonce = "stego nonce".to_string();
+
+        let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let cover_file = tmpdir.path().join("cover.png");
+        cover(32, 32)
+            .save(&cover_file)
+            .expect("Failed to write cover image");
+        let opts = CommonEncryptionOpts {
+            key: Some(key.into()),
+            encrypted_file: tmpdir.path().join("stego.png"),
+            nonce: NonceSource::Provided(nonce),
+            ..Default::default()
+        };
+        let _ = opts
+            .stego_encode(input.clone(), &cover_file)
+            .expect("Failed to encode message");
+        let output = opts.stego_decode().expect("Failed to decode message");
+        assert_eq!(input.as_bytes(), output);
+    }
+}
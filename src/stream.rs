@@ -0,0 +1,669 @@
+use chacha20poly1305::{
+    aead::{
+        self,
+        stream::{DecryptorBE32, EncryptorBE32, NewStream, Nonce, StreamBE32, StreamPrimitive},
+        KeyInit, Payload,
+    },
+    Key, XChaCha20Poly1305, XNonce,
+};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    num::NonZeroUsize,
+    sync::{mpsc, Mutex},
+    thread,
+};
+
+use crate::{authentication_failed, SimpleCipherAlgorithm, SimpleCipherError, TAG_LENGTH};
+
+/// Every streamed file starts with these bytes so decryption can tell it apart from a single
+/// shot ciphertext.
+pub const STREAM_MAGIC: &[u8; 4] = b"SKES";
+// Version 2 added the algorithm to the preamble and authenticates the whole preamble with every
+// chunk. Version 1 streams are still decrypted.
+const STREAM_VERSION: u8 = 2;
+const LEGACY_STREAM_VERSION: u8 = 1;
+// The magic and version come first in every version so the decryptor can tell which version it
+// was sent before reading anything else.
+const HANDSHAKE_LENGTH: usize = STREAM_MAGIC.len() + 1;
+const PREAMBLE_LENGTH: usize = HANDSHAKE_LENGTH + 1 + 4 + 4;
+const LEGACY_PREAMBLE_LENGTH: usize = HANDSHAKE_LENGTH + 4 + 4;
+
+pub const DEFAULT_CHUNK_SIZE: u32 = 64 * 1024;
+/// The largest chunk size a stream is encrypted or decrypted with. A whole chunk is held in
+/// memory, and the size is read from the preamble before anything authenticates it, so a larger
+/// one is refused rather than allocated.
+pub const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+// The STREAM construction uses 5 bytes of the nonce for its chunk counter and last chunk flag
+// so only the first 19 bytes of the nonce are used as the nonce prefix.
+const NONCE_PREFIX_LENGTH: usize = 19;
+type StreamNonce = Nonce<XChaCha20Poly1305, StreamBE32<XChaCha20Poly1305>>;
+
+/// The parameters of a streamed encryption. These are written to the preamble of the stream so
+/// decryption always follows the schedule used on encryption.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamParams {
+    /// The number of plaintext bytes in every chunk but the last.
+    pub chunk_size: u32,
+    /// The number of chunks encrypted under a key before a fresh sub-key is derived. Zero means
+    /// the key is never rotated.
+    pub rekey_interval: u32,
+}
+
+impl Default for StreamParams {
+    fn default() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            rekey_interval: 0,
+        }
+    }
+}
+
+impl StreamParams {
+    // This refuses a chunk size which is zero or above the maximum.
+    fn check(&self) -> Result<(), SimpleCipherError> {
+        if !(1..=MAX_CHUNK_SIZE).contains(&self.chunk_size) {
+            return Err(SimpleCipherError::StreamChunkSize(self.chunk_size));
+        }
+        Ok(())
+    }
+
+    fn to_preamble(self) -> [u8; PREAMBLE_LENGTH] {
+        let mut preamble = [0_u8; PREAMBLE_LENGTH];
+        preamble[..4].copy_from_slice(STREAM_MAGIC);
+        preamble[4] = STREAM_VERSION;
+        preamble[5] = SimpleCipherAlgorithm::XChaCha20Poly1305.id();
+        preamble[6..10].copy_from_slice(&self.chunk_size.to_be_bytes());
+        preamble[10..].copy_from_slice(&self.rekey_interval.to_be_bytes());
+        preamble
+    }
+
+    // This reads the preamble, checking the handshake before the rest of it, and returns the
+    // parameters with the bytes every chunk authenticates: the whole preamble from version 2
+    // on, and nothing for version 1.
+    fn read_preamble<R: Read>(reader: &mut R) -> Result<(Self, Vec<u8>), SimpleCipherError> {
+        let mut preamble = vec![0_u8; HANDSHAKE_LENGTH];
+        if read_full(reader, &mut preamble)? < HANDSHAKE_LENGTH || &preamble[..4] != STREAM_MAGIC {
+            return Err(SimpleCipherError::StreamPreamble);
+        }
+        let version = preamble[4];
+        let length = match version {
+            LEGACY_STREAM_VERSION => LEGACY_PREAMBLE_LENGTH,
+            STREAM_VERSION => PREAMBLE_LENGTH,
+            0 => return Err(SimpleCipherError::StreamPreamble),
+            _ => return Err(SimpleCipherError::UnsupportedFormatVersion(version)),
+        };
+        preamble.resize(length, 0);
+        if read_full(reader, &mut preamble[HANDSHAKE_LENGTH..])? < length - HANDSHAKE_LENGTH {
+            return Err(SimpleCipherError::StreamPreamble);
+        }
+        let mut fields = &preamble[HANDSHAKE_LENGTH..];
+        if version != LEGACY_STREAM_VERSION {
+            let algorithm = SimpleCipherAlgorithm::from_id(fields[0])?;
+            if algorithm != SimpleCipherAlgorithm::XChaCha20Poly1305 {
+                return Err(SimpleCipherError::StreamAlgorithm(algorithm));
+            }
+            fields = &fields[1..];
+        }
+        let chunk_size = u32::from_be_bytes(fields[..4].try_into().expect("4 bytes"));
+        let rekey_interval = u32::from_be_bytes(fields[4..].try_into().expect("4 bytes"));
+        if chunk_size == 0 {
+            return Err(SimpleCipherError::StreamPreamble);
+        }
+        if version == LEGACY_STREAM_VERSION {
+            preamble.clear();
+        }
+        let params = Self {
+            chunk_size,
+            rekey_interval,
+        };
+        params.check()?;
+        Ok((params, preamble))
+    }
+
+    // This returns the index of the key used for the given chunk.
+    fn epoch(&self, chunk: u64) -> u64 {
+        match self.rekey_interval {
+            0 => 0,
+            interval => chunk / u64::from(interval),
+        }
+    }
+
+    // This returns the STREAM counter of the given chunk, which restarts with every epoch.
+    fn position(&self, chunk: u64) -> Result<u32, SimpleCipherError> {
+        let position = match self.rekey_interval {
+            0 => chunk,
+            interval => chunk % u64::from(interval),
+        };
+        u32::try_from(position).map_err(|_| aead::Error.into())
+    }
+}
+
+// Every rekey epoch gets its own sub-key derived from the key and nonce with HKDF-SHA256. The
+// first epoch of a stream which never rekeys uses the key as is.
+fn epoch_cipher(key: &Key, nonce: &XNonce, params: &StreamParams, epoch: u64) -> XChaCha20Poly1305 {
+    if params.rekey_interval == 0 {
+        return XChaCha20Poly1305::new(key);
+    }
+    let hkdf = Hkdf::<Sha256>::new(Some(nonce.as_slice()), key.as_slice());
+    let mut info = b"symmetric-key-exercise stream rekey".to_vec();
+    info.extend_from_slice(&epoch.to_be_bytes());
+    let mut sub_key = Key::default();
+    hkdf.expand(&info, &mut sub_key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    XChaCha20Poly1305::new(&sub_key)
+}
+
+fn nonce_prefix(nonce: &XNonce) -> &StreamNonce {
+    StreamNonce::from_slice(&nonce[..NONCE_PREFIX_LENGTH])
+}
+
+// This fills `buf` as far as the reader allows and returns how many bytes were read. A short
+// count means the reader is exhausted.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize, SimpleCipherError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+/// Encrypts everything from `reader` to `writer` in chunks of `params.chunk_size` bytes. The
+/// final chunk is always shorter than a full chunk, possibly empty, and flagged as the last so a
+/// truncated stream fails to decrypt. Every chunk also authenticates the preamble followed by
+/// `aad`, so a tampered version or chunk size fails to decrypt as well as different associated
+/// data.
+pub fn encrypt_stream<R: Read, W: Write>(
+    key: &Key,
+    nonce: &XNonce,
+    aad: &[u8],
+    params: &StreamParams,
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<(), SimpleCipherError> {
+    params.check()?;
+    let preamble = params.to_preamble();
+    writer.write_all(&preamble)?;
+    let authenticated = [preamble.as_slice(), aad].concat();
+
+    let chunk_size = params.chunk_size as usize;
+    let mut chunk = vec![0_u8; chunk_size];
+    let mut index: u64 = 0;
+    let mut epoch = params.epoch(index);
+    let mut encryptor =
+        EncryptorBE32::from_aead(epoch_cipher(key, nonce, params, epoch), nonce_prefix(nonce));
+    loop {
+        let read = read_full(reader, &mut chunk)?;
+        if read < chunk_size {
+            let payload = Payload {
+                msg: &chunk[..read],
+                aad: &authenticated,
+            };
+            writer.write_all(&encryptor.encrypt_last(payload)?)?;
+            break;
+        }
+        let payload = Payload {
+            msg: &chunk,
+            aad: &authenticated,
+        };
+        writer.write_all(&encryptor.encrypt_next(payload)?)?;
+        index += 1;
+        if params.epoch(index) != epoch {
+            epoch = params.epoch(index);
+            encryptor = EncryptorBE32::from_aead(
+                epoch_cipher(key, nonce, params, epoch),
+                nonce_prefix(nonce),
+            );
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// A chunk read by the coordinating thread of `encrypt_stream_parallel`: its index, its
+// plaintext and whether it is the last.
+type Job = (u64, Vec<u8>, bool);
+
+// Each worker keeps the cipher of the epoch it last encrypted in so sub-keys are only derived
+// when a worker moves on to a new epoch.
+fn encrypt_jobs(
+    key: &Key,
+    nonce: &XNonce,
+    params: &StreamParams,
+    authenticated: &[u8],
+    jobs: &Mutex<mpsc::Receiver<Job>>,
+    results: mpsc::Sender<(u64, Result<Vec<u8>, SimpleCipherError>)>,
+) {
+    let mut cipher: Option<(u64, StreamBE32<XChaCha20Poly1305>)> = None;
+    loop {
+        let job = jobs
+            .lock()
+            .expect("no worker panics holding the lock")
+            .recv();
+        let Ok((index, plaintext, last)) = job else {
+            return;
+        };
+        let epoch = params.epoch(index);
+        let stream = match &cipher {
+            Some((cached, stream)) if *cached == epoch => stream,
+            _ => {
+                let aead = epoch_cipher(key, nonce, params, epoch);
+                &cipher
+                    .insert((epoch, StreamBE32::from_aead(aead, nonce_prefix(nonce))))
+                    .1
+            }
+        };
+        let payload = Payload {
+            msg: &plaintext,
+            aad: authenticated,
+        };
+        let ciphertext = params
+            .position(index)
+            .and_then(|position| Ok(stream.encrypt(position, last, payload)?));
+        if results.send((index, ciphertext)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Encrypts like [`encrypt_stream`] but with up to `workers` chunks encrypted concurrently.
+/// Chunks are written in order so the output is identical to [`encrypt_stream`]. At most twice
+/// as many chunks as workers are held in memory at once.
+pub fn encrypt_stream_parallel<R: Read, W: Write>(
+    key: &Key,
+    nonce: &XNonce,
+    aad: &[u8],
+    params: &StreamParams,
+    workers: NonZeroUsize,
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<(), SimpleCipherError> {
+    params.check()?;
+    let preamble = params.to_preamble();
+    writer.write_all(&preamble)?;
+    let authenticated = [preamble.as_slice(), aad].concat();
+
+    let chunk_size = params.chunk_size as usize;
+    let max_in_flight = 2 * workers.get();
+    let (job_sender, jobs) = mpsc::channel::<Job>();
+    let jobs = Mutex::new(jobs);
+    let (result_sender, results) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..workers.get() {
+            let (authenticated, jobs, results) = (&authenticated, &jobs, result_sender.clone());
+            scope.spawn(move || encrypt_jobs(key, nonce, params, authenticated, jobs, results));
+        }
+        drop(result_sender);
+
+        // Finished chunks arrive in any order and wait here until every earlier chunk has been
+        // written.
+        let mut finished = BTreeMap::new();
+        let mut next_write: u64 = 0;
+        let mut next_read: u64 = 0;
+        let mut read_all = false;
+        while !read_all || next_write < next_read {
+            if !read_all && next_read - next_write < max_in_flight as u64 {
+                let mut chunk = vec![0_u8; chunk_size];
+                let read = read_full(reader, &mut chunk)?;
+                read_all = read < chunk_size;
+                chunk.truncate(read);
+                job_sender
+                    .send((next_read, chunk, read_all))
+                    .expect("workers run until the job sender is dropped");
+                next_read += 1;
+                continue;
+            }
+            let (index, ciphertext) = results
+                .recv()
+                .expect("workers run until the job sender is dropped");
+            finished.insert(index, ciphertext);
+            while let Some(ciphertext) = finished.remove(&next_write) {
+                writer.write_all(&ciphertext?)?;
+                next_write += 1;
+            }
+        }
+        // Dropping the sender stops the workers, including after an error above.
+        drop(job_sender);
+        writer.flush()?;
+        Ok(())
+    })
+}
+
+/// Decrypts a stream written by [`encrypt_stream`] with the same `aad` from `reader` to
+/// `writer`, following the chunk size and rekey schedule in the stream preamble. A stream from a
+/// newer format version is rejected with [`SimpleCipherError::UnsupportedFormatVersion`] before
+/// anything is written.
+pub fn decrypt_stream<R: Read, W: Write>(
+    key: &Key,
+    nonce: &XNonce,
+    aad: &[u8],
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<(), SimpleCipherError> {
+    let (params, preamble) = StreamParams::read_preamble(reader)?;
+    let authenticated = [preamble.as_slice(), aad].concat();
+
+    let encrypted_chunk_size = params.chunk_size as usize + TAG_LENGTH;
+    let mut chunk = vec![0_u8; encrypted_chunk_size];
+    let mut index: u64 = 0;
+    let mut epoch = params.epoch(index);
+    let mut decryptor = DecryptorBE32::from_aead(
+        epoch_cipher(key, nonce, &params, epoch),
+        nonce_prefix(nonce),
+    );
+    loop {
+        let read = read_full(reader, &mut chunk)?;
+        if read < encrypted_chunk_size {
+            // Every stream ends with a last chunk holding at least the tag, so running out of
+            // data on a chunk boundary means the stream was truncated.
+            if read == 0 {
+                return Err(SimpleCipherError::StreamTruncated);
+            }
+            let payload = Payload {
+                msg: &chunk[..read],
+                aad: &authenticated,
+            };
+            writer.write_all(
+                &decryptor
+                    .decrypt_last(payload)
+                    .map_err(authentication_failed)?,
+            )?;
+            break;
+        }
+        let payload = Payload {
+            msg: &chunk,
+            aad: &authenticated,
+        };
+        writer.write_all(
+            &decryptor
+                .decrypt_next(payload)
+                .map_err(authentication_failed)?,
+        )?;
+        index += 1;
+        if params.epoch(index) != epoch {
+            epoch = params.epoch(index);
+            decryptor = DecryptorBE32::from_aead(
+                epoch_cipher(key, nonce, &params, epoch),
+                nonce_prefix(nonce),
+            );
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHUNK_SIZE: u32 = 1024;
+
+    fn round_trip(params: &StreamParams, plaintext: &[u8]) -> Vec<u8> {
+        let key = Key::from_slice(&[7_u8; 32]);
+        let nonce = XNonce::from_slice(&[9_u8; 24]);
+        let mut ciphertext = Vec::new();
+        encrypt_stream(
+            key,
+            nonce,
+            b"",
+            params,
+            &mut &plaintext[..],
+            &mut ciphertext,
+        )
+        .expect("Failed to encrypt stream");
+        let mut output = Vec::new();
+        decrypt_stream(key, nonce, b"", &mut ciphertext.as_slice(), &mut output)
+            .expect("Failed to decrypt stream");
+        output
+    }
+
+    #[test]
+    fn stream_round_trip() {
+        let params = StreamParams {
+            chunk_size: CHUNK_SIZE,
+            rekey_interval: 0,
+        };
+        // An exact multiple of the chunk size ends with an empty last chunk.
+        for length in [0, 1, CHUNK_SIZE as usize, 3 * CHUNK_SIZE as usize + 7] {
+            let plaintext: Vec<u8> = (0..length).map(|i| i as u8).collect();
+            assert_eq!(round_trip(&params, &plaintext), plaintext);
+        }
+    }
+
+    #[test]
+    fn stream_round_trip_with_rekey() {
+        let params = StreamParams {
+            chunk_size: CHUNK_SIZE,
+            rekey_interval: 2,
+        };
+        let plaintext: Vec<u8> = (0..10 * CHUNK_SIZE as usize).map(|i| i as u8).collect();
+        assert_eq!(round_trip(&params, &plaintext), plaintext);
+
+        // The chunks from the second key onwards differ from a stream which never rekeys.
+        let key = Key::from_slice(&[7_u8; 32]);
+        let nonce = XNonce::from_slice(&[9_u8; 24]);
+        let no_rekey = StreamParams {
+            rekey_interval: 0,
+            ..params
+        };
+        let mut rekeyed = Vec::new();
+        let mut not_rekeyed = Vec::new();
+        encrypt_stream(key, nonce, b"", &params, &mut &plaintext[..], &mut rekeyed)
+            .expect("Failed to encrypt stream");
+        encrypt_stream(
+            key,
+            nonce,
+            b"",
+            &no_rekey,
+            &mut &plaintext[..],
+            &mut not_rekeyed,
+        )
+        .expect("Failed to encrypt stream");
+        let second_epoch = PREAMBLE_LENGTH + 2 * (CHUNK_SIZE as usize + TAG_LENGTH);
+        assert_ne!(rekeyed[second_epoch..], not_rekeyed[second_epoch..]);
+    }
+
+    #[test]
+    fn parallel_stream_matches_sequential() {
+        let key = Key::from_slice(&[7_u8; 32]);
+        let nonce = XNonce::from_slice(&[9_u8; 24]);
+        for rekey_interval in [0, 3] {
+            let params = StreamParams {
+                chunk_size: CHUNK_SIZE,
+                rekey_interval,
+            };
+            for length in [0, CHUNK_SIZE as usize, 20 * CHUNK_SIZE as usize + 7] {
+                let plaintext: Vec<u8> = (0..length).map(|i| i as u8).collect();
+                let mut sequential = Vec::new();
+                encrypt_stream(
+                    key,
+                    nonce,
+                    b"",
+                    &params,
+                    &mut &plaintext[..],
+                    &mut sequential,
+                )
+                .expect("Failed to encrypt stream");
+                for workers in [1, 4] {
+                    let mut parallel = Vec::new();
+                    encrypt_stream_parallel(
+                        key,
+                        nonce,
+                        b"",
+                        &params,
+                        NonZeroUsize::new(workers).unwrap(),
+                        &mut &plaintext[..],
+                        &mut parallel,
+                    )
+                    .expect("Failed to encrypt stream");
+                    assert_eq!(parallel, sequential);
+
+                    let mut output = Vec::new();
+                    decrypt_stream(key, nonce, b"", &mut parallel.as_slice(), &mut output)
+                        .expect("Failed to decrypt stream");
+                    assert_eq!(output, plaintext);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stream_truncated() {
+        let key = Key::from_slice(&[7_u8; 32]);
+        let nonce = XNonce::from_slice(&[9_u8; 24]);
+        let params = StreamParams {
+            chunk_size: CHUNK_SIZE,
+            rekey_interval: 1,
+        };
+        let plaintext = vec![1_u8; 4 * CHUNK_SIZE as usize + 1];
+        let mut ciphertext = Vec::new();
+        encrypt_stream(
+            key,
+            nonce,
+            b"",
+            &params,
+            &mut &plaintext[..],
+            &mut ciphertext,
+        )
+        .expect("Failed to encrypt stream");
+
+        // Drop the last chunk so the stream ends on a chunk boundary.
+        ciphertext.truncate(ciphertext.len() - (1 + TAG_LENGTH));
+        let out = decrypt_stream(key, nonce, b"", &mut ciphertext.as_slice(), &mut Vec::new());
+        assert_eq!(
+            format!("{:?}", out.unwrap_err()),
+            format!("{:?}", SimpleCipherError::StreamTruncated)
+        );
+    }
+
+    #[test]
+    fn newer_format_version_is_rejected_across_a_pipe() {
+        let key = Key::from_slice(&[7_u8; 32]);
+        let nonce = XNonce::from_slice(&[9_u8; 24]);
+        let mut ciphertext = Vec::new();
+        encrypt_stream(
+            key,
+            nonce,
+            b"",
+            &StreamParams::default(),
+            &mut &b"sent by a newer version"[..],
+            &mut ciphertext,
+        )
+        .expect("Failed to encrypt stream");
+        ciphertext[4] = STREAM_VERSION + 1;
+
+        let (mut reader, mut writer) = std::io::pipe().expect("Failed to create pipe");
+        let sender = thread::spawn(move || {
+            // The decryptor stops reading after the handshake, so the rest may not be taken.
+            let _ = writer.write_all(&ciphertext);
+        });
+        let mut output = Vec::new();
+        let out = decrypt_stream(key, nonce, b"", &mut reader, &mut output);
+        drop(reader);
+        sender.join().expect("Sender panicked");
+        assert_eq!(
+            format!("{:?}", out.unwrap_err()),
+            format!(
+                "{:?}",
+                SimpleCipherError::UnsupportedFormatVersion(STREAM_VERSION + 1)
+            )
+        );
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn tampered_preamble_fails_to_decrypt() {
+        let key = Key::from_slice(&[7_u8; 32]);
+        let nonce = XNonce::from_slice(&[9_u8; 24]);
+        let params = StreamParams {
+            chunk_size: CHUNK_SIZE,
+            rekey_interval: 0,
+        };
+        let mut ciphertext = Vec::new();
+        encrypt_stream(
+            key,
+            nonce,
+            b"",
+            &params,
+            &mut &b"short"[..],
+            &mut ciphertext,
+        )
+        .expect("Failed to encrypt stream");
+
+        // A larger chunk size still fits the one chunk, so only the authenticated preamble
+        // catches it.
+        ciphertext[6..10].copy_from_slice(&(2 * CHUNK_SIZE).to_be_bytes());
+        let out = decrypt_stream(key, nonce, b"", &mut ciphertext.as_slice(), &mut Vec::new());
+        assert!(matches!(out, Err(SimpleCipherError::AuthenticationFailed)));
+
+        ciphertext[5] = 0xff;
+        let out = decrypt_stream(key, nonce, b"", &mut ciphertext.as_slice(), &mut Vec::new());
+        assert!(matches!(out, Err(SimpleCipherError::UnknownAlgorithm(_))));
+    }
+
+    #[test]
+    fn oversized_chunk_size_is_refused() {
+        let key = Key::from_slice(&[7_u8; 32]);
+        let nonce = XNonce::from_slice(&[9_u8; 24]);
+        let params = StreamParams {
+            chunk_size: MAX_CHUNK_SIZE,
+            ..StreamParams::default()
+        };
+        let mut ciphertext = Vec::new();
+        encrypt_stream(
+            key,
+            nonce,
+            b"",
+            &params,
+            &mut &b"foobar"[..],
+            &mut ciphertext,
+        )
+        .expect("Failed to encrypt stream");
+        let mut plaintext = Vec::new();
+        decrypt_stream(key, nonce, b"", &mut &ciphertext[..], &mut plaintext)
+            .expect("Failed to decrypt stream");
+        assert_eq!(plaintext, b"foobar");
+
+        // A crafted preamble asking for 4 GiB chunks is refused before anything is allocated.
+        ciphertext[6..10].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(
+            decrypt_stream(key, nonce, b"", &mut &ciphertext[..], &mut Vec::new()),
+            Err(SimpleCipherError::StreamChunkSize(u32::MAX))
+        ));
+        for chunk_size in [0, MAX_CHUNK_SIZE + 1] {
+            let params = StreamParams {
+                chunk_size,
+                ..StreamParams::default()
+            };
+            assert!(matches!(
+                encrypt_stream(key, nonce, b"", &params, &mut &b""[..], &mut Vec::new()),
+                Err(SimpleCipherError::StreamChunkSize(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn stream_bad_preamble() {
+        let key = Key::from_slice(&[7_u8; 32]);
+        let nonce = XNonce::from_slice(&[9_u8; 24]);
+        let out = decrypt_stream(
+            key,
+            nonce,
+            b"",
+            &mut &b"not a stream at all"[..],
+            &mut Vec::new(),
+        );
+        assert_eq!(
+            format!("{:?}", out.unwrap_err()),
+            format!("{:?}", SimpleCipherError::StreamPreamble)
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::{Duration, Instant};
+
+use crate::{CommonEncryptionOpts, NonceSource, SimpleCipherError};
+
+const MESSAGE_LENGTH: usize = 4096;
+
+/// The median time taken to reject a ciphertext for each way decryption can fail
+/// authentication.
+#[derive(Debug, Clone, Copy)]
+pub struct TimingReport {
+    pub wrong_key: Duration,
+    pub wrong_tag: Duration,
+}
+
+impl TimingReport {
+    /// The slower of the two failure modes divided by the faster one.
+    pub fn ratio(&self) -> f64 {
+        let (wrong_key, wrong_tag) = (
+            self.wrong_key.as_secs_f64().max(f64::EPSILON),
+            self.wrong_tag.as_secs_f64().max(f64::EPSILON),
+        );
+        wrong_key.max(wrong_tag) / wrong_key.min(wrong_tag)
+    }
+
+    /// Whether the failure modes are indistinguishable within `tolerance`, e.g. 1.5 allows one
+    /// to be up to 50% slower than the other.
+    pub fn within(&self, tolerance: f64) -> bool {
+        self.ratio() <= tolerance
+    }
+}
+
+fn median(mut samples: Vec<Duration>) -> Duration {
+    samples.sort();
+    samples[samples.len() / 2]
+}
+
+/// Measures how long the decrypt path takes to reject a ciphertext under the wrong key compared
+/// to a ciphertext with a corrupted tag. An observable difference means a check added to the
+/// decrypt path leaks which of the two went wrong.
+pub fn self_check_timing(samples: usize) -> Result<TimingReport, SimpleCipherError> {
+    let nonce = NonceSource::Provided("timing self check".to_string());
+    let right = CommonEncryptionOpts {
+        key: Some("the right key".to_string().into()),
+        nonce: nonce.clone(),
+        ..Default::default()
+    };
+    let wrong = CommonEncryptionOpts {
+        key: Some("the wrong key".to_string().into()),
+        nonce,
+        ..Default::default()
+    };
+    let (ciphertext, nonce, header) = right.seal(&[0x5a; MESSAGE_LENGTH])?;
+    let mut corrupted = ciphertext.clone();
+    *corrupted.last_mut().expect("ciphertext has a tag") ^= 1;
+    // The header checksum is computed over the corrupted tag, as an attacker would, so both
+    // failure modes reach the AEAD.
+    let ciphertext = crate::frame(&ciphertext, &header);
+    let corrupted = crate::frame(&corrupted, &header);
+
+    let mut wrong_key_samples = Vec::with_capacity(samples);
+    let mut wrong_tag_samples = Vec::with_capacity(samples);
+    // The two failure modes are interleaved so drift in machine load affects both equally.
+    for _ in 0..samples.max(1) {
+        let start = Instant::now();
+        let out = wrong.open(Some(&nonce), &ciphertext);
+        wrong_key_samples.push(start.elapsed());
+        debug_assert!(out.is_err());
+
+        let start = Instant::now();
+        let out = right.open(Some(&nonce), &corrupted);
+        wrong_tag_samples.push(start.elapsed());
+        debug_assert!(out.is_err());
+    }
+    Ok(TimingReport {
+        wrong_key: median(wrong_key_samples),
+        wrong_tag: median(wrong_tag_samples),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The tolerance is loose as test machines are noisy. A structural check that skips the AEAD
+    // for one failure mode shows up as a difference of orders of magnitude.
+    #[test]
+    fn wrong_key_and_wrong_tag_take_similar_time() {
+        let report = self_check_timing(301).expect("Failed to run timing check");
+        assert!(report.within(3.0), "{report:?}");
+    }
+}
@@ -0,0 +1,62 @@
+use std::{
+    path::Path,
+    process::{Command, Output},
+};
+
+// This runs `ske` without any configuration file, so tests only see the flags they pass.
+fn ske(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ske"))
+        .args(args)
+        .current_dir(dir)
+        .env("HOME", dir)
+        .env_remove("SKX_CONFIG")
+        .env_remove("XDG_CONFIG_HOME")
+        .output()
+        .expect("Failed to run ske")
+}
+
+#[test]
+fn encrypt_and_decrypt_subcommands_round_trip() {
+    let tmpdir = tempfile::tempdir().expect("Failed to create tempdir");
+    let encrypted = ske(
+        tmpdir.path(),
+        &[
+            "encrypt",
+            "--message",
+            "foobar",
+            "--key",
+            "baz",
+            "--generate-nonce",
+            "--encrypted-file",
+            "encrypted.dat",
+        ],
+    );
+    assert!(encrypted.status.success(), "{encrypted:?}");
+    assert!(tmpdir.path().join("encrypted.dat").exists());
+
+    let decrypted = ske(
+        tmpdir.path(),
+        &[
+            "decrypt",
+            "--key",
+            "baz",
+            "--encrypted-file",
+            "encrypted.dat",
+        ],
+    );
+    assert!(decrypted.status.success(), "{decrypted:?}");
+    assert_eq!(decrypted.stdout, b"foobar");
+
+    let wrong_key = ske(
+        tmpdir.path(),
+        &[
+            "decrypt",
+            "--key",
+            "qux",
+            "--encrypted-file",
+            "encrypted.dat",
+        ],
+    );
+    assert!(!wrong_key.status.success());
+    assert!(wrong_key.stdout.is_empty());
+}